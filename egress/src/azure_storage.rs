@@ -1,12 +1,12 @@
 use crate::config::AppConfig;
-use anyhow::anyhow;
 use async_trait::async_trait;
-use azure_core::{date, Url};
-use azure_storage::{prelude::BlobSasPermissions, ConnectionString};
+use azure_storage::ConnectionString;
 use azure_storage_blobs::prelude::*;
 use futures::{Stream, StreamExt};
-use storage::{StorageContainer, StorageError, StreamType};
-use time::OffsetDateTime;
+use storage::{
+    CachingStorageContainer, InstrumentedStorageContainer, StorageCache, StorageContainer,
+};
+use storage_azure::AzureStorageContainer;
 
 #[async_trait]
 pub trait StorageServer {
@@ -14,50 +14,18 @@ pub trait StorageServer {
         &self,
         account: &str,
         video: &str,
-    ) -> anyhow::Result<Box<dyn StorageContainer>>;
+    ) -> anyhow::Result<Box<dyn StorageContainer + Send + Sync>>;
 }
 
 #[derive(Clone)]
 pub struct AzureStorage {
     config: AppConfig,
+    cache: Option<StorageCache>,
 }
 
 impl AzureStorage {
-    pub fn new(config: AppConfig) -> Self {
-        Self { config }
-    }
-
-    pub async fn check_video(&self, container: &str, file: &str) -> azure_core::Result<()> {
-        let connection_string = ConnectionString::new(&self.config.storage)?;
-        let blob_service = BlobServiceClient::new(
-            connection_string.account_name.unwrap(),
-            connection_string.storage_credentials()?,
-        );
-
-        let container_client = blob_service.container_client(container);
-        container_client.get_properties().into_future().await?;
-        let blob_client = container_client.blob_client(file);
-        blob_client.get_properties().into_future().await?;
-        Ok(())
-    }
-
-    pub async fn get_media_duration(&self, file: &str) -> anyhow::Result<f32> {
-        let connection_string = ConnectionString::new(&self.config.storage)?;
-        let blob_service = BlobServiceClient::new(
-            connection_string.account_name.unwrap(),
-            connection_string.storage_credentials()?,
-        );
-
-        let blob_client = blob_service.container_client(container).blob_client(file);
-
-        let result = blob_client.get_properties().into_future().await?;
-        if let Some(metadata) = result.blob.metadata {
-            if let Some(dur) = metadata.get("duration") {
-                let duration = dur.parse::<f32>()?;
-                return Ok(duration);
-            }
-        }
-        Err(anyhow!("Missing metadata!"))
+    pub fn new(config: AppConfig, cache: Option<StorageCache>) -> Self {
+        Self { config, cache }
     }
 
     pub async fn get_media_file(
@@ -96,78 +64,25 @@ impl StorageServer for AzureStorage {
         &self,
         account: &str,
         video: &str,
-    ) -> anyhow::Result<Box<dyn StorageContainer>> {
+    ) -> anyhow::Result<Box<dyn StorageContainer + Send + Sync>> {
         let connection_string = ConnectionString::new(&self.config.storage)?;
         let blob_service = BlobServiceClient::new(
             connection_string.account_name.unwrap(),
             connection_string.storage_credentials()?,
         );
 
-        Ok(Box::new(AzureStorageContainer::new(container)))
-    }
-}
-
-struct AzureStorageContainer {
-    container: ContainerClient,
-}
-
-impl AzureStorageContainer {
-    pub fn new(container: ContainerClient) -> Self {
-        Self { container }
-    }
-
-    pub fn get_sas_url(
-        &self,
-        container: &str,
-        file: &str,
-        read_only: bool,
-    ) -> azure_core::Result<Url> {
-        let blob_client = self.container.blob_client(file);
-        let expiry = OffsetDateTime::now_utc() + date::duration_from_minutes(1);
-        let permissions = BlobSasPermissions {
-            read: true,
-            write: !read_only,
-            ..Default::default()
-        };
-        let sas = blob_client.shared_access_signature(permissions, expiry)?;
-        let url = blob_client.generate_signed_blob_url(&sas)?;
-        Ok(url)
-    }
-}
-
-#[async_trait]
-impl StorageContainer for AzureStorageContainer {
-    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
-        let blob_client = self.container.blob_client(path);
-        blob_client.get_properties().into_future().await?;
-        let result = blob_client
-            .get()
-            .chunk_size(64000_u64)
-            .into_stream()
-            .map(|r| r.map(|p| p.data))
-            .then(|r| async move {
-                match r {
-                    Ok(d) => d.collect().await,
-                    Err(e) => Err(e),
-                }
-            });
-        Ok(result)
-    }
-
-    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
-        todo!()
-    }
-
-    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
-        todo!()
-    }
-
-    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
-        todo!()
-    }
-
-    async fn exists(&self, path: &str) -> bool {
-        let blob_client = self.container.blob_client(path);
-        blob_client.get_properties().into_future().await.is_ok()
+        let container = InstrumentedStorageContainer::new(
+            AzureStorageContainer::new(blob_service.container_client(account)),
+            account,
+            "azure",
+        );
+        match &self.cache {
+            Some(cache) => Ok(Box::new(CachingStorageContainer::with_namespace(
+                container,
+                cache.clone(),
+                &format!("{account}/{video}"),
+            ))),
+            None => Ok(Box::new(container)),
+        }
     }
 }
@@ -10,6 +10,22 @@ pub struct AppConfig {
     pub use_gpu: bool,
     pub encode_ahead: bool,
     pub cache_fragments: bool,
+    pub cache_memory_size: usize,
+    pub cache_directory: Option<String>,
+    /// Either `azure` or `s3`; S3 is configured through the AWS environment variables.
+    pub storage_type: String,
+    /// Overrides `storage_type` with a storage URL template such as
+    /// `s3://{account}` or `http://proxy:8080/{account}/{video}`.
+    pub storage_url: Option<String>,
+    /// Signs the requests to a storage proxy `storage_url` points to, for
+    /// proxies started with the same `SIGNING_KEY`.
+    pub storage_signing_key: Option<String>,
+    /// A PEM CA certificate to trust for an `https://` storage proxy.
+    pub storage_ca_certificate: Option<String>,
+    /// Seconds to wait for a job to write the segment it was started for.
+    pub job_timeout: u64,
+    /// Seconds the presigned URLs handed to jobs stay valid.
+    pub presign_expiry: u64,
 }
 
 #[derive(Debug, Default, serde_derive::Deserialize, PartialEq, Eq, Clone)]
@@ -30,6 +46,10 @@ impl AppConfig {
             .set_default("use_gpu", false)?
             .set_default("encode_ahead", false)?
             .set_default("cache_fragments", true)?
+            .set_default("cache_memory_size", 256 * 1024 * 1024)?
+            .set_default("storage_type", "azure")?
+            .set_default("job_timeout", 60)?
+            .set_default("presign_expiry", 60)?
             .build()?;
         config.try_deserialize()
     }
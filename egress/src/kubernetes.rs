@@ -1,17 +1,13 @@
-#[cfg(target_os = "unix")]
 use std::os::unix::prelude::{IntoRawFd, RawFd};
 use tokio_pipe::PipeRead;
 
 use bytes::BytesMut;
 use futures::Stream;
 use k8s_openapi::{api::batch::v1::Job, serde_json};
-use kube::{
-    api::PostParams,
-    runtime::wait::{await_condition, conditions},
-    Api, Client,
-};
+use kube::{api::PostParams, Api, Client};
 use log::{info, trace};
-use storage::StorageContainer;
+use storage::{Permissions, StorageContainer, StorageError};
+use tokio::io::AsyncReadExt;
 // use tokio_util::io::ReaderStream;
 use uuid::Uuid;
 
@@ -22,6 +18,44 @@ use crate::{
     config::{AppConfig, JobConfig},
 };
 
+/// How long a server owns the encode of a segment before others may retry it,
+/// unless it renews the lease. The owner renews it while it waits for the job.
+const JOB_LEASE_SECONDS: u64 = 60;
+/// Where the leases on encodes are kept, apart from the segments.
+const LOCK_PREFIX: &str = ".locks/";
+
+/// The URL a job reads the video from.
+async fn input_url(
+    storage: &(dyn StorageContainer + Send + Sync),
+    video: &str,
+    expiry: std::time::Duration,
+) -> Result<storage::Url, StorageError> {
+    storage.presign(video, Permissions::READ, expiry).await
+}
+
+/// The URL template a job uploads the segments of a level to, with `%d` for
+/// the segment number. The job writes every segment of the level, so the
+/// signature is for its prefix.
+async fn segments_url(
+    storage: &(dyn StorageContainer + Send + Sync),
+    video: &str,
+    level: u32,
+    expiry: std::time::Duration,
+) -> Result<String, StorageError> {
+    let stem = std::path::Path::new(video)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or(video);
+    let prefix = format!("{stem}/level{level}/");
+    let output = storage.presign(&prefix, Permissions::WRITE, expiry).await?;
+    let mut base = output.clone();
+    base.set_query(None);
+    Ok(format!(
+        "{base}segment%d.ts?{}",
+        output.query().unwrap_or_default()
+    ))
+}
+
 #[derive(Clone)]
 pub struct KubernetesMediaServer {
     client: Client,
@@ -66,15 +100,15 @@ impl KubernetesMediaServer {
             pipes = Some((p.0, p.1.into_raw_fd()));
         }
 
-        let pipe_name = pipes.as_ref().map(|(_, w)| w.to_string());
-        self.submit_job(container, video, level, segment, pipe_name)
-            .await?;
         if self.config.stream_while_encoding {
+            let pipe_name = pipes.as_ref().map(|(_, w)| w.to_string());
+            self.submit_job(container, video, level, segment, pipe_name)
+                .await?;
             let (reader, writer) = pipes.unwrap();
             let buf = BytesMut::with_capacity(64000);
             let stream =
                 futures::stream::unfold((reader, writer, buf), |(mut r, w, mut b)| async move {
-                    let res = r.read(&mut b).await;
+                    let res = r.read_buf(&mut b).await;
                     match res {
                         Ok(size) => {
                             info!("read {} bytes from pipe", size);
@@ -91,6 +125,8 @@ impl KubernetesMediaServer {
                 });
             Ok(Box::new(stream))
         } else {
+            self.encode_once(container, video, level, segment, &blob_name)
+                .await?;
             let stream = self.storage.get_media_file(container, &blob_name).await?;
             Ok(Box::new(stream))
         }
@@ -117,9 +153,9 @@ impl KubernetesMediaServer {
         segment: u32,
         pipe_name: Option<String>,
     ) -> anyhow::Result<()> {
-        let url = self
-            .storage
-            .get_sas_url(container, video, pipe_name.is_some())?;
+        let storage = self.storage.get_video(container, video).await?;
+        let expiry = std::time::Duration::from_secs(self.config.presign_expiry);
+        let url = input_url(&*storage, video, expiry).await?;
         let job_name = self.get_job_name(video, level, segment);
         let jobs: Api<Job> = Api::default_namespaced(self.client.clone());
         let image_name: &str = if self.config.use_gpu {
@@ -132,14 +168,13 @@ impl KubernetesMediaServer {
         args.push(String::from("-i"));
         args.push(url.to_string());
         args.push("-o".to_string());
-        if pipe_name.is_some() {
+        if let Some(pipe_name) = &pipe_name {
             args.push(format!(
                 "http://{}/pipe/{}",
-                self.config.pod_address,
-                pipe_name.as_ref().unwrap()
+                self.config.pod_address, pipe_name
             ));
         } else {
-            args.push(format!("{url}/level{}/segment%d.ts", level));
+            args.push(segments_url(&*storage, video, level, expiry).await?);
         }
         args.push("-t".to_string());
         args.push((segment * crate::manifest::SEGMENT_DURATION).to_string());
@@ -191,28 +226,106 @@ impl KubernetesMediaServer {
         let result = jobs.create(&PostParams::default(), &data).await;
         trace!("Job result is {:?}", result);
 
-        if let Ok(job) = result {
-            info!(
+        match result {
+            Ok(job) => info!(
                 "Successfully created job: {}",
                 job.metadata.name.as_ref().unwrap()
-            );
-
-            if pipe_name.is_none() {
-                info!("Waiting for job to complete {}", job.metadata.name.unwrap());
-                let cond = await_condition(jobs.clone(), &job_name, conditions::is_job_completed());
-                let _ = tokio::time::timeout(std::time::Duration::from_secs(20), cond).await;
+            ),
+            // someone else is encoding it already.
+            Err(kube::Error::Api(e)) if e.reason == "AlreadyExists" => {
+                info!("Job {} is already running", job_name)
             }
-        } else {
-            info!(
-                "Failed to create job. Probably another job runing {:?}",
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Submits the job for the segment unless another server holds the lease
+    /// on it, then waits for the segment either way. The lease is renewed for
+    /// as long as the job takes.
+    async fn encode_once(
+        &self,
+        container: &str,
+        video: &str,
+        level: u32,
+        segment: u32,
+        blob_name: &str,
+    ) -> anyhow::Result<()> {
+        let storage = self.storage.get_video(container, video).await?;
+        let lock = format!("{LOCK_PREFIX}{}", blob_name.trim_start_matches('/'));
+        let ttl = std::time::Duration::from_secs(JOB_LEASE_SECONDS);
+        match storage.acquire_lease(&lock, ttl).await {
+            Ok(lease) => {
+                let encode = async {
+                    self.submit_job(container, video, level, segment, None)
+                        .await?;
+                    self.wait_for_job(container, video, blob_name).await
+                };
+                tokio::pin!(encode);
+                let start = tokio::time::Instant::now() + ttl / 2;
+                let mut renewals = tokio::time::interval_at(start, ttl / 2);
+                let result = loop {
+                    tokio::select! {
+                        result = &mut encode => break result,
+                        _ = renewals.tick() => {
+                            if let Err(e) = storage.renew_lease(&lease).await {
+                                info!("Failed to renew {}: {:?}", lock, e);
+                            }
+                        }
+                    }
+                };
+                if let Err(e) = storage.release_lease(lease).await {
+                    info!("Failed to release {}: {:?}", lock, e);
+                }
                 result
-            );
+            }
+            Err(StorageError::Conflict) => {
+                info!("Another server is encoding {}", blob_name);
+                self.wait_for_job(container, video, blob_name).await
+            }
+            Err(e) => Err(e.into()),
         }
+    }
+
+    /// Waits until the job has written `blob_name`, or gives up after the
+    /// configured job timeout.
+    async fn wait_for_job(
+        &self,
+        container: &str,
+        video: &str,
+        blob_name: &str,
+    ) -> anyhow::Result<()> {
+        let storage = self.storage.get_video(container, video).await?;
+        let path = blob_name.trim_start_matches('/');
+        info!("Waiting for job to write {}", path);
+        let timeout = std::time::Duration::from_secs(self.config.job_timeout);
+        tokio::time::timeout(timeout, storage.wait_for(path))
+            .await
+            .map_err(|_| anyhow::anyhow!("timed out waiting for {}", path))??;
         Ok(())
     }
+}
 
-    async fn wait_for_job(&self) {
-        let jobs: Api<Job> = Api::default_namespaced(self.client.clone());
-        //await jobs.watch(lp, version)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use storage_azure::AzureStorageContainer;
+
+    #[tokio::test]
+    async fn presigns_job_urls_on_azure() {
+        let storage = AzureStorageContainer::from_connection_string(
+            "AccountName=account;AccountKey=a2V5;EndpointSuffix=core.windows.net",
+            "videos",
+        )
+        .unwrap();
+        let expiry = std::time::Duration::from_secs(600);
+        let input = input_url(&storage, "movie.mp4", expiry).await.unwrap();
+        assert_eq!(input.path(), "/videos/movie.mp4");
+
+        let output = segments_url(&storage, "movie.mp4", 2, expiry).await.unwrap();
+        let segment = storage::Url::parse(&output.replace("%d", "7")).unwrap();
+        assert_eq!(segment.path(), "/videos/movie/level2/segment7.ts");
+        assert!(segment.query_pairs().any(|(k, v)| k == "sp" && v == "w"));
+        assert!(segment.query_pairs().any(|(k, _)| k == "sig"));
     }
 }
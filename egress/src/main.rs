@@ -2,6 +2,8 @@ mod azure_storage;
 mod config;
 mod kubernetes;
 mod manifest;
+mod s3_storage;
+mod url_storage;
 
 use config::{AppConfig, JobConfig};
 use axum::{
@@ -9,7 +11,7 @@ use axum::{
     extract::{BodyStream, FromRef, Path, State},
     http::HeaderValue,
     response::{IntoResponse, Redirect, Response},
-    routing::{get, get_service, post},
+    routing::{get, post},
     Router, Server,
 };
 use azure_storage::{AzureStorage, StorageServer};
@@ -18,7 +20,15 @@ use hyper::{HeaderMap, Method, StatusCode};
 use kubernetes::KubernetesMediaServer;
 use log::{error, info};
 use manifest::ManifestServer;
+use metrics_exporter_prometheus::PrometheusBuilder;
+use s3_storage::S3Storage;
+use std::sync::Arc;
+use storage::{CachePolicy, StorageCache, UrlSigner};
+use storage_proxy::StorageConfig;
+use storage_s3::S3Config;
+use tokio::io::AsyncWriteExt;
 use tokio_pipe::PipeWrite;
+use url_storage::UrlStorage;
 use tower_http::{
     cors::Any,
     services::{ServeDir, ServeFile},
@@ -26,17 +36,40 @@ use tower_http::{
 
 #[derive(Clone)]
 struct AppState {
-    storage: AzureStorage,
+    videos: Arc<dyn StorageServer + Send + Sync>,
     media: KubernetesMediaServer,
 }
 
 impl AppState {
     async fn new() -> Self {
         let config = AppConfig::new().unwrap();
-        let storage = AzureStorage::new(config.clone());
+        let cache = config.cache_fragments.then(|| {
+            StorageCache::new(CachePolicy {
+                memory_capacity: config.cache_memory_size,
+                disk_root: config.cache_directory.as_ref().map(Into::into),
+                ..Default::default()
+            })
+        });
+        let storage = AzureStorage::new(config.clone(), cache.clone());
+        let videos: Arc<dyn StorageServer + Send + Sync> =
+            match (&config.storage_url, config.storage_type.as_str()) {
+                (Some(url), _) => {
+                    let proxy = StorageConfig {
+                        signer: config.storage_signing_key.as_deref().map(UrlSigner::new),
+                        ca_certificate: config
+                            .storage_ca_certificate
+                            .as_ref()
+                            .map(|path| std::fs::read(path).unwrap()),
+                        ..Default::default()
+                    };
+                    Arc::new(UrlStorage::new(url, cache, proxy))
+                }
+                (None, "s3") => Arc::new(S3Storage::new(&S3Config::from_env(), cache)),
+                (None, _) => Arc::new(storage.clone()),
+            };
         let job_config = JobConfig::new();
         let media = KubernetesMediaServer::new(config, job_config, storage).await;
-        Self { storage, media }
+        Self { videos, media }
     }
 }
 
@@ -56,11 +89,9 @@ async fn get_variant_playlist(
     let mut headers = HeaderMap::new();
     if video.ends_with(".m3u8") {
         headers.append("Content-Type", HeaderValue::from_static(HLS_MIME_TYPE));
-        let result = server.storage.get_video(&container, &video).await;
-        if let Err(e) = result  {
+        let Ok(storage) = server.videos.get_video(&container, &video).await else {
             return (StatusCode::NOT_FOUND, headers, String::new());
-        }
-        let storage = result.unwrap();
+        };
         let manifest = ManifestServer::new(storage);
         let result = manifest.get_variant_playlist(video).await;
         return match result {
@@ -78,11 +109,9 @@ async fn get_media_playlist(
     let mut headers = HeaderMap::new();
     if level.ends_with(".m3u8") && level.starts_with("level") {
         headers.append("Content-Type", HeaderValue::from_static(HLS_MIME_TYPE));
-        let l = level.parse::<u32>().unwrap_or(0_u32);
-        let result = server.storage.get_video(conatiner, video).await;
-        let storage = match result  {
-            Ok(s) =>s,
-            _ => { return (StatusCode::NOT_FOUND, headers, String::new()); }
+        let l = level[5..level.len() - 5].parse::<u32>().unwrap_or(0_u32);
+        let Ok(storage) = server.videos.get_video(&container, &video).await else {
+            return (StatusCode::NOT_FOUND, headers, String::new());
         };
         let manifest = ManifestServer::new(storage);
         let result = manifest
@@ -147,9 +176,6 @@ async fn post_to_pipe(Path(pipe): Path<String>, stream: BodyStream) -> (StatusCo
 }
 
 const STATIC_DIR: &str = "./wwwroot/";
-async fn handle_error(_err: std::io::Error) -> impl IntoResponse {
-    (StatusCode::INTERNAL_SERVER_ERROR, "Something went wrong...")
-}
 async fn shutdown_signal() {
     // Wait for the CTRL+C signal
     tokio::signal::ctrl_c()
@@ -166,12 +192,14 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .allow_methods(vec![Method::GET])
         .allow_origin(Any);
 
+    // the storage containers record their metrics here, see `/metrics`.
+    let metrics = PrometheusBuilder::new().install_recorder()?;
+
     // build our application with a single route
     let state = AppState::new().await;
     let service = ServeDir::new(STATIC_DIR).not_found_service(ServeFile::new("wwwroot/hlsjs.html"));
     let app = Router::new()
-        .with_state(state)
-        .nest("/wwwroot", get_service(service).handle_error(handle_error))
+        .nest_service("/wwwroot", service)
         .route(
             "/",
             get(|| async { Redirect::permanent("/wwwroot/hlsjs.html") }),
@@ -180,7 +208,9 @@ pub async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         .route("/:container/:video/:level", get(get_media_playlist))
         .route("/:container/:video/:level/:segment", get(get_media_segment))
         .route("/pipe/:pipe", post(post_to_pipe))
-        .layer(cors);
+        .route("/metrics", get(move || async move { metrics.render() }))
+        .layer(cors)
+        .with_state(state);
 
     // run it with hyper on localhost:3000
     let addr = std::env::var("BIND_ENDPOINT").unwrap_or_else(|_| "0.0.0.0:3000".into());
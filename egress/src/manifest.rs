@@ -5,6 +5,7 @@ use hls_m3u8::{
     types::{StreamData, UFloat},
     MasterPlaylist, MediaPlaylist, MediaSegment,
 };
+use anyhow::anyhow;
 use serde::{Deserialize, Serialize};
 use storage::StorageContainer;
 
@@ -38,7 +39,7 @@ pub const VARIANTS: [Variant; 3] = [
 ];
 
 pub struct ManifestServer {
-    storage: Box<dyn StorageContainer>,
+    storage: Box<dyn StorageContainer + Send + Sync>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -52,15 +53,13 @@ struct Metadata {
 }
 
 impl ManifestServer {
-    pub fn new(storage: impl StorageContainer) -> Self {
-        ManifestServer {
-            storage: Box::new(storage),
-        }
+    pub fn new(storage: Box<dyn StorageContainer + Send + Sync>) -> Self {
+        ManifestServer { storage }
     }
 
     pub async fn get_variant_playlist(&self, video: String) -> anyhow::Result<String> {
         let file_name: String = video[..video.len() - 5].into();
-        if !self.storage.exists(&file_name).await {
+        if !self.storage.exists(&file_name).await? {
             return Err(anyhow!("failed to find the video {}", video));
         }
         let variants: Vec<_> = VARIANTS
@@ -88,7 +87,7 @@ impl ManifestServer {
     }
 
     pub async fn get_media_playlist(&self, video: String, level: u32) -> anyhow::Result<String> {
-        let duration = self.get_media_duration("", &container, &video).await?;
+        let duration = self.get_media_duration(&video).await?;
         let num_segments = duration as u32 / SEGMENT_DURATION;
         let segment_duration = Duration::from_secs(SEGMENT_DURATION as u64);
         let segments: Vec<_> = (0..num_segments)
@@ -109,12 +108,7 @@ impl ManifestServer {
         Ok(playlist.to_string())
     }
 
-    async fn get_media_duration(
-        &self,
-        account: &str,
-        video: &str,
-        path: &str,
-    ) -> anyhow::Result<f64> {
+    async fn get_media_duration(&self, path: &str) -> anyhow::Result<f64> {
         let metadata = self.storage.get_metadata(path).await?;
         let value = serde_json::from_str::<Metadata>(&metadata)?;
         Ok(value.format.duration)
@@ -0,0 +1,45 @@
+use crate::azure_storage::StorageServer;
+use async_trait::async_trait;
+use storage::{
+    CachingStorageContainer, InstrumentedStorageContainer, StorageCache, StorageContainer,
+};
+use storage_s3::{aws_sdk_s3::Client, S3Config, S3StorageContainer};
+
+/// Serves videos from S3 buckets, one bucket per account.
+#[derive(Clone)]
+pub struct S3Storage {
+    client: Client,
+    cache: Option<StorageCache>,
+}
+
+impl S3Storage {
+    pub fn new(config: &S3Config, cache: Option<StorageCache>) -> Self {
+        Self {
+            client: config.client(),
+            cache,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageServer for S3Storage {
+    async fn get_video(
+        &self,
+        account: &str,
+        video: &str,
+    ) -> anyhow::Result<Box<dyn StorageContainer + Send + Sync>> {
+        let container = InstrumentedStorageContainer::new(
+            S3StorageContainer::new(self.client.clone(), account, ""),
+            account,
+            "s3",
+        );
+        match &self.cache {
+            Some(cache) => Ok(Box::new(CachingStorageContainer::with_namespace(
+                container,
+                cache.clone(),
+                &format!("{account}/{video}"),
+            ))),
+            None => Ok(Box::new(container)),
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use crate::azure_storage::StorageServer;
+use async_trait::async_trait;
+use storage::{
+    CachingStorageContainer, InstrumentedStorageContainer, StorageCache, StorageContainer,
+    StorageRegistry,
+};
+use storage_proxy::StorageConfig;
+
+/// Serves videos from any backend of the storage registry. The URL template
+/// may contain `{account}` and `{video}` placeholders, e.g. `s3://{account}`
+/// or `http://proxy:8080/{account}/{video}`. Storage proxies are talked to
+/// with the CA certificate and the signer of `proxy`.
+#[derive(Clone)]
+pub struct UrlStorage {
+    registry: StorageRegistry,
+    template: String,
+    cache: Option<StorageCache>,
+}
+
+impl UrlStorage {
+    pub fn new(template: &str, cache: Option<StorageCache>, proxy: StorageConfig) -> Self {
+        let mut registry = StorageRegistry::new();
+        storage_azure::register(&mut registry);
+        storage_proxy::register_with(&mut registry, proxy);
+        storage_s3::register(&mut registry);
+        Self {
+            registry,
+            template: template.to_owned(),
+            cache,
+        }
+    }
+}
+
+#[async_trait]
+impl StorageServer for UrlStorage {
+    async fn get_video(
+        &self,
+        account: &str,
+        video: &str,
+    ) -> anyhow::Result<Box<dyn StorageContainer + Send + Sync>> {
+        let url = self
+            .template
+            .replace("{account}", account)
+            .replace("{video}", video);
+        // the scheme tells the backends apart.
+        let backend = url.split(':').next().unwrap_or_default();
+        let container =
+            InstrumentedStorageContainer::new(self.registry.open_str(&url)?, account, backend);
+        match &self.cache {
+            Some(cache) => Ok(Box::new(CachingStorageContainer::with_namespace(
+                container,
+                cache.clone(),
+                &format!("{account}/{video}"),
+            ))),
+            None => Ok(Box::new(container)),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+use serde::Serialize;
+use storage::Checksums;
+
+/// The name of the descriptor uploaded next to the encoded files.
+pub const ASSET_DESCRIPTOR: &str = "asset.json";
+
+/// Lists the uploaded files with their hashes, so consumers can verify what
+/// they download.
+#[derive(Debug, Default, Serialize)]
+pub struct AssetDescriptor {
+    pub files: Vec<AssetFile>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AssetFile {
+    pub name: String,
+    pub size: u64,
+    /// Base64 encoded, as in a `Content-MD5` header.
+    pub md5: Option<String>,
+    /// Base64 encoded big-endian CRC64/NVME, as in S3's `x-amz-checksum-crc64nvme`.
+    /// Azure's `x-ms-content-crc64` carries the same CRC little-endian.
+    pub crc64: Option<String>,
+}
+
+impl AssetFile {
+    pub fn new(name: &str, size: u64, checksums: &Checksums) -> Self {
+        Self {
+            name: name.to_owned(),
+            size,
+            md5: checksums.md5_base64(),
+            crc64: checksums.crc64_base64(),
+        }
+    }
+}
@@ -1,4 +1,7 @@
+use azure_storage_blobs::prelude::ContainerClient;
 use std::{ffi::OsString, path::PathBuf};
+use storage::{BoxedStorageContainer, StorageRegistry};
+use storage_azure::AzureStorageContainer;
 use url::Url;
 
 pub enum Location {
@@ -21,4 +24,31 @@ impl Location {
             Self::Path(path) => path.to_str().unwrap(),
         }
     }
+
+    /// Opens the container a URL output is uploaded to. Local paths are
+    /// written directly and have no container.
+    pub fn open(
+        &self,
+        registry: &StorageRegistry,
+    ) -> anyhow::Result<Option<BoxedStorageContainer>> {
+        match self {
+            Self::Url(url) => Ok(Some(registry.open(url)?)),
+            Self::Path(_) => Ok(None),
+        }
+    }
+}
+
+/// The schemes outputs can be uploaded to: `file`, `memory`, `http` for a
+/// storage proxy, `az`, `s3`, and `https` for Azure container SAS URLs.
+pub fn storage_registry() -> StorageRegistry {
+    let mut registry = StorageRegistry::new();
+    storage_azure::register(&mut registry);
+    storage_proxy::register(&mut registry);
+    storage_s3::register(&mut registry);
+    registry.register("https", |url| {
+        let container =
+            ContainerClient::from_sas_url(url).map_err(AzureStorageContainer::from_azure_error)?;
+        Ok(Box::new(AzureStorageContainer::new(container)))
+    });
+    registry
 }
@@ -1,17 +1,19 @@
-mod azure_storage;
+mod asset;
 mod encoder;
 mod location;
 mod packager;
 mod preset;
+mod storage_uploader;
 mod uploader;
 
-use azure_storage::AzureUploader;
 use encoder::Encoder;
 use futures::future::{join, join3};
-use location::Location;
+use location::{storage_registry, Location};
 use log::info;
 use packager::{Packager, PackagerOptions};
 use preset::Preset;
+use storage::UploadPolicy;
+use storage_uploader::StorageUploader;
 use tempfile::TempDir;
 use uploader::Uploader;
 
@@ -30,7 +32,11 @@ async fn main() -> anyhow::Result<()> {
     let mut output_dir = temp_dir.path();
     let path_prefix = "rust/";
     let uploader = match &output {
-        Location::Url(url) => Some(AzureUploader::create(url, path_prefix)),
+        Location::Url(_) => output
+            .open(&storage_registry())?
+            .map(|container| {
+                StorageUploader::create(container, path_prefix, UploadPolicy::default())
+            }),
         Location::Path(path) => {
             output_dir = path.as_path();
             None
@@ -75,6 +81,7 @@ async fn main() -> anyhow::Result<()> {
         if !output_pipes {
             uploader.upload_media_file(&files).await?;
         }
+        uploader.upload_descriptor().await?;
     }
 
     Ok(())
@@ -31,7 +31,6 @@ pub struct PackagerFiles {
 }
 pub struct PackagerOptions {
     pub manifest_name: PathBuf,
-    pub pipes: bool,
     pub command: String,
 }
 
@@ -92,7 +91,6 @@ impl PackagerOptions {
     pub fn create(manifest_name: PathBuf) -> Self {
         PackagerOptions {
             manifest_name,
-            pipes: !cfg!(windows),
             command: if cfg!(windows) {
                 "packager-win-x64.exe"
             } else {
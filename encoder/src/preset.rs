@@ -3,15 +3,12 @@ use log::info;
 use crate::location::Location;
 
 pub struct Video {
-    pub width: i32,
-    pub height: i32,
     pub bitrate: String,
     pub size: String,
 }
 
 pub struct Audio {
     pub bitrate: String,
-    pub channels: String,
 }
 
 pub struct Preset {
@@ -24,8 +21,6 @@ pub struct Preset {
 impl Video {
     fn create(width: i32, height: i32, bitrate: i32) -> Self {
         Video {
-            width,
-            height,
             bitrate: bitrate.to_string(),
             size: format!("{width}x{height}"),
         }
@@ -36,7 +31,6 @@ impl Audio {
     fn create(bitrate: i32) -> Self {
         Audio {
             bitrate: bitrate.to_string(),
-            channels: 2.to_string(),
         }
     }
 }
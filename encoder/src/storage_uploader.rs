@@ -0,0 +1,110 @@
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use storage::{BoxedStorageContainer, ChecksumHasher, StorageError, UploadPolicy};
+use tokio::fs::File;
+use tokio_util::io::ReaderStream;
+
+use crate::asset::{AssetDescriptor, AssetFile, ASSET_DESCRIPTOR};
+use crate::uploader::Uploader;
+
+/// Uploads to any container opened through the storage registry. Outputs that
+/// are still being encoded are appended as they are read, so players can tail
+/// them, if the backend supports appends. Finished files are uploaded in
+/// blocks staged in parallel.
+pub struct StorageUploader {
+    container: BoxedStorageContainer,
+    prefix: String,
+    policy: UploadPolicy,
+    uploaded: Mutex<Vec<AssetFile>>,
+}
+
+impl StorageUploader {
+    pub fn create(container: BoxedStorageContainer, prefix: &str, policy: UploadPolicy) -> Self {
+        Self {
+            container,
+            prefix: prefix.to_owned(),
+            policy,
+            uploaded: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+/// Outputs the packager writes into named pipes are only complete once it
+/// closes them.
+#[cfg(unix)]
+async fn is_growing(path: &Path) -> std::io::Result<bool> {
+    use std::os::unix::fs::FileTypeExt;
+    Ok(tokio::fs::metadata(path).await?.file_type().is_fifo())
+}
+
+#[cfg(windows)]
+async fn is_growing(_: &Path) -> std::io::Result<bool> {
+    Ok(false)
+}
+
+#[async_trait]
+impl Uploader for StorageUploader {
+    async fn upload(&self, path: &Path) -> anyhow::Result<()> {
+        let mut name = self.prefix.clone();
+        name.push_str(path.file_name().unwrap().to_str().unwrap());
+        let file = File::open(path).await?;
+        let capacity = match path.extension().and_then(|e| e.to_str()) {
+            Some("mp4") => 0x10000usize,
+            _ => 0x1000usize,
+        };
+        // hash what is actually sent, the file may still be growing.
+        let hashed = Arc::new(Mutex::new((ChecksumHasher::new(), 0u64)));
+        let hashing = hashed.clone();
+        let mut stream = ReaderStream::with_capacity(file, capacity)
+            .map_err(StorageError::from)
+            .inspect_ok(move |chunk: &Bytes| {
+                let mut hashing = hashing.lock().unwrap();
+                hashing.0.update(chunk);
+                hashing.1 += chunk.len() as u64;
+            });
+        if is_growing(path).await? {
+            match self.container.open_append(&name).await {
+                Ok(mut session) => {
+                    while let Some(chunk) = stream.next().await {
+                        session.append(chunk?).await?;
+                    }
+                    session.seal().await?;
+                }
+                Err(StorageError::Unsupported) => {
+                    self.container.set_content(&name, Box::pin(stream)).await?;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        } else {
+            self.container
+                .set_content_parallel(&name, Box::pin(stream), &self.policy)
+                .await?;
+        }
+        let (hasher, size) = hashed.lock().unwrap().clone();
+        self.uploaded
+            .lock()
+            .unwrap()
+            .push(AssetFile::new(&name, size, &hasher.finish()));
+        Ok(())
+    }
+
+    async fn upload_descriptor(&self) -> anyhow::Result<()> {
+        let mut descriptor = AssetDescriptor::default();
+        for file in self.uploaded.lock().unwrap().iter() {
+            // files uploaded twice are described as last uploaded.
+            descriptor.files.retain(|f| f.name != file.name);
+            descriptor.files.push(file.clone());
+        }
+        descriptor.files.sort_by(|a, b| a.name.cmp(&b.name));
+        let json = serde_json::to_vec_pretty(&descriptor)?;
+        let name = format!("{}{}", self.prefix, ASSET_DESCRIPTOR);
+        let body = futures::stream::iter([Ok(Bytes::from(json))]);
+        self.container.set_content(&name, Box::pin(body)).await?;
+        Ok(())
+    }
+}
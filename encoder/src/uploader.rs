@@ -10,6 +10,9 @@ use crate::packager::PackagerFiles;
 pub trait Uploader {
     async fn upload(&self, path: &Path) -> anyhow::Result<()>;
 
+    /// Uploads the asset descriptor listing what was uploaded so far.
+    async fn upload_descriptor(&self) -> anyhow::Result<()>;
+
     async fn upload_media_file(&self, files: &PackagerFiles) -> anyhow::Result<()> {
         let mut uploads: Vec<_> = files
             .streams
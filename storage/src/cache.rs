@@ -0,0 +1,1026 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use lru::LruCache;
+
+use crate::{
+    slice_stream, AppendSession, BlockUpload, Checksums, FsStorageContainer, Lease, ListStream,
+    ObjectProperties, Permissions, StorageContainer, StorageError, StreamType, Url, WatchStream,
+    WriteCondition,
+};
+
+/// How much [`StorageCache`] keeps and for how long.
+#[derive(Debug, Clone)]
+pub struct CachePolicy {
+    /// The total size of the content and metadata kept in memory.
+    pub memory_capacity: usize,
+    /// Objects larger than this are never cached.
+    pub max_object_size: usize,
+    /// A directory for the on-disk tier, which only holds content. The cache
+    /// keeps its files in a `storage-cache` subdirectory and leaves the rest
+    /// of the directory alone. Entries do not survive a restart, so the
+    /// subdirectory is emptied when the cache is created.
+    pub disk_root: Option<PathBuf>,
+    /// The total size of the content kept on disk.
+    pub disk_capacity: u64,
+    /// How long entries stay valid, `None` meaning until they are evicted or
+    /// invalidated by a write through the cache.
+    pub default_ttl: Option<Duration>,
+    /// Overrides `default_ttl` for paths ending with the given suffix. The
+    /// first matching suffix wins.
+    pub ttls: Vec<(String, Option<Duration>)>,
+}
+
+impl Default for CachePolicy {
+    fn default() -> Self {
+        Self {
+            memory_capacity: 256 * 1024 * 1024,
+            max_object_size: 16 * 1024 * 1024,
+            disk_root: None,
+            disk_capacity: 4 * 1024 * 1024 * 1024,
+            default_ttl: None,
+            ttls: Vec::new(),
+        }
+    }
+}
+
+impl CachePolicy {
+    pub fn ttl(&self, path: &str) -> Option<Duration> {
+        self.ttls
+            .iter()
+            .find(|(suffix, _)| path.ends_with(suffix.as_str()))
+            .map_or(self.default_ttl, |(_, ttl)| *ttl)
+    }
+}
+
+#[derive(Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Content(String),
+    Metadata(String),
+}
+
+enum Value {
+    Content(Bytes),
+    Metadata(String),
+}
+
+struct Entry {
+    value: Value,
+    expires: Option<Instant>,
+}
+
+impl Entry {
+    fn size(&self) -> usize {
+        match &self.value {
+            Value::Content(content) => content.len(),
+            Value::Metadata(metadata) => metadata.len(),
+        }
+    }
+}
+
+struct DiskEntry {
+    size: u64,
+    expires: Option<Instant>,
+}
+
+fn is_expired(expires: Option<Instant>) -> bool {
+    expires.is_some_and(|expires| expires <= Instant::now())
+}
+
+/// The invalidations of a key that fills are in flight for.
+struct Pending {
+    generation: u64,
+    fills: usize,
+}
+
+struct CacheState {
+    memory: LruCache<Key, Entry>,
+    memory_size: usize,
+    disk: LruCache<String, DiskEntry>,
+    disk_size: u64,
+    /// Only holds keys with fills in flight, which are dropped if their key
+    /// is invalidated before they finish, since they may have read stale data.
+    pending: HashMap<String, Pending>,
+}
+
+impl CacheState {
+    fn get(&mut self, key: &Key) -> Option<&Value> {
+        if self.memory.peek(key).is_some_and(|e| is_expired(e.expires)) {
+            self.remove(key);
+        }
+        self.memory.get(key).map(|entry| &entry.value)
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.memory.pop(key) {
+            self.memory_size -= entry.size();
+        }
+    }
+
+    fn insert(&mut self, key: Key, entry: Entry, capacity: usize) {
+        self.remove(&key);
+        if entry.size() > capacity {
+            return;
+        }
+        self.memory_size += entry.size();
+        self.memory.put(key, entry);
+        while self.memory_size > capacity {
+            match self.memory.pop_lru() {
+                Some((_, entry)) => self.memory_size -= entry.size(),
+                None => break,
+            }
+        }
+    }
+
+    fn is_current(&self, ticket: &Ticket) -> bool {
+        self.pending
+            .get(&ticket.key)
+            .is_some_and(|pending| pending.generation == ticket.generation)
+    }
+
+    fn invalidate_pending(&mut self, key: &str) {
+        if let Some(pending) = self.pending.get_mut(key) {
+            pending.generation += 1;
+        }
+    }
+
+    fn get_disk(&mut self, key: &str) -> bool {
+        if self.disk.peek(key).is_some_and(|e| is_expired(e.expires)) {
+            self.remove_disk(key);
+        }
+        self.disk.get(key).is_some()
+    }
+
+    fn remove_disk(&mut self, key: &str) -> bool {
+        match self.disk.pop(key) {
+            Some(entry) => {
+                self.disk_size -= entry.size;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/// Registers a fill of `key` for as long as it is alive, so invalidations of
+/// the key while it is in flight can be told apart from earlier ones.
+struct Ticket {
+    state: Arc<Mutex<CacheState>>,
+    key: String,
+    generation: u64,
+}
+
+impl Drop for Ticket {
+    fn drop(&mut self) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pending) = state.pending.get_mut(&self.key) {
+            pending.fills -= 1;
+            if pending.fills == 0 {
+                state.pending.remove(&self.key);
+            }
+        }
+    }
+}
+
+/// The subdirectory of `CachePolicy::disk_root` the disk tier writes to.
+const DISK_DIR: &str = "storage-cache";
+
+/// Empties the disk tier under `root`, whose files would otherwise count
+/// against its capacity without being accounted for.
+fn clear_disk(root: &Path) -> std::io::Result<()> {
+    match std::fs::remove_dir_all(root) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// The memory and disk tiers behind [`CachingStorageContainer`]. Clones share
+/// the same tiers, so one cache can serve many containers.
+#[derive(Clone)]
+pub struct StorageCache {
+    policy: Arc<CachePolicy>,
+    state: Arc<Mutex<CacheState>>,
+    disk: Option<Arc<FsStorageContainer>>,
+}
+
+impl StorageCache {
+    pub fn new(policy: CachePolicy) -> Self {
+        let disk = policy
+            .disk_root
+            .as_ref()
+            .map(|root| root.join(DISK_DIR))
+            .and_then(|root| match clear_disk(&root) {
+                Ok(()) => Some(Arc::new(FsStorageContainer::new(root))),
+                Err(e) => {
+                    tracing::warn!(root = %root.display(), error = %e, "not caching on disk");
+                    None
+                }
+            });
+        Self {
+            state: Arc::new(Mutex::new(CacheState {
+                memory: LruCache::unbounded(),
+                memory_size: 0,
+                disk: LruCache::unbounded(),
+                disk_size: 0,
+                pending: HashMap::new(),
+            })),
+            policy: Arc::new(policy),
+            disk,
+        }
+    }
+
+    pub fn policy(&self) -> &CachePolicy {
+        &self.policy
+    }
+
+    /// The number of bytes currently cached in memory.
+    pub fn memory_size(&self) -> usize {
+        self.state.lock().unwrap().memory_size
+    }
+
+    /// The number of bytes currently cached on disk.
+    pub fn disk_size(&self) -> u64 {
+        self.state.lock().unwrap().disk_size
+    }
+
+    fn ticket(&self, key: &str) -> Ticket {
+        let mut state = self.state.lock().unwrap();
+        let pending = state.pending.entry(key.to_owned()).or_insert(Pending {
+            generation: 0,
+            fills: 0,
+        });
+        pending.fills += 1;
+        Ticket {
+            state: self.state.clone(),
+            key: key.to_owned(),
+            generation: pending.generation,
+        }
+    }
+
+    fn get_content(&self, key: &str) -> Option<Bytes> {
+        match self
+            .state
+            .lock()
+            .unwrap()
+            .get(&Key::Content(key.to_owned()))
+        {
+            Some(Value::Content(content)) => Some(content.clone()),
+            _ => None,
+        }
+    }
+
+    fn get_metadata(&self, key: &str) -> Option<String> {
+        match self
+            .state
+            .lock()
+            .unwrap()
+            .get(&Key::Metadata(key.to_owned()))
+        {
+            Some(Value::Metadata(metadata)) => Some(metadata.clone()),
+            _ => None,
+        }
+    }
+
+    async fn get_disk_content(&self, key: &str) -> Option<StreamType> {
+        let disk = self.disk.as_ref()?;
+        if !self.state.lock().unwrap().get_disk(key) {
+            return None;
+        }
+        match disk.get_content(key).await {
+            Ok(content) => Some(content),
+            Err(_) => {
+                self.state.lock().unwrap().remove_disk(key);
+                None
+            }
+        }
+    }
+
+    fn insert_metadata(&self, ticket: &Ticket, metadata: String, ttl: Option<Duration>) {
+        let mut state = self.state.lock().unwrap();
+        if !state.is_current(ticket) {
+            return;
+        }
+        let entry = Entry {
+            value: Value::Metadata(metadata),
+            expires: ttl.map(|ttl| Instant::now() + ttl),
+        };
+        state.insert(
+            Key::Metadata(ticket.key.clone()),
+            entry,
+            self.policy.memory_capacity,
+        );
+    }
+
+    fn insert_content(&self, ticket: Ticket, content: Bytes, ttl: Option<Duration>, to_disk: bool) {
+        let expires = ttl.map(|ttl| Instant::now() + ttl);
+        {
+            let mut state = self.state.lock().unwrap();
+            if !state.is_current(&ticket) {
+                return;
+            }
+            let entry = Entry {
+                value: Value::Content(content.clone()),
+                expires,
+            };
+            state.insert(
+                Key::Content(ticket.key.clone()),
+                entry,
+                self.policy.memory_capacity,
+            );
+        }
+        match &self.disk {
+            Some(disk) if to_disk && content.len() as u64 <= self.policy.disk_capacity => {
+                let cache = self.clone();
+                let disk = disk.clone();
+                tokio::spawn(async move {
+                    let key = ticket.key.clone();
+                    let size = content.len() as u64;
+                    if !cache.state.lock().unwrap().is_current(&ticket) {
+                        return;
+                    }
+                    let body = Box::pin(futures::stream::once(async { Ok(content) }));
+                    if disk.set_content(&key, body).await.is_err() {
+                        return;
+                    }
+                    let evicted = {
+                        let mut state = cache.state.lock().unwrap();
+                        if !state.is_current(&ticket) {
+                            // the file may have replaced one a newer fill
+                            // wrote, so neither can be trusted.
+                            state.remove_disk(&key);
+                            vec![key]
+                        } else {
+                            state.remove_disk(&key);
+                            state.disk_size += size;
+                            state.disk.put(key, DiskEntry { size, expires });
+                            let mut evicted = Vec::new();
+                            while state.disk_size > cache.policy.disk_capacity {
+                                match state.disk.pop_lru() {
+                                    Some((key, entry)) => {
+                                        state.disk_size -= entry.size;
+                                        evicted.push(key);
+                                    }
+                                    None => break,
+                                }
+                            }
+                            evicted
+                        }
+                    };
+                    for key in evicted {
+                        let _ = disk.delete(&key).await;
+                    }
+                });
+            }
+            _ => {}
+        }
+    }
+
+    /// Drops the cached content and metadata of `key`.
+    pub async fn invalidate(&self, key: &str) {
+        let on_disk = {
+            let mut state = self.state.lock().unwrap();
+            state.invalidate_pending(key);
+            state.remove(&Key::Content(key.to_owned()));
+            state.remove(&Key::Metadata(key.to_owned()));
+            state.remove_disk(key)
+        };
+        if let (true, Some(disk)) = (on_disk, &self.disk) {
+            let _ = disk.delete(key).await;
+        }
+    }
+
+    /// Drops every cached entry whose key starts with `prefix`.
+    pub async fn invalidate_prefix(&self, prefix: &str) {
+        let on_disk: Vec<_> = {
+            let mut state = self.state.lock().unwrap();
+            let keys: Vec<_> = state
+                .pending
+                .keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect();
+            for key in &keys {
+                state.invalidate_pending(key);
+            }
+            let keys: Vec<_> = state
+                .memory
+                .iter()
+                .map(|(key, _)| key.clone())
+                .filter(|key| match key {
+                    Key::Content(key) | Key::Metadata(key) => key.starts_with(prefix),
+                })
+                .collect();
+            for key in keys {
+                state.remove(&key);
+            }
+            let keys: Vec<_> = state
+                .disk
+                .iter()
+                .map(|(key, _)| key.clone())
+                .filter(|key| key.starts_with(prefix))
+                .collect();
+            for key in &keys {
+                state.remove_disk(key);
+            }
+            keys
+        };
+        if let Some(disk) = &self.disk {
+            for key in on_disk {
+                let _ = disk.delete(&key).await;
+            }
+        }
+    }
+}
+
+/// Caches whole objects as they are read through it, until `max_object_size`
+/// is exceeded.
+struct FillStream {
+    stream: StreamType,
+    chunks: Vec<Bytes>,
+    size: usize,
+    fill: Option<Fill>,
+}
+
+struct Fill {
+    cache: StorageCache,
+    ticket: Ticket,
+    ttl: Option<Duration>,
+    to_disk: bool,
+}
+
+impl Stream for FillStream {
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let result = self.stream.poll_next_unpin(cx);
+        match &result {
+            Poll::Ready(Some(Ok(chunk))) if self.fill.is_some() => {
+                self.size += chunk.len();
+                let limit = self.fill.as_ref().unwrap().cache.policy.max_object_size;
+                if self.size > limit {
+                    self.fill = None;
+                    self.chunks = Vec::new();
+                } else {
+                    self.chunks.push(chunk.clone());
+                }
+            }
+            Poll::Ready(Some(Err(_))) => self.fill = None,
+            Poll::Ready(None) => {
+                if let Some(fill) = self.fill.take() {
+                    let content = Bytes::from(std::mem::take(&mut self.chunks).concat());
+                    fill.cache
+                        .insert_content(fill.ticket, content, fill.ttl, fill.to_disk);
+                }
+            }
+            _ => {}
+        }
+        result
+    }
+}
+
+/// A read-through cache in front of another container.
+///
+/// Content and metadata are cached when they are read and dropped when they are
+/// written, deleted or replaced through this container. Writes that bypass it
+/// are only picked up once the entries expire.
+pub struct CachingStorageContainer<S> {
+    inner: S,
+    cache: StorageCache,
+    namespace: String,
+}
+
+impl<S> CachingStorageContainer<S> {
+    pub fn new(inner: S, cache: StorageCache) -> Self {
+        Self::with_namespace(inner, cache, "")
+    }
+
+    /// Prefixes every cache key with `namespace`, so containers for different
+    /// accounts or videos can share a cache.
+    pub fn with_namespace(inner: S, cache: StorageCache, namespace: &str) -> Self {
+        Self {
+            inner,
+            cache,
+            namespace: namespace.trim_matches('/').to_owned(),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn cache(&self) -> &StorageCache {
+        &self.cache
+    }
+
+    fn key(&self, path: &str) -> String {
+        let path = path.trim_start_matches('/');
+        if self.namespace.is_empty() {
+            path.to_owned()
+        } else {
+            format!("{}/{}", self.namespace, path)
+        }
+    }
+
+    fn fill(&self, path: &str, stream: StreamType, ticket: Ticket, to_disk: bool) -> StreamType {
+        Box::pin(FillStream {
+            stream,
+            chunks: Vec::new(),
+            size: 0,
+            fill: Some(Fill {
+                cache: self.cache.clone(),
+                ticket,
+                ttl: self.cache.policy.ttl(path),
+                to_disk,
+            }),
+        })
+    }
+}
+
+#[async_trait]
+impl<S> StorageContainer for CachingStorageContainer<S>
+where
+    S: StorageContainer + Send + Sync,
+{
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        let key = self.key(path);
+        if let Some(content) = self.cache.get_content(&key) {
+            return Ok(Box::pin(futures::stream::once(async { Ok(content) })));
+        }
+        let ticket = self.cache.ticket(&key);
+        if let Some(content) = self.cache.get_disk_content(&key).await {
+            return Ok(self.fill(path, content, ticket, false));
+        }
+        let content = self.inner.get_content(path).await?;
+        Ok(self.fill(path, content, ticket, true))
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        let key = self.key(path);
+        if let Some(content) = self.cache.get_content(&key) {
+            let content: StreamType = Box::pin(futures::stream::once(async { Ok(content) }));
+            return Ok(slice_stream(content, offset, length));
+        }
+        if let Some(content) = self.cache.get_disk_content(&key).await {
+            return Ok(slice_stream(content, offset, length));
+        }
+        self.inner.get_content_range(path, offset, length).await
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        let key = self.key(path);
+        if let Some(metadata) = self.cache.get_metadata(&key) {
+            return Ok(metadata);
+        }
+        let ticket = self.cache.ticket(&key);
+        let metadata = self.inner.get_metadata(path).await?;
+        let ttl = self.cache.policy.ttl(path);
+        self.cache.insert_metadata(&ticket, metadata.clone(), ttl);
+        Ok(metadata)
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        let result = self.inner.set_content(path, content).await;
+        self.cache.invalidate(&self.key(path)).await;
+        result
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        let result = self.inner.set_metadata(path, metadata).await;
+        self.cache.invalidate(&self.key(path)).await;
+        result
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        if self.cache.get_content(&self.key(path)).is_some() {
+            return Ok(true);
+        }
+        self.inner.exists(path).await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        let result = self.inner.set_content_if(path, content, condition).await;
+        self.cache.invalidate(&self.key(path)).await;
+        result
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        let result = self.inner.set_metadata_if(path, metadata, condition).await;
+        self.cache.invalidate(&self.key(path)).await;
+        result
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        self.inner.stat(path).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        self.inner.list(prefix).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let result = self.inner.delete(path).await;
+        self.cache.invalidate(&self.key(path)).await;
+        result
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<usize, StorageError> {
+        let result = self.inner.delete_prefix(prefix).await;
+        self.cache.invalidate_prefix(&self.key(prefix)).await;
+        result
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.copy(from, to).await;
+        self.cache.invalidate(&self.key(to)).await;
+        result
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let result = self.inner.rename(from, to).await;
+        self.cache.invalidate(&self.key(from)).await;
+        self.cache.invalidate(&self.key(to)).await;
+        result
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        let result = self.inner.open_append(path).await;
+        self.cache.invalidate(&self.key(path)).await;
+        Ok(Box::new(CachingAppendSession {
+            inner: result?,
+            cache: self.cache.clone(),
+            key: self.key(path),
+        }))
+    }
+
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        Ok(Box::new(CachingBlockUpload {
+            inner: self.inner.start_block_upload(path).await?,
+            cache: self.cache.clone(),
+            key: self.key(path),
+        }))
+    }
+
+    /// Growing objects are never cached, so tails always go to the inner
+    /// container.
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.inner.get_content_tail(path).await
+    }
+
+    /// Leases always go to the inner container, a cached lease object would
+    /// be stale.
+    async fn acquire_lease(&self, path: &str, ttl: Duration) -> Result<Lease, StorageError> {
+        self.inner.acquire_lease(path, ttl).await
+    }
+
+    async fn renew_lease(&self, lease: &Lease) -> Result<(), StorageError> {
+        self.inner.renew_lease(lease).await
+    }
+
+    async fn release_lease(&self, lease: Lease) -> Result<(), StorageError> {
+        self.inner.release_lease(lease).await
+    }
+
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        self.inner.presign(path, permissions, expiry).await
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        self.inner.watch(prefix).await
+    }
+}
+
+/// Drops whatever was cached while the object was growing once it is sealed.
+struct CachingAppendSession {
+    inner: Box<dyn AppendSession>,
+    cache: StorageCache,
+    key: String,
+}
+
+#[async_trait]
+impl AppendSession for CachingAppendSession {
+    async fn append(&mut self, chunk: Bytes) -> Result<(), StorageError> {
+        self.inner.append(chunk).await
+    }
+
+    async fn seal(self: Box<Self>) -> Result<(), StorageError> {
+        let result = self.inner.seal().await;
+        self.cache.invalidate(&self.key).await;
+        result
+    }
+}
+
+/// Drops what was cached of the previous content once the blocks replace it.
+struct CachingBlockUpload {
+    inner: Box<dyn BlockUpload>,
+    cache: StorageCache,
+    key: String,
+}
+
+#[async_trait]
+impl BlockUpload for CachingBlockUpload {
+    fn min_block_size(&self) -> usize {
+        self.inner.min_block_size()
+    }
+
+    async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError> {
+        self.inner.stage_block(index, block).await
+    }
+
+    async fn commit(
+        self: Box<Self>,
+        blocks: usize,
+        checksums: Checksums,
+    ) -> Result<(), StorageError> {
+        let result = self.inner.commit(blocks, checksums).await;
+        self.cache.invalidate(&self.key).await;
+        result
+    }
+
+    async fn abort(self: Box<Self>) -> Result<(), StorageError> {
+        self.inner.abort().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryStorageContainer, Operation};
+    use futures::TryStreamExt;
+
+    fn body(content: &'static str) -> StreamType {
+        Box::pin(futures::stream::once(
+            async move { Ok(Bytes::from(content)) },
+        ))
+    }
+
+    async fn read_all(stream: StreamType) -> Vec<u8> {
+        let chunks: Vec<Bytes> = stream.try_collect().await.unwrap();
+        chunks.concat()
+    }
+
+    /// Fails every read of the backend, so only cached content can be read.
+    fn break_reads(memory: &MemoryStorageContainer) {
+        for _ in 0..10 {
+            memory.inject_error(Operation::GetContent, StorageError::NotFound);
+            memory.inject_error(Operation::GetMetadata, StorageError::NotFound);
+        }
+    }
+
+    #[tokio::test]
+    async fn conforms() {
+        let storage = CachingStorageContainer::new(
+            MemoryStorageContainer::new(),
+            StorageCache::new(Default::default()),
+        );
+        crate::conformance::run(&storage, "conformance/").await;
+    }
+
+    #[tokio::test]
+    async fn serves_reads_from_memory() {
+        let memory = MemoryStorageContainer::new();
+        memory.set_content("a", body("abc")).await.unwrap();
+        memory.set_metadata("a", "meta".into()).await.unwrap();
+        let storage =
+            CachingStorageContainer::new(memory.clone(), StorageCache::new(Default::default()));
+
+        assert_eq!(
+            read_all(storage.get_content("a").await.unwrap()).await,
+            b"abc"
+        );
+        assert_eq!(storage.get_metadata("a").await.unwrap(), "meta");
+        assert_eq!(storage.cache().memory_size(), 7);
+
+        break_reads(&memory);
+        assert_eq!(
+            read_all(storage.get_content("a").await.unwrap()).await,
+            b"abc"
+        );
+        let range = storage.get_content_range("a", 1, Some(1)).await.unwrap();
+        assert_eq!(read_all(range).await, b"b");
+        assert_eq!(storage.get_metadata("a").await.unwrap(), "meta");
+    }
+
+    #[tokio::test]
+    async fn writes_invalidate_entries() {
+        let memory = MemoryStorageContainer::new();
+        memory.set_content("a", body("abc")).await.unwrap();
+        memory.set_metadata("a", "meta".into()).await.unwrap();
+        let storage =
+            CachingStorageContainer::new(memory.clone(), StorageCache::new(Default::default()));
+        read_all(storage.get_content("a").await.unwrap()).await;
+        storage.get_metadata("a").await.unwrap();
+
+        storage.set_content("a", body("def")).await.unwrap();
+        storage.set_metadata("a", "other".into()).await.unwrap();
+        assert_eq!(
+            read_all(storage.get_content("a").await.unwrap()).await,
+            b"def"
+        );
+        assert_eq!(storage.get_metadata("a").await.unwrap(), "other");
+
+        storage.delete("a").await.unwrap();
+        assert!(matches!(
+            storage.get_content("a").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn appends_invalidate_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = CachingStorageContainer::new(
+            FsStorageContainer::new(dir.path()),
+            StorageCache::new(Default::default()),
+        );
+        storage.set_content("a", body("abc")).await.unwrap();
+        read_all(storage.get_content("a").await.unwrap()).await;
+
+        let mut session = storage.open_append("a").await.unwrap();
+        session.append(Bytes::from("de")).await.unwrap();
+        assert_eq!(
+            read_all(storage.get_content("a").await.unwrap()).await,
+            b"de"
+        );
+        session.append(Bytes::from("f")).await.unwrap();
+        session.seal().await.unwrap();
+        assert_eq!(
+            read_all(storage.get_content("a").await.unwrap()).await,
+            b"def"
+        );
+    }
+
+    #[tokio::test]
+    async fn drops_fills_that_race_with_writes() {
+        let memory = MemoryStorageContainer::new();
+        memory.set_content("a", body("abc")).await.unwrap();
+        let storage =
+            CachingStorageContainer::new(memory.clone(), StorageCache::new(Default::default()));
+        let stale = storage.get_content("a").await.unwrap();
+        storage.set_content("a", body("def")).await.unwrap();
+        assert_eq!(read_all(stale).await, b"abc");
+        assert_eq!(storage.cache().memory_size(), 0);
+    }
+
+    #[tokio::test]
+    async fn keeps_fills_of_other_keys() {
+        let memory = MemoryStorageContainer::new();
+        memory.set_content("a", body("abc")).await.unwrap();
+        let storage =
+            CachingStorageContainer::new(memory.clone(), StorageCache::new(Default::default()));
+        let fill = storage.get_content("a").await.unwrap();
+        storage.set_content("b", body("def")).await.unwrap();
+        assert_eq!(read_all(fill).await, b"abc");
+        assert_eq!(storage.cache().memory_size(), 3);
+    }
+
+    #[tokio::test]
+    async fn drops_disk_writes_that_race_with_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = MemoryStorageContainer::new();
+        memory.set_content("a", body("abc")).await.unwrap();
+        let policy = CachePolicy {
+            memory_capacity: 0,
+            disk_root: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+        let storage = CachingStorageContainer::new(memory.clone(), StorageCache::new(policy));
+        // the fill's disk write is spawned, but doesn't run before the write.
+        read_all(storage.get_content("a").await.unwrap()).await;
+        storage.set_content("a", body("def")).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert_eq!(storage.cache().disk_size(), 0);
+        assert!(!dir.path().join(DISK_DIR).join("a").exists());
+        assert!(storage.cache().state.lock().unwrap().pending.is_empty());
+        assert_eq!(
+            read_all(storage.get_content("a").await.unwrap()).await,
+            b"def"
+        );
+    }
+
+    #[tokio::test]
+    async fn expires_entries_after_their_ttl() {
+        let memory = MemoryStorageContainer::new();
+        memory.set_content("a.m3u8", body("old")).await.unwrap();
+        let policy = CachePolicy {
+            ttls: vec![(".m3u8".into(), Some(Duration::from_millis(20)))],
+            ..Default::default()
+        };
+        let storage = CachingStorageContainer::new(memory.clone(), StorageCache::new(policy));
+        read_all(storage.get_content("a.m3u8").await.unwrap()).await;
+
+        // written behind the cache's back.
+        memory.set_content("a.m3u8", body("new")).await.unwrap();
+        assert_eq!(
+            read_all(storage.get_content("a.m3u8").await.unwrap()).await,
+            b"old"
+        );
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        assert_eq!(
+            read_all(storage.get_content("a.m3u8").await.unwrap()).await,
+            b"new"
+        );
+    }
+
+    #[tokio::test]
+    async fn evicts_least_recently_used_entries() {
+        let memory = MemoryStorageContainer::new();
+        for path in ["a", "b", "c"] {
+            memory.set_content(path, body("0123456789")).await.unwrap();
+        }
+        let policy = CachePolicy {
+            memory_capacity: 25,
+            max_object_size: 10,
+            ..Default::default()
+        };
+        let storage = CachingStorageContainer::new(memory.clone(), StorageCache::new(policy));
+        for path in ["a", "b", "a", "c"] {
+            read_all(storage.get_content(path).await.unwrap()).await;
+        }
+        assert_eq!(storage.cache().memory_size(), 20);
+        assert!(storage.cache().get_content("a").is_some());
+        assert!(storage.cache().get_content("b").is_none());
+
+        memory.set_content("d", body("0123456789a")).await.unwrap();
+        read_all(storage.get_content("d").await.unwrap()).await;
+        assert!(storage.cache().get_content("d").is_none());
+    }
+
+    #[tokio::test]
+    async fn starts_with_an_empty_disk_tier() {
+        let dir = tempfile::tempdir().unwrap();
+        let disk = dir.path().join(DISK_DIR);
+        std::fs::create_dir_all(disk.join("account/.meta/a")).unwrap();
+        std::fs::write(disk.join("account/a"), "left over").unwrap();
+        std::fs::write(dir.path().join("other"), "not ours").unwrap();
+        let policy = CachePolicy {
+            disk_root: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+        let cache = StorageCache::new(policy);
+        assert_eq!(cache.disk_size(), 0);
+        assert!(!disk.exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("other")).unwrap(),
+            "not ours"
+        );
+    }
+
+    #[tokio::test]
+    async fn keeps_evicted_content_on_disk() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = MemoryStorageContainer::new();
+        memory.set_content("a", body("abc")).await.unwrap();
+        let policy = CachePolicy {
+            memory_capacity: 0,
+            disk_root: Some(dir.path().to_owned()),
+            ..Default::default()
+        };
+        let cache = StorageCache::new(policy);
+        let storage =
+            CachingStorageContainer::with_namespace(memory.clone(), cache, "account/video");
+        read_all(storage.get_content("a").await.unwrap()).await;
+        while storage.cache().disk_size() == 0 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        assert!(dir.path().join(DISK_DIR).join("account/video/a").exists());
+
+        break_reads(&memory);
+        assert_eq!(
+            read_all(storage.get_content("a").await.unwrap()).await,
+            b"abc"
+        );
+
+        storage.delete_prefix("").await.unwrap();
+        assert_eq!(storage.cache().disk_size(), 0);
+        assert!(!dir.path().join(DISK_DIR).join("account/video/a").exists());
+    }
+}
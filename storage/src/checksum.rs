@@ -0,0 +1,378 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use crc::{Crc, CRC_64_NVME};
+use futures::Stream;
+use md5::{Digest, Md5};
+
+use crate::{
+    AppendSession, BlockUpload, Lease, ListStream, ObjectInfo, ObjectProperties, Permissions,
+    StorageContainer, StorageError, StreamType, Url, WatchStream, WriteCondition,
+};
+
+/// The CRC64 variant Azure blob storage and S3 (`CRC64NVME`) compute.
+static CRC64: Crc<u64> = Crc::<u64>::new(&CRC_64_NVME);
+
+/// Hashes of the content of an object. Backends keep whichever they can
+/// store next to the content, so either may be missing.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Checksums {
+    pub md5: Option<[u8; 16]>,
+    pub crc64: Option<u64>,
+}
+
+impl Checksums {
+    pub fn of(content: &[u8]) -> Self {
+        let mut hasher = ChecksumHasher::new();
+        hasher.update(content);
+        hasher.finish()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.md5.is_none() && self.crc64.is_none()
+    }
+
+    /// The MD5 as in a `Content-MD5` header.
+    pub fn md5_base64(&self) -> Option<String> {
+        self.md5.map(|md5| STANDARD.encode(md5))
+    }
+
+    /// The CRC64 as base64 of its big-endian bytes, as S3 reports it.
+    pub fn crc64_base64(&self) -> Option<String> {
+        self.crc64.map(|crc64| STANDARD.encode(crc64.to_be_bytes()))
+    }
+
+    /// Parses the encodings of [`Checksums::md5_base64`] and
+    /// [`Checksums::crc64_base64`], ignoring malformed values.
+    pub fn from_base64(md5: Option<&str>, crc64: Option<&str>) -> Self {
+        let decode = |value: Option<&str>| value.and_then(|v| STANDARD.decode(v).ok());
+        Self {
+            md5: decode(md5).and_then(|md5| md5.try_into().ok()),
+            crc64: decode(crc64)
+                .and_then(|crc64| crc64.try_into().ok())
+                .map(u64::from_be_bytes),
+        }
+    }
+
+    /// Whether every hash both sides have agrees.
+    pub fn matches(&self, other: &Checksums) -> bool {
+        fn agree<T: PartialEq>(a: Option<T>, b: Option<T>) -> bool {
+            match (a, b) {
+                (Some(a), Some(b)) => a == b,
+                _ => true,
+            }
+        }
+        agree(self.md5, other.md5) && agree(self.crc64, other.crc64)
+    }
+}
+
+/// Computes [`Checksums`] over content that arrives in chunks.
+#[derive(Clone)]
+pub struct ChecksumHasher {
+    md5: Md5,
+    crc64: crc::Digest<'static, u64>,
+}
+
+impl Default for ChecksumHasher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ChecksumHasher {
+    pub fn new() -> Self {
+        Self {
+            md5: Md5::new(),
+            crc64: CRC64.digest(),
+        }
+    }
+
+    pub fn update(&mut self, chunk: &[u8]) {
+        self.md5.update(chunk);
+        self.crc64.update(chunk);
+    }
+
+    pub fn finish(self) -> Checksums {
+        Checksums {
+            md5: Some(self.md5.finalize().into()),
+            crc64: Some(self.crc64.finalize()),
+        }
+    }
+}
+
+/// Passes `content` through and fails with
+/// [`StorageError::IntegrityMismatch`] at its end if it doesn't hash to
+/// `expected`.
+pub fn verify_stream(content: StreamType, expected: Checksums) -> StreamType {
+    Box::pin(VerifyStream {
+        content,
+        hasher: Some(ChecksumHasher::new()),
+        expected,
+    })
+}
+
+struct VerifyStream {
+    content: StreamType,
+    /// `None` once the content was checked.
+    hasher: Option<ChecksumHasher>,
+    expected: Checksums,
+}
+
+impl Stream for VerifyStream {
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let Some(hasher) = &mut this.hasher else {
+            return Poll::Ready(None);
+        };
+        match this.content.as_mut().poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                hasher.update(&chunk);
+                Poll::Ready(Some(Ok(chunk)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                this.hasher = None;
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                let actual = this.hasher.take().unwrap().finish();
+                if actual.matches(&this.expected) {
+                    Poll::Ready(None)
+                } else {
+                    Poll::Ready(Some(Err(StorageError::IntegrityMismatch)))
+                }
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Wraps another container and checks full reads against the checksums the
+/// backend stored on write. Objects without checksums are read unchecked, as
+/// are ranges and tails, which the checksums don't cover.
+///
+/// The checksums are looked up before the content is read, so an object that
+/// is replaced in between fails the check as well.
+pub struct VerifyingStorageContainer<S> {
+    inner: S,
+}
+
+impl<S> VerifyingStorageContainer<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<S> StorageContainer for VerifyingStorageContainer<S>
+where
+    S: StorageContainer + Send + Sync,
+{
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        let checksums = match self.inner.stat(path).await {
+            Ok(properties) => properties.checksums,
+            Err(StorageError::Unsupported) => Checksums::default(),
+            Err(e) => return Err(e),
+        };
+        let content = self.inner.get_content(path).await?;
+        if checksums.is_empty() {
+            return Ok(content);
+        }
+        Ok(verify_stream(content, checksums))
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        self.inner.get_content_range(path, offset, length).await
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        self.inner.get_metadata(path).await
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        self.inner.set_content(path, content).await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        self.inner.set_metadata(path, metadata).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.inner.exists(path).await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.inner.set_content_if(path, content, condition).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.inner.set_metadata_if(path, metadata, condition).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        self.inner.stat(path).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        self.inner.list(prefix).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.inner.delete(path).await
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<usize, StorageError> {
+        self.inner.delete_prefix(prefix).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner.copy(from, to).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.inner.rename(from, to).await
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        self.inner.open_append(path).await
+    }
+
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.inner.get_content_tail(path).await
+    }
+
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        self.inner.start_block_upload(path).await
+    }
+
+    async fn acquire_lease(&self, path: &str, ttl: Duration) -> Result<Lease, StorageError> {
+        self.inner.acquire_lease(path, ttl).await
+    }
+
+    async fn renew_lease(&self, lease: &Lease) -> Result<(), StorageError> {
+        self.inner.renew_lease(lease).await
+    }
+
+    async fn release_lease(&self, lease: Lease) -> Result<(), StorageError> {
+        self.inner.release_lease(lease).await
+    }
+
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        self.inner.presign(path, permissions, expiry).await
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        self.inner.watch(prefix).await
+    }
+
+    async fn list_all(&self, prefix: &str) -> Result<Vec<ObjectInfo>, StorageError> {
+        self.inner.list_all(prefix).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FsStorageContainer, MemoryStorageContainer};
+    use futures::TryStreamExt;
+
+    fn body(content: &'static str) -> StreamType {
+        Box::pin(futures::stream::iter([Ok(Bytes::from(content))]))
+    }
+
+    #[test]
+    fn computes_standard_checksums() {
+        let checksums = Checksums::of(b"123456789");
+        assert_eq!(checksums.crc64, Some(0xae8b14860a799888));
+        assert_eq!(
+            checksums.md5_base64().as_deref(),
+            Some("JfnnlDI7RTiF9RgfG2JNCw==")
+        );
+        let parsed = Checksums::from_base64(
+            checksums.md5_base64().as_deref(),
+            checksums.crc64_base64().as_deref(),
+        );
+        assert_eq!(parsed, checksums);
+    }
+
+    #[test]
+    fn matches_the_hashes_both_sides_have() {
+        let full = Checksums::of(b"abc");
+        let crc_only = Checksums { md5: None, ..full };
+        assert!(full.matches(&crc_only));
+        assert!(!Checksums::of(b"abd").matches(&crc_only));
+        assert!(full.matches(&Checksums::default()));
+    }
+
+    #[tokio::test]
+    async fn stores_checksums_on_write() {
+        let storage = MemoryStorageContainer::new();
+        storage.set_content("a", body("hello")).await.unwrap();
+        let properties = storage.stat("a").await.unwrap();
+        assert_eq!(properties.checksums, Checksums::of(b"hello"));
+
+        let mut session = storage.open_append("b").await.unwrap();
+        session.append(Bytes::from("hel")).await.unwrap();
+        assert!(storage.stat("b").await.unwrap().checksums.is_empty());
+        session.append(Bytes::from("lo")).await.unwrap();
+        session.seal().await.unwrap();
+        let properties = storage.stat("b").await.unwrap();
+        assert_eq!(properties.checksums, Checksums::of(b"hello"));
+    }
+
+    #[tokio::test]
+    async fn detects_corrupted_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = VerifyingStorageContainer::new(FsStorageContainer::new(dir.path()));
+        storage.set_content("a.ts", body("segment")).await.unwrap();
+        let content: Vec<Bytes> = storage
+            .get_content("a.ts")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(content.concat(), b"segment");
+
+        // flip a byte behind the container's back.
+        std::fs::write(dir.path().join("a.ts"), "segmenT").unwrap();
+        let result: Result<Vec<Bytes>, _> = storage
+            .get_content("a.ts")
+            .await
+            .unwrap()
+            .try_collect()
+            .await;
+        assert!(matches!(result, Err(StorageError::IntegrityMismatch)));
+    }
+}
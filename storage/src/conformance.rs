@@ -0,0 +1,242 @@
+//! Checks that a [`StorageContainer`] behaves the way the media servers
+//! expect, for backends to run from their own tests:
+//!
+//! ```ignore
+//! #[tokio::test]
+//! async fn conforms() {
+//!     storage::conformance::run(&MyStorageContainer::new(), "conformance/").await;
+//! }
+//! ```
+//!
+//! The checks panic on the first deviation. Each one writes below the given
+//! prefix only, so live backends can share a container with other tests.
+//! Operations a backend reports as [`StorageError::Unsupported`] are skipped.
+
+use bytes::Bytes;
+use futures::{StreamExt, TryStreamExt};
+
+use crate::{ChecksumHasher, StorageContainer, StorageError, StreamType};
+
+/// The size of the body of [`large_bodies`], well above typical block and
+/// buffer sizes.
+pub const LARGE_BODY_SIZE: usize = 20 * 1024 * 1024;
+
+/// Runs every check of the suite against `storage`.
+pub async fn run<S>(storage: &S, prefix: &str)
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    round_trip(storage, prefix).await;
+    not_found(storage, prefix).await;
+    exists(storage, prefix).await;
+    large_bodies(storage, prefix).await;
+    concurrent_writers(storage, prefix).await;
+}
+
+fn body(content: impl Into<Bytes>) -> StreamType {
+    Box::pin(futures::stream::iter([Ok(content.into())]))
+}
+
+/// Streams `content` in chunks and yields between them, so the writes of
+/// concurrent writers interleave.
+fn chunked_body(content: impl Into<Bytes>) -> StreamType {
+    const CHUNK_SIZE: usize = 16 * 1024;
+    let content = content.into();
+    let chunks: Vec<_> = (0..content.len())
+        .step_by(CHUNK_SIZE)
+        .map(|start| content.slice(start..content.len().min(start + CHUNK_SIZE)))
+        .collect();
+    Box::pin(futures::stream::iter(chunks).then(|chunk| async move {
+        tokio::task::yield_now().await;
+        Ok(chunk)
+    }))
+}
+
+async fn read<S>(storage: &S, path: &str) -> Result<Vec<u8>, StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let chunks: Vec<Bytes> = storage.get_content(path).await?.try_collect().await?;
+    Ok(chunks.concat())
+}
+
+/// Content and metadata read back as written, and writes replace them.
+pub async fn round_trip<S>(storage: &S, prefix: &str)
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let path = format!("{prefix}round-trip/segment0.ts");
+    storage.set_content(&path, body("first")).await.unwrap();
+    assert_eq!(read(storage, &path).await.unwrap(), b"first");
+    storage.set_content(&path, body("second")).await.unwrap();
+    assert_eq!(
+        read(storage, &path).await.unwrap(),
+        b"second",
+        "writes replace the content"
+    );
+
+    let empty = format!("{prefix}round-trip/empty.ts");
+    storage
+        .set_content(&empty, body(Bytes::new()))
+        .await
+        .unwrap();
+    assert!(read(storage, &empty).await.unwrap().is_empty());
+
+    storage
+        .set_metadata(&path, "{\"duration\":10}".to_owned())
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get_metadata(&path).await.unwrap(),
+        "{\"duration\":10}"
+    );
+    storage
+        .set_metadata(&path, "{\"duration\":20}".to_owned())
+        .await
+        .unwrap();
+    assert_eq!(
+        storage.get_metadata(&path).await.unwrap(),
+        "{\"duration\":20}"
+    );
+    assert_eq!(
+        read(storage, &path).await.unwrap(),
+        b"second",
+        "metadata writes keep the content"
+    );
+}
+
+/// Objects that were never written, or were deleted, are
+/// [`StorageError::NotFound`] rather than empty or failing otherwise. Their
+/// metadata can't be set either.
+pub async fn not_found<S>(storage: &S, prefix: &str)
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let missing = format!("{prefix}not-found/missing.ts");
+    assert!(matches!(
+        storage.get_content(&missing).await,
+        Err(StorageError::NotFound)
+    ));
+    assert!(matches!(
+        storage.get_metadata(&missing).await,
+        Err(StorageError::NotFound)
+    ));
+    assert!(matches!(
+        storage.stat(&missing).await,
+        Err(StorageError::NotFound | StorageError::Unsupported)
+    ));
+    assert!(matches!(
+        storage.delete(&missing).await,
+        Err(StorageError::NotFound | StorageError::Unsupported)
+    ));
+    assert!(
+        matches!(
+            storage.set_metadata(&missing, "{}".to_owned()).await,
+            Err(StorageError::NotFound)
+        ),
+        "metadata was set without an object"
+    );
+    assert!(!storage.exists(&missing).await.unwrap());
+
+    let path = format!("{prefix}not-found/deleted.ts");
+    storage.set_content(&path, body("content")).await.unwrap();
+    match storage.delete(&path).await {
+        Ok(()) => assert!(matches!(
+            storage.get_content(&path).await,
+            Err(StorageError::NotFound)
+        )),
+        Err(StorageError::Unsupported) => {}
+        Err(e) => panic!("deleting {path} failed: {e:?}"),
+    }
+}
+
+/// `exists` follows writes and deletes.
+pub async fn exists<S>(storage: &S, prefix: &str)
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let path = format!("{prefix}exists/segment0.ts");
+    assert!(!storage.exists(&path).await.unwrap());
+    storage.set_content(&path, body("content")).await.unwrap();
+    assert!(storage.exists(&path).await.unwrap());
+    assert!(
+        !storage
+            .exists(&format!("{prefix}exists/segment"))
+            .await
+            .unwrap(),
+        "a prefix of a name is not an object"
+    );
+    match storage.delete(&path).await {
+        Ok(()) => assert!(!storage.exists(&path).await.unwrap()),
+        Err(StorageError::Unsupported) => {}
+        Err(e) => panic!("deleting {path} failed: {e:?}"),
+    }
+}
+
+/// A body of [`LARGE_BODY_SIZE`] streamed in small chunks reads back intact.
+pub async fn large_bodies<S>(storage: &S, prefix: &str)
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    const CHUNK_SIZE: usize = 64 * 1024;
+    let chunk = |index: usize| {
+        let chunk: Vec<u8> = (0..CHUNK_SIZE).map(|i| (i * 31 + index) as u8).collect();
+        Bytes::from(chunk)
+    };
+    let mut hasher = ChecksumHasher::new();
+    for index in 0..LARGE_BODY_SIZE / CHUNK_SIZE {
+        hasher.update(&chunk(index));
+    }
+    let expected = hasher.finish();
+
+    let path = format!("{prefix}large/segment0.ts");
+    let content = futures::stream::iter(0..LARGE_BODY_SIZE / CHUNK_SIZE).map(move |i| Ok(chunk(i)));
+    storage.set_content(&path, Box::pin(content)).await.unwrap();
+
+    let mut hasher = ChecksumHasher::new();
+    let mut size = 0;
+    let mut content = storage.get_content(&path).await.unwrap();
+    while let Some(chunk) = content.try_next().await.unwrap() {
+        size += chunk.len();
+        hasher.update(&chunk);
+    }
+    assert_eq!(size, LARGE_BODY_SIZE);
+    assert_eq!(hasher.finish(), expected, "the content came back changed");
+}
+
+/// Concurrent writes to distinct objects all land, and concurrent writes to
+/// the same object leave exactly one of them, never a mix. The bodies arrive
+/// in many chunks, so the writes overlap.
+pub async fn concurrent_writers<S>(storage: &S, prefix: &str)
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    const WRITERS: usize = 8;
+    let content = |writer: usize| vec![b'a' + writer as u8; 256 * 1024];
+
+    let distinct = (0..WRITERS).map(|writer| async move {
+        let path = format!("{prefix}concurrent/segment{writer}.ts");
+        storage
+            .set_content(&path, chunked_body(content(writer)))
+            .await
+    });
+    for result in futures::future::join_all(distinct).await {
+        result.unwrap();
+    }
+    for writer in 0..WRITERS {
+        let path = format!("{prefix}concurrent/segment{writer}.ts");
+        assert_eq!(read(storage, &path).await.unwrap(), content(writer));
+    }
+
+    let path = format!("{prefix}concurrent/shared.ts");
+    let shared =
+        (0..WRITERS).map(|writer| storage.set_content(&path, chunked_body(content(writer))));
+    for result in futures::future::join_all(shared).await {
+        result.unwrap();
+    }
+    let written = read(storage, &path).await.unwrap();
+    assert!(
+        (0..WRITERS).any(|writer| written == content(writer)),
+        "concurrent writes to {path} were mixed"
+    );
+}
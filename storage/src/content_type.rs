@@ -0,0 +1,15 @@
+/// Guesses the content type of a media file from its extension.
+pub fn guess_content_type(path: &str) -> Option<&'static str> {
+    let extension = path.rsplit_once('.')?.1;
+    let content_type = match extension.to_ascii_lowercase().as_str() {
+        "m3u8" => "application/vnd.apple.mpegurl",
+        "mpd" => "application/dash+xml",
+        "ts" => "video/mp2t",
+        "mp4" | "m4s" | "m4v" | "cmfv" => "video/mp4",
+        "m4a" | "cmfa" => "audio/mp4",
+        "vtt" => "text/vtt",
+        "json" => "application/json",
+        _ => return None,
+    };
+    Some(content_type)
+}
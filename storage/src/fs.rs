@@ -0,0 +1,1321 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use async_trait::async_trait;
+use futures::StreamExt;
+use notify::{
+    event::{ModifyKind, RenameMode},
+    EventKind, RecommendedWatcher, RecursiveMode, Watcher,
+};
+use tokio::{
+    fs::{self, File},
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom},
+    sync::{mpsc, Mutex},
+};
+use tokio_util::io::ReaderStream;
+
+use crate::{
+    guess_content_type, stream::paginate, AppendSession, ChangeEvent, ChangeKind, ChecksumHasher,
+    Checksums, ListStream, ObjectInfo, ObjectProperties, Permissions, StorageContainer,
+    StorageError, StreamType, Url, UrlSigner, WatchStream, WriteCondition,
+};
+
+/// The hidden directory below the root that keeps the sidecars of every
+/// object, so they never share a name with an object.
+const META_DIR: &str = ".meta";
+const METADATA_SIDECAR: &str = "metadata";
+const ETAG_SIDECAR: &str = "etag";
+const APPENDING_SIDECAR: &str = "appending";
+const CHECKSUMS_SIDECAR: &str = "checksums";
+const TEMP_SUFFIX: &str = ".partial";
+const READ_CHUNK_SIZE: usize = 0x10000;
+/// How often tailing readers check for appended bytes.
+const TAIL_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+static TEMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// A storage container backed by a directory on the local file system.
+///
+/// The content of `path` is kept in the file `path` below the root, and
+/// everything else in sidecar files in the directory `.meta/path`: the
+/// metadata in `metadata`, the ETag in `etag` and the content hashes in
+/// `checksums`. Objects open for appending are marked by an `appending`
+/// sidecar until they are sealed. Names below `.meta/` are reserved.
+///
+/// Listing walks every directory below the prefix before the first page is
+/// returned, which suits trees of moderate size.
+///
+/// Conditional writes are only atomic among the writes made through the same
+/// container, as they are serialized by a lock in memory. The exception is
+/// creating content with [`WriteCondition::IfNoneMatch`], which holds across
+/// processes too. Other processes, or other containers for the same root,
+/// can make an `IfMatch` write or an `IfNoneMatch` metadata write lose an
+/// update.
+pub struct FsStorageContainer {
+    root: PathBuf,
+    /// Serializes committing writes so conditional writes are atomic within
+    /// this container.
+    lock: Mutex<()>,
+    /// The URL of a storage proxy serving `root` and the key it checks
+    /// presigned URLs with.
+    presign: Option<(Url, UrlSigner)>,
+}
+
+impl FsStorageContainer {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self {
+            root: root.into(),
+            lock: Mutex::new(()),
+            presign: None,
+        }
+    }
+
+    /// Enables [`StorageContainer::presign`] with URLs below `base`, a
+    /// storage proxy serving this directory that checks them with `signer`.
+    pub fn with_presign(mut self, mut base: Url, signer: UrlSigner) -> Self {
+        if !base.path().ends_with('/') {
+            base.set_path(&format!("{}/", base.path()));
+        }
+        self.presign = Some((base, signer));
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn from_io_error(error: std::io::Error) -> StorageError {
+        match error.kind() {
+            ErrorKind::NotFound => StorageError::NotFound,
+            _ => StorageError::Other(error),
+        }
+    }
+
+    /// Checks that `path` stays below the root and out of the sidecars.
+    fn get_relative_path(path: &str) -> Result<&Path, StorageError> {
+        let relative = Path::new(path.trim_start_matches('/'));
+        let valid = relative
+            .components()
+            .all(|c| matches!(c, Component::Normal(_) | Component::CurDir));
+        let reserved = relative
+            .components()
+            .find(|c| *c != Component::CurDir)
+            .is_some_and(|c| c.as_os_str() == META_DIR);
+        if !valid || reserved {
+            return Err(StorageError::Other(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                format!("invalid path {path}"),
+            )));
+        }
+        Ok(relative)
+    }
+
+    fn get_path(&self, path: &str) -> Result<PathBuf, StorageError> {
+        Ok(self.root.join(Self::get_relative_path(path)?))
+    }
+
+    fn get_sidecar_dir(&self, path: &str) -> Result<PathBuf, StorageError> {
+        Ok(self
+            .root
+            .join(META_DIR)
+            .join(Self::get_relative_path(path)?))
+    }
+
+    fn get_sidecar_path(&self, path: &str, name: &str) -> Result<PathBuf, StorageError> {
+        Ok(self.get_sidecar_dir(path)?.join(name))
+    }
+
+    fn get_metadata_path(&self, path: &str) -> Result<PathBuf, StorageError> {
+        self.get_sidecar_path(path, METADATA_SIDECAR)
+    }
+
+    async fn open_file(&self, path: &str) -> Result<File, StorageError> {
+        let file = File::open(self.get_path(path)?)
+            .await
+            .map_err(Self::from_io_error)?;
+        if !file.metadata().await?.is_file() {
+            return Err(StorageError::NotFound);
+        }
+        Ok(file)
+    }
+
+    async fn read_metadata(&self, path: &str) -> Result<Option<String>, StorageError> {
+        match fs::read_to_string(self.get_metadata_path(path)?).await {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn read_checksums(&self, path: &str) -> Result<Checksums, StorageError> {
+        let text = match fs::read_to_string(self.get_sidecar_path(path, CHECKSUMS_SIDECAR)?).await
+        {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Checksums::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut md5 = None;
+        let mut crc64 = None;
+        for line in text.lines() {
+            match line.split_once('=') {
+                Some(("md5", value)) => md5 = Some(value),
+                Some(("crc64", value)) => crc64 = Some(value),
+                _ => {}
+            }
+        }
+        Ok(Checksums::from_base64(md5, crc64))
+    }
+
+    async fn write_checksums(file: &Path, checksums: &Checksums) -> Result<(), StorageError> {
+        let mut text = String::new();
+        if let Some(md5) = checksums.md5_base64() {
+            text.push_str(&format!("md5={md5}\n"));
+        }
+        if let Some(crc64) = checksums.crc64_base64() {
+            text.push_str(&format!("crc64={crc64}\n"));
+        }
+        Self::replace_file(file, text).await
+    }
+
+    fn nanos(time: std::io::Result<SystemTime>) -> u128 {
+        time.ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |d| d.as_nanos())
+    }
+
+    /// Reads the ETag recorded by the last write. Files written by other
+    /// means fall back to an ETag derived from their size and modified time.
+    async fn get_etag(&self, path: &str, info: &std::fs::Metadata) -> Result<String, StorageError> {
+        match fs::read_to_string(self.get_sidecar_path(path, ETAG_SIDECAR)?).await {
+            Ok(etag) => Ok(etag),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(format!(
+                "{:x}-{:x}",
+                info.len(),
+                Self::nanos(info.modified())
+            )),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Records a new ETag after the content or the metadata changed.
+    async fn update_etag(&self, path: &str) -> Result<(), StorageError> {
+        Self::write_etag(&self.get_sidecar_path(path, ETAG_SIDECAR)?).await
+    }
+
+    async fn write_etag(file: &Path) -> Result<(), StorageError> {
+        let etag = format!(
+            "{:x}-{:x}-{:x}",
+            std::process::id(),
+            Self::nanos(Ok(SystemTime::now())),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        Self::replace_file(file, etag).await
+    }
+
+    async fn current_etag(&self, path: &str) -> Result<Option<String>, StorageError> {
+        match fs::metadata(self.get_path(path)?).await {
+            Ok(info) if info.is_file() => Ok(Some(self.get_etag(path, &info).await?)),
+            Ok(_) => Ok(None),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn check_etag(&self, path: &str, etag: &str) -> Result<(), StorageError> {
+        match self.current_etag(path).await? {
+            Some(current) if current == etag => Ok(()),
+            _ => Err(StorageError::PreconditionFailed),
+        }
+    }
+
+    async fn write_content(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        let file = self.get_path(path)?;
+        Self::create_parent(&file).await?;
+
+        // write to a temporary file first so readers never see a partial object.
+        let temp = self.get_temp_path(path).await?;
+        let checksums = match Self::write_stream(&temp, content).await {
+            Ok(checksums) => checksums,
+            Err(e) => {
+                let _ = fs::remove_file(&temp).await;
+                return Err(e);
+            }
+        };
+
+        let _lock = self.lock.lock().await;
+        let result = match condition {
+            // a hard link fails if the target exists, even across processes.
+            Some(WriteCondition::IfNoneMatch) => match fs::hard_link(&temp, &file).await {
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    Err(StorageError::PreconditionFailed)
+                }
+                result => result.map_err(StorageError::from),
+            },
+            Some(WriteCondition::IfMatch(etag)) => match self.check_etag(path, &etag).await {
+                Ok(()) => fs::rename(&temp, &file).await.map_err(StorageError::from),
+                Err(e) => Err(e),
+            },
+            None => fs::rename(&temp, &file).await.map_err(StorageError::from),
+        };
+        let _ = fs::remove_file(&temp).await;
+        result?;
+        Self::write_checksums(&self.get_sidecar_path(path, CHECKSUMS_SIDECAR)?, &checksums).await?;
+        self.update_etag(path).await
+    }
+
+    async fn write_metadata(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        let file = self.get_metadata_path(path)?;
+        Self::create_parent(&file).await?;
+
+        let _lock = self.lock.lock().await;
+        if !self.exists(path).await? {
+            return Err(StorageError::NotFound);
+        }
+        match condition {
+            Some(WriteCondition::IfNoneMatch) if fs::try_exists(&file).await? => {
+                return Err(StorageError::PreconditionFailed);
+            }
+            Some(WriteCondition::IfMatch(etag)) => self.check_etag(path, &etag).await?,
+            _ => {}
+        }
+        Self::replace_file(&file, metadata).await?;
+        self.update_etag(path).await
+    }
+
+    /// Writes a sidecar through a temporary file, so readers see either the
+    /// old or the new contents.
+    async fn replace_file(file: &Path, contents: impl AsRef<[u8]>) -> Result<(), StorageError> {
+        let temp = Self::temp_path_for(file);
+        let result = match fs::write(&temp, contents).await {
+            Ok(()) => fs::rename(&temp, file).await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            let _ = fs::remove_file(&temp).await;
+        }
+        Ok(result?)
+    }
+
+    /// Removes the sidecars of `path` and their directory, which would
+    /// otherwise stand in the way of an object named like one of them.
+    async fn remove_sidecars(&self, path: &str) -> Result<(), StorageError> {
+        for name in [
+            METADATA_SIDECAR,
+            APPENDING_SIDECAR,
+            CHECKSUMS_SIDECAR,
+            ETAG_SIDECAR,
+        ] {
+            Self::remove_if_exists(&self.get_sidecar_path(path, name)?).await?;
+        }
+        // still holds the temporary files of writes in flight.
+        let _ = fs::remove_dir(self.get_sidecar_dir(path)?).await;
+        Ok(())
+    }
+
+    async fn remove_if_exists(file: &Path) -> Result<(), StorageError> {
+        match fs::remove_file(file).await {
+            Err(e) if e.kind() != ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn create_parent(file: &Path) -> Result<(), StorageError> {
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+        Ok(())
+    }
+
+    /// A temporary file next to the sidecars of `path` to write its content
+    /// to, on the same file system as the content.
+    async fn get_temp_path(&self, path: &str) -> Result<PathBuf, StorageError> {
+        let dir = self.get_sidecar_dir(path)?;
+        fs::create_dir_all(&dir).await?;
+        Ok(Self::temp_path_for(&dir.join("content")))
+    }
+
+    fn temp_path_for(file: &Path) -> PathBuf {
+        let mut name = file.as_os_str().to_owned();
+        name.push(format!(
+            ".{}.{}{}",
+            std::process::id(),
+            TEMP_COUNTER.fetch_add(1, Ordering::Relaxed),
+            TEMP_SUFFIX
+        ));
+        name.into()
+    }
+
+    /// Walks `dir` and collects the objects whose name starts with `prefix`,
+    /// skipping the sidecars.
+    fn walk(
+        dir: &Path,
+        name: &str,
+        prefix: &str,
+        objects: &mut Vec<ObjectInfo>,
+    ) -> std::io::Result<()> {
+        let entries = match std::fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let Some(file_name) = entry.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            if name.is_empty() && file_name == META_DIR {
+                continue;
+            }
+            let entry_name = format!("{name}{file_name}");
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                let dir_name = format!("{entry_name}/");
+                if dir_name.starts_with(prefix) || prefix.starts_with(&dir_name) {
+                    Self::walk(&entry.path(), &dir_name, prefix, objects)?;
+                }
+            } else if entry_name.starts_with(prefix) {
+                objects.push(ObjectInfo {
+                    name: entry_name,
+                    size: metadata.len(),
+                    last_modified: metadata.modified().ok(),
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `file` to its end, and then keeps reading what is appended for as
+    /// long as the `marker` sidecar exists.
+    fn tail(file: File, marker: PathBuf) -> StreamType {
+        let stream = futures::stream::unfold(Some((file, false)), move |state| {
+            let marker = marker.clone();
+            async move {
+                let (mut file, mut sealed) = state?;
+                loop {
+                    let mut chunk = vec![0; READ_CHUNK_SIZE];
+                    match file.read(&mut chunk).await {
+                        Ok(0) if sealed => return None,
+                        Ok(0) => {}
+                        Ok(n) => {
+                            chunk.truncate(n);
+                            return Some((Ok(chunk.into()), Some((file, sealed))));
+                        }
+                        Err(e) => return Some((Err(e.into()), None)),
+                    }
+                    match fs::try_exists(&marker).await {
+                        // read once more, bytes may have been appended right
+                        // before sealing.
+                        Ok(false) => sealed = true,
+                        Ok(true) => tokio::time::sleep(TAIL_POLL_INTERVAL).await,
+                        Err(e) => return Some((Err(e.into()), None)),
+                    }
+                }
+            }
+        });
+        Box::pin(stream)
+    }
+
+    async fn list_objects(&self, prefix: &str) -> Result<Vec<ObjectInfo>, StorageError> {
+        // validates the prefix the same way as any other path.
+        self.get_path(prefix)?;
+        let root = self.root.clone();
+        let prefix = prefix.trim_start_matches('/').to_owned();
+        tokio::task::spawn_blocking(move || {
+            let mut objects = Vec::new();
+            Self::walk(&root, "", &prefix, &mut objects).map(|_| objects)
+        })
+        .await
+        .map_err(|e| StorageError::Other(e.into()))?
+        .map_err(StorageError::from)
+    }
+
+    async fn write_stream(file: &Path, mut content: StreamType) -> Result<Checksums, StorageError> {
+        let mut writer = File::create(file).await?;
+        let mut hasher = ChecksumHasher::new();
+        while let Some(chunk) = content.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        Ok(hasher.finish())
+    }
+}
+
+#[async_trait]
+impl StorageContainer for FsStorageContainer {
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        let file = self.open_file(path).await?;
+        let stream = ReaderStream::with_capacity(file, READ_CHUNK_SIZE)
+            .map(|r| r.map_err(StorageError::from));
+        Ok(Box::pin(stream))
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        let mut file = self.open_file(path).await?;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let reader = file.take(length.unwrap_or(u64::MAX));
+        let stream = ReaderStream::with_capacity(reader, READ_CHUNK_SIZE)
+            .map(|r| r.map_err(StorageError::from));
+        Ok(Box::pin(stream))
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        fs::read_to_string(self.get_metadata_path(path)?)
+            .await
+            .map_err(Self::from_io_error)
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        self.write_content(path, content, None).await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        self.write_metadata(path, metadata, None).await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.write_content(path, content, Some(condition)).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.write_metadata(path, metadata, Some(condition)).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        // paths outside the root can't hold objects.
+        let Ok(file) = self.get_path(path) else {
+            return Ok(false);
+        };
+        match fs::metadata(file).await {
+            Ok(metadata) => Ok(metadata.is_file()),
+            Err(e) if matches!(e.kind(), ErrorKind::NotFound | ErrorKind::NotADirectory) => {
+                Ok(false)
+            }
+            Err(e) => Err(Self::from_io_error(e)),
+        }
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        let info = fs::metadata(self.get_path(path)?)
+            .await
+            .map_err(Self::from_io_error)?;
+        if !info.is_file() {
+            return Err(StorageError::NotFound);
+        }
+        Ok(ObjectProperties {
+            content_length: info.len(),
+            etag: Some(self.get_etag(path, &info).await?),
+            content_type: guess_content_type(path).map(str::to_owned),
+            last_modified: info.modified().ok(),
+            metadata: self.read_metadata(path).await?,
+            checksums: self.read_checksums(path).await?,
+        })
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let file = self.get_path(path)?;
+        let _lock = self.lock.lock().await;
+        fs::remove_file(&file).await.map_err(Self::from_io_error)?;
+        self.remove_sidecars(path).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let source = self.get_path(from)?;
+        let target = self.get_path(to)?;
+        Self::create_parent(&target).await?;
+
+        let temp = self.get_temp_path(to).await?;
+        if let Err(e) = fs::copy(&source, &temp).await {
+            let _ = fs::remove_file(&temp).await;
+            return Err(Self::from_io_error(e));
+        }
+        let metadata = self.read_metadata(from).await?;
+        let checksums = self.read_checksums(from).await?;
+
+        let _lock = self.lock.lock().await;
+        let result = fs::rename(&temp, &target).await;
+        let _ = fs::remove_file(&temp).await;
+        result?;
+        let metadata_path = self.get_metadata_path(to)?;
+        match metadata {
+            Some(metadata) => Self::replace_file(&metadata_path, metadata).await?,
+            None => Self::remove_if_exists(&metadata_path).await?,
+        }
+        let checksums_path = self.get_sidecar_path(to, CHECKSUMS_SIDECAR)?;
+        if checksums.is_empty() {
+            Self::remove_if_exists(&checksums_path).await?;
+        } else {
+            Self::write_checksums(&checksums_path, &checksums).await?;
+        }
+        self.update_etag(to).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let source = self.get_path(from)?;
+        let target = self.get_path(to)?;
+        Self::create_parent(&target).await?;
+        fs::create_dir_all(self.get_sidecar_dir(to)?).await?;
+
+        let _lock = self.lock.lock().await;
+        fs::rename(&source, &target)
+            .await
+            .map_err(Self::from_io_error)?;
+        let metadata_path = self.get_metadata_path(to)?;
+        match fs::rename(self.get_metadata_path(from)?, &metadata_path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                Self::remove_if_exists(&metadata_path).await?
+            }
+            result => result?,
+        }
+        let checksums_path = self.get_sidecar_path(to, CHECKSUMS_SIDECAR)?;
+        match fs::rename(self.get_sidecar_path(from, CHECKSUMS_SIDECAR)?, &checksums_path).await {
+            Err(e) if e.kind() == ErrorKind::NotFound => {
+                Self::remove_if_exists(&checksums_path).await?
+            }
+            result => result?,
+        }
+        self.remove_sidecars(from).await?;
+        self.update_etag(to).await
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        let file = self.get_path(path)?;
+        Self::create_parent(&file).await?;
+        let marker = self.get_sidecar_path(path, APPENDING_SIDECAR)?;
+        Self::create_parent(&marker).await?;
+        let checksums = self.get_sidecar_path(path, CHECKSUMS_SIDECAR)?;
+
+        let _lock = self.lock.lock().await;
+        fs::write(&marker, "").await?;
+        Self::remove_if_exists(&checksums).await?;
+        let writer = File::create(&file).await?;
+        self.update_etag(path).await?;
+        Ok(Box::new(FsAppendSession {
+            file: writer,
+            marker,
+            etag: self.get_sidecar_path(path, ETAG_SIDECAR)?,
+            checksums,
+            hasher: ChecksumHasher::new(),
+            sealed: false,
+        }))
+    }
+
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        let file = self.open_file(path).await?;
+        Ok(Self::tail(file, self.get_sidecar_path(path, APPENDING_SIDECAR)?))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        Ok(paginate(self.list_objects(prefix).await?))
+    }
+
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        let Some((base, signer)) = &self.presign else {
+            return Err(StorageError::Unsupported);
+        };
+        self.get_path(path)?;
+        let url = base.join(path.trim_start_matches('/')).map_err(|e| {
+            StorageError::Other(std::io::Error::new(ErrorKind::InvalidInput, e))
+        })?;
+        Ok(signer.sign(url, permissions, expiry))
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        fs::create_dir_all(&self.root).await?;
+        let (sender, events) = mpsc::unbounded_channel();
+        let mut watcher = notify::recommended_watcher(move |event| {
+            let _ = sender.send(event);
+        })
+        .map_err(from_notify_error)?;
+        watcher
+            .watch(&self.root, RecursiveMode::Recursive)
+            .map_err(from_notify_error)?;
+        // objects that exist once the watch is in place are not reported.
+        let known = self.list_objects(prefix).await?;
+        let watch = FsWatch {
+            _watcher: watcher,
+            events,
+            canonical_root: fs::canonicalize(&self.root).await?,
+            root: self.root.clone(),
+            prefix: prefix.trim_start_matches('/').to_owned(),
+            known: known.into_iter().map(|o| o.name).collect(),
+            scanned: HashSet::new(),
+            changes: VecDeque::new(),
+        };
+        let stream = futures::stream::unfold(watch, |mut watch| async move {
+            loop {
+                if let Some(change) = watch.changes.pop_front() {
+                    return Some((Ok(change), watch));
+                }
+                match watch.events.recv().await? {
+                    Ok(event) => watch.handle(event),
+                    Err(e) => return Some((Err(from_notify_error(e)), watch)),
+                }
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+fn from_notify_error(error: notify::Error) -> StorageError {
+    match error.kind {
+        notify::ErrorKind::Io(error) => StorageError::Other(error),
+        _ => StorageError::Other(std::io::Error::other(error)),
+    }
+}
+
+/// Turns file system events into changes of objects. Whether an object was
+/// created, updated or deleted is decided by looking at the file once the
+/// event arrives, which copes with the different ways platforms report renames.
+struct FsWatch {
+    _watcher: RecommendedWatcher,
+    events: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    root: PathBuf,
+    canonical_root: PathBuf,
+    prefix: String,
+    known: HashSet<String>,
+    /// Objects found by scanning a new directory, whose own creation event
+    /// is still to come.
+    scanned: HashSet<String>,
+    changes: VecDeque<ChangeEvent>,
+}
+
+impl FsWatch {
+    fn handle(&mut self, event: notify::Event) {
+        match event.kind {
+            // renames reported as both halves are also reported separately.
+            EventKind::Access(_)
+            | EventKind::Modify(ModifyKind::Metadata(_))
+            | EventKind::Modify(ModifyKind::Name(RenameMode::Both)) => return,
+            _ => {}
+        }
+        let created = matches!(
+            event.kind,
+            EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_))
+        );
+        for path in event.paths {
+            if path.is_dir() {
+                // files may have been created before the new directory was watched.
+                self.scan(&path);
+            } else {
+                self.check(&path, created);
+            }
+        }
+    }
+
+    fn scan(&mut self, dir: &Path) {
+        // sidecars are not objects of their own.
+        if self
+            .name(dir)
+            .is_some_and(|name| Path::new(&name).starts_with(META_DIR))
+        {
+            return;
+        }
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                self.scan(&path);
+            } else if let Some(name) = self.name(&path) {
+                if !self.known.contains(&name) {
+                    self.check(&path, false);
+                    self.scanned.insert(name);
+                }
+            }
+        }
+    }
+
+    fn check(&mut self, path: &Path, created: bool) {
+        let Some(name) = self.name(path) else {
+            return;
+        };
+        if self.scanned.remove(&name) && created {
+            return;
+        }
+        let sidecar = name
+            .strip_prefix(META_DIR)
+            .and_then(|sidecar| sidecar.strip_prefix('/'));
+        if let Some(sidecar) = sidecar {
+            // the other sidecars only change along with the content.
+            if let Some((object, file)) = sidecar.rsplit_once('/') {
+                if matches!(file, METADATA_SIDECAR | APPENDING_SIDECAR)
+                    && self.known.contains(object)
+                {
+                    self.push(object, ChangeKind::Updated);
+                }
+            }
+            return;
+        }
+        let exists = path.is_file();
+        let known = self.known.contains(&name);
+        match (exists, known) {
+            (true, false) => {
+                self.known.insert(name.clone());
+                self.push(&name, ChangeKind::Created);
+            }
+            (true, true) => self.push(&name, ChangeKind::Updated),
+            (false, true) => {
+                self.known.remove(&name);
+                self.push(&name, ChangeKind::Deleted);
+            }
+            (false, false) => {}
+        }
+    }
+
+    fn push(&mut self, name: &str, kind: ChangeKind) {
+        if name.starts_with(&self.prefix) {
+            self.changes.push_back(ChangeEvent::new(name, kind));
+        }
+    }
+
+    fn name(&self, path: &Path) -> Option<String> {
+        let relative = path
+            .strip_prefix(&self.root)
+            .or_else(|_| path.strip_prefix(&self.canonical_root))
+            .ok()?;
+        let parts: Option<Vec<_>> = relative
+            .components()
+            .map(|c| c.as_os_str().to_str())
+            .collect();
+        Some(parts?.join("/"))
+    }
+}
+
+/// Appends straight to the object file. The ETag changes when the object is
+/// opened and when it is sealed.
+struct FsAppendSession {
+    file: File,
+    marker: PathBuf,
+    etag: PathBuf,
+    checksums: PathBuf,
+    hasher: ChecksumHasher,
+    sealed: bool,
+}
+
+#[async_trait]
+impl AppendSession for FsAppendSession {
+    async fn append(&mut self, chunk: bytes::Bytes) -> Result<(), StorageError> {
+        self.file.write_all(&chunk).await?;
+        self.file.flush().await?;
+        self.hasher.update(&chunk);
+        Ok(())
+    }
+
+    async fn seal(mut self: Box<Self>) -> Result<(), StorageError> {
+        self.file.flush().await?;
+        let checksums = self.hasher.clone().finish();
+        FsStorageContainer::write_checksums(&self.checksums, &checksums).await?;
+        FsStorageContainer::write_etag(&self.etag).await?;
+        FsStorageContainer::remove_if_exists(&self.marker).await?;
+        self.sealed = true;
+        Ok(())
+    }
+}
+
+impl Drop for FsAppendSession {
+    /// Ends the object where the writer stopped, so tailing readers finish.
+    fn drop(&mut self) {
+        if !self.sealed {
+            let _ = std::fs::remove_file(&self.marker);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    fn to_stream(chunks: Vec<&'static str>) -> StreamType {
+        Box::pin(futures::stream::iter(
+            chunks.into_iter().map(|c| Ok(Bytes::from(c))),
+        ))
+    }
+
+    #[tokio::test]
+    async fn tails_appends_until_sealed() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        let mut session = storage.open_append("live/out.ts").await.unwrap();
+        storage
+            .set_metadata("live/out.ts", "meta".into())
+            .await
+            .unwrap();
+        session.append(Bytes::from("abc")).await.unwrap();
+
+        let mut tail = storage.get_content_tail("live/out.ts").await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), "abc");
+        assert_eq!(storage.list_all("live/").await.unwrap().len(), 1);
+        session.append(Bytes::from("def")).await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), "def");
+        session.append(Bytes::from("ghi")).await.unwrap();
+        session.seal().await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), "ghi");
+        assert!(tail.try_next().await.unwrap().is_none());
+
+        assert_eq!(
+            storage.get_metadata("live/out.ts").await.unwrap(),
+            "meta"
+        );
+        let content: Vec<Bytes> = storage
+            .get_content_tail("live/out.ts")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(content.concat(), b"abcdefghi");
+    }
+
+    #[tokio::test]
+    async fn presigns_proxy_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        let expiry = Duration::from_secs(60);
+        assert!(matches!(
+            storage.presign("a.ts", Permissions::READ, expiry).await,
+            Err(StorageError::Unsupported)
+        ));
+
+        let signer = UrlSigner::new("secret");
+        let base = Url::parse("http://proxy:8080/account/video").unwrap();
+        let storage = storage.with_presign(base, signer.clone());
+        let url = storage
+            .presign("/level0/a.ts", Permissions::READ, expiry)
+            .await
+            .unwrap();
+        assert_eq!(url.path(), "/account/video/level0/a.ts");
+        signer.verify(&url, Permissions::READ).unwrap();
+        assert!(signer.verify(&url, Permissions::WRITE).is_err());
+        assert!(storage
+            .presign("../a.ts", Permissions::READ, expiry)
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn watches_changes_by_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        storage
+            .set_content("video/old.ts", to_stream(vec!["old"]))
+            .await
+            .unwrap();
+        let mut changes = storage.watch("video/").await.unwrap();
+
+        storage
+            .set_content("video/level0/segment0.ts", to_stream(vec!["abc"]))
+            .await
+            .unwrap();
+        storage
+            .set_content("other/segment0.ts", to_stream(vec!["abc"]))
+            .await
+            .unwrap();
+        storage.delete("video/old.ts").await.unwrap();
+        storage
+            .set_content("video/level0/segment0.ts.metadata", to_stream(vec!["abc"]))
+            .await
+            .unwrap();
+
+        let mut seen = Vec::new();
+        while seen.len() < 3 {
+            let change = changes.try_next().await.unwrap().unwrap();
+            if !seen.contains(&change) {
+                seen.push(change);
+            }
+        }
+        seen.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(
+            seen,
+            vec![
+                ChangeEvent::new("video/level0/segment0.ts", ChangeKind::Created),
+                ChangeEvent::new("video/level0/segment0.ts.metadata", ChangeKind::Created),
+                ChangeEvent::new("video/old.ts", ChangeKind::Deleted),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = std::sync::Arc::new(FsStorageContainer::new(dir.path()));
+        let waiter = storage.clone();
+        let wait = tokio::spawn(async move { waiter.wait_for("level0/segment0.ts").await });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert!(!wait.is_finished());
+        storage
+            .set_content("level0/segment0.ts", to_stream(vec!["abc"]))
+            .await
+            .unwrap();
+        tokio::time::timeout(Duration::from_secs(5), wait)
+            .await
+            .unwrap()
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn conforms() {
+        let dir = tempfile::tempdir().unwrap();
+        crate::conformance::run(&FsStorageContainer::new(dir.path()), "conformance/").await;
+    }
+
+    #[tokio::test]
+    async fn round_trip_content_and_metadata() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+
+        storage
+            .set_content(
+                "video/level0/segment0.ts",
+                to_stream(vec!["hello ", "world"]),
+            )
+            .await
+            .unwrap();
+        storage
+            .set_metadata("video/level0/segment0.ts", "{\"duration\":5}".into())
+            .await
+            .unwrap();
+
+        assert!(storage.exists("video/level0/segment0.ts").await.unwrap());
+        let content: Vec<Bytes> = storage
+            .get_content("video/level0/segment0.ts")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(content.concat(), b"hello world");
+        assert_eq!(
+            storage
+                .get_metadata("video/level0/segment0.ts")
+                .await
+                .unwrap(),
+            "{\"duration\":5}"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_files_are_not_found() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+
+        assert!(!storage.exists("missing.ts").await.unwrap());
+        assert!(matches!(
+            storage.get_content("missing.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(matches!(
+            storage.get_metadata("missing.ts").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_write_keeps_previous_content() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        storage
+            .set_content("a.ts", to_stream(vec!["original"]))
+            .await
+            .unwrap();
+
+        let failing: StreamType = Box::pin(futures::stream::iter(vec![
+            Ok(Bytes::from("partial")),
+            Err(StorageError::NotFound),
+        ]));
+        assert!(storage.set_content("a.ts", failing).await.is_err());
+
+        let content: Vec<Bytes> = storage
+            .get_content("a.ts")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(content.concat(), b"original");
+    }
+
+    #[tokio::test]
+    async fn reads_byte_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        storage
+            .set_content("a.mp4", to_stream(vec!["0123456789"]))
+            .await
+            .unwrap();
+
+        let range: Vec<Bytes> = storage
+            .get_content_range("a.mp4", 2, Some(4))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(range.concat(), b"2345");
+        let tail: Vec<Bytes> = storage
+            .get_content_range("a.mp4", 7, None)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(tail.concat(), b"789");
+    }
+
+    #[tokio::test]
+    async fn lists_objects_by_prefix() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        for path in [
+            "video/level1/segment0.ts",
+            "video/level0/segment1.ts",
+            "video/level0/segment0.ts",
+            "other.ts",
+        ] {
+            storage
+                .set_content(path, to_stream(vec!["data"]))
+                .await
+                .unwrap();
+        }
+        storage
+            .set_metadata("video/level0/segment0.ts", "meta".into())
+            .await
+            .unwrap();
+
+        let names: Vec<_> = storage
+            .list_all("video/level0/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| (o.name, o.size))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                ("video/level0/segment0.ts".to_owned(), 4),
+                ("video/level0/segment1.ts".to_owned(), 4)
+            ]
+        );
+        assert_eq!(storage.list_all("video/level").await.unwrap().len(), 3);
+        assert_eq!(storage.list_all("").await.unwrap().len(), 4);
+        assert!(storage.list_all("missing/").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn stat_reports_properties() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        storage
+            .set_content("level0.m3u8", to_stream(vec!["#EXTM3U"]))
+            .await
+            .unwrap();
+
+        let properties = storage.stat("level0.m3u8").await.unwrap();
+        assert_eq!(properties.content_length, 7);
+        assert_eq!(
+            properties.content_type.as_deref(),
+            Some("application/vnd.apple.mpegurl")
+        );
+        assert!(properties.last_modified.is_some());
+        assert_eq!(properties.metadata, None);
+
+        storage
+            .set_metadata("level0.m3u8", "meta".into())
+            .await
+            .unwrap();
+        let updated = storage.stat("level0.m3u8").await.unwrap();
+        assert_eq!(updated.metadata.as_deref(), Some("meta"));
+        assert_ne!(updated.etag, properties.etag);
+        assert!(matches!(
+            storage.stat("missing.ts").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn conditional_writes_detect_lost_updates() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+
+        storage
+            .set_content_if("a.m3u8", to_stream(vec!["v1"]), WriteCondition::IfNoneMatch)
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .set_content_if("a.m3u8", to_stream(vec!["v2"]), WriteCondition::IfNoneMatch)
+                .await,
+            Err(StorageError::PreconditionFailed)
+        ));
+
+        let etag = storage.stat("a.m3u8").await.unwrap().etag.unwrap();
+        storage
+            .set_content_if(
+                "a.m3u8",
+                to_stream(vec!["v2"]),
+                WriteCondition::IfMatch(etag.clone()),
+            )
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .set_metadata_if("a.m3u8", "meta".into(), WriteCondition::IfMatch(etag))
+                .await,
+            Err(StorageError::PreconditionFailed)
+        ));
+
+        let etag = storage.stat("a.m3u8").await.unwrap().etag.unwrap();
+        storage
+            .set_metadata_if("a.m3u8", "meta".into(), WriteCondition::IfMatch(etag))
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .set_metadata_if("a.m3u8", "other".into(), WriteCondition::IfNoneMatch)
+                .await,
+            Err(StorageError::PreconditionFailed)
+        ));
+        assert_eq!(storage.list_all("").await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn delete_copy_and_rename() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        storage
+            .set_content("staging/a.ts", to_stream(vec!["data"]))
+            .await
+            .unwrap();
+        storage
+            .set_metadata("staging/a.ts", "meta".into())
+            .await
+            .unwrap();
+
+        storage.copy("staging/a.ts", "staging/b.ts").await.unwrap();
+        assert_eq!(storage.get_metadata("staging/b.ts").await.unwrap(), "meta");
+        storage.rename("staging/b.ts", "final/b.ts").await.unwrap();
+        assert!(!storage.exists("staging/b.ts").await.unwrap());
+        assert_eq!(storage.get_metadata("final/b.ts").await.unwrap(), "meta");
+        assert_eq!(storage.stat("final/b.ts").await.unwrap().content_length, 4);
+
+        assert_eq!(storage.delete_prefix("staging/").await.unwrap(), 1);
+        assert!(!storage.exists("staging/a.ts").await.unwrap());
+        assert!(matches!(
+            storage.get_metadata("staging/a.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(matches!(
+            storage.delete("staging/a.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(matches!(
+            storage.rename("missing.ts", "other.ts").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn rejects_paths_outside_root() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path().join("root"));
+        assert!(storage.get_content("../escape").await.is_err());
+        assert!(!storage.exists("../escape").await.unwrap());
+        assert!(storage.get_content(".meta/a.ts/metadata").await.is_err());
+        assert!(storage
+            .set_content("./.meta/a.ts", to_stream(vec!["data"]))
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_sidecars_apart_from_objects() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = FsStorageContainer::new(dir.path());
+        storage
+            .set_content("a.ts", to_stream(vec!["data"]))
+            .await
+            .unwrap();
+        storage.set_metadata("a.ts", "meta".into()).await.unwrap();
+        for name in ["a.ts.metadata", "a.ts.etag", "a.ts.partial", "a.ts.checksums"] {
+            storage
+                .set_content(name, to_stream(vec![name]))
+                .await
+                .unwrap();
+        }
+
+        let names: Vec<_> = storage
+            .list_all("a.ts")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| o.name)
+            .collect();
+        assert_eq!(
+            names,
+            ["a.ts", "a.ts.checksums", "a.ts.etag", "a.ts.metadata", "a.ts.partial"]
+        );
+        assert_eq!(storage.get_metadata("a.ts").await.unwrap(), "meta");
+        let content: Vec<Bytes> = storage
+            .get_content("a.ts.metadata")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(content.concat(), b"a.ts.metadata");
+
+        // the sidecars of a deleted object don't stand in the way of its parent.
+        storage
+            .set_content("b/metadata", to_stream(vec!["data"]))
+            .await
+            .unwrap();
+        storage.delete("b/metadata").await.unwrap();
+        std::fs::remove_dir(dir.path().join("b")).unwrap();
+        storage
+            .set_content("b", to_stream(vec!["data"]))
+            .await
+            .unwrap();
+        storage.set_metadata("b", "meta".into()).await.unwrap();
+        assert_eq!(storage.get_metadata("b").await.unwrap(), "meta");
+    }
+}
@@ -0,0 +1,582 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::Stream;
+use metrics::{counter, histogram, Counter, Histogram, Label};
+use tracing::{field, Instrument, Span};
+
+use crate::{
+    AppendSession, BlockUpload, Checksums, Lease, ListStream, ObjectInfo, ObjectProperties,
+    Permissions, StorageContainer, StorageError, StreamType, UploadPolicy, Url, WatchStream,
+    WriteCondition,
+};
+
+/// How long each operation took until it returned, in seconds.
+pub const DURATION_METRIC: &str = "storage_operation_duration_seconds";
+/// Failed operations, labelled with the `kind` of [`StorageError`].
+pub const ERRORS_METRIC: &str = "storage_errors_total";
+pub const READ_BYTES_METRIC: &str = "storage_read_bytes_total";
+pub const WRITTEN_BYTES_METRIC: &str = "storage_written_bytes_total";
+/// How long reads took from the call until their first chunk, in seconds.
+pub const FIRST_BYTE_METRIC: &str = "storage_time_to_first_byte_seconds";
+
+/// The `kind` label of an error.
+fn error_kind(error: &StorageError) -> &'static str {
+    match error {
+        StorageError::NotFound => "not_found",
+        StorageError::AuthenticationError => "authentication",
+        StorageError::Conflict => "conflict",
+        StorageError::PreconditionFailed => "precondition_failed",
+        StorageError::Unsupported => "unsupported",
+        StorageError::IntegrityMismatch => "integrity_mismatch",
+        StorageError::Timeout(_) => "timeout",
+        StorageError::Throttled(_) => "throttled",
+        StorageError::ServiceUnavailable(_) => "service_unavailable",
+        StorageError::HttpError(_) => "http",
+        StorageError::Other(_) => "other",
+    }
+}
+
+/// Records the metrics and spans of one container.
+struct Meter {
+    account: String,
+    backend: String,
+}
+
+impl Meter {
+    fn labels(&self, operation: &'static str) -> Vec<Label> {
+        vec![
+            Label::new("operation", operation),
+            Label::new("account", self.account.clone()),
+            Label::new("backend", self.backend.clone()),
+        ]
+    }
+
+    fn span(&self, operation: &'static str, path: &str) -> Span {
+        tracing::info_span!(
+            "storage",
+            operation,
+            account = %self.account,
+            backend = %self.backend,
+            path,
+            error = field::Empty,
+        )
+    }
+
+    fn record_error(&self, operation: &'static str, span: &Span, error: &StorageError) {
+        let kind = error_kind(error);
+        span.record("error", kind);
+        let mut labels = self.labels(operation);
+        labels.push(Label::new("kind", kind));
+        counter!(ERRORS_METRIC, labels).increment(1);
+    }
+
+    fn finish(
+        &self,
+        operation: &'static str,
+        span: &Span,
+        elapsed: Duration,
+        error: Option<&StorageError>,
+    ) {
+        histogram!(DURATION_METRIC, self.labels(operation)).record(elapsed);
+        if let Some(error) = error {
+            self.record_error(operation, span, error);
+        }
+    }
+
+    async fn observe<T, F>(
+        &self,
+        operation: &'static str,
+        path: &str,
+        f: F,
+    ) -> Result<T, StorageError>
+    where
+        F: Future<Output = Result<T, StorageError>>,
+    {
+        let span = self.span(operation, path);
+        let start = Instant::now();
+        let result = f.instrument(span.clone()).await;
+        self.finish(operation, &span, start.elapsed(), result.as_ref().err());
+        result
+    }
+
+    fn written(&self, operation: &'static str, content: StreamType) -> StreamType {
+        Box::pin(MeteredStream {
+            inner: content,
+            bytes: counter!(WRITTEN_BYTES_METRIC, self.labels(operation)),
+            first_byte: None,
+            errors: None,
+        })
+    }
+}
+
+/// Wraps another container and records how long its operations take, how
+/// many bytes they move and how they fail, as `tracing` spans and as
+/// [`metrics`] labelled with the operation, account and backend. Install a
+/// recorder such as a Prometheus exporter to collect them.
+///
+/// Reads are metered as their stream is consumed, so a read that breaks
+/// halfway counts as an error of its operation too.
+pub struct InstrumentedStorageContainer<S> {
+    inner: S,
+    meter: Arc<Meter>,
+}
+
+impl<S> InstrumentedStorageContainer<S> {
+    /// `account` and `backend` (for example `azure` or `s3`) become labels
+    /// of every metric and span.
+    pub fn new(inner: S, account: &str, backend: &str) -> Self {
+        Self {
+            inner,
+            meter: Arc::new(Meter {
+                account: account.to_owned(),
+                backend: backend.to_owned(),
+            }),
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+}
+
+impl<S> InstrumentedStorageContainer<S>
+where
+    S: StorageContainer + Send + Sync,
+{
+    async fn read<F>(
+        &self,
+        operation: &'static str,
+        path: &str,
+        f: F,
+    ) -> Result<StreamType, StorageError>
+    where
+        F: Future<Output = Result<StreamType, StorageError>>,
+    {
+        let span = self.meter.span(operation, path);
+        let start = Instant::now();
+        let result = f.instrument(span.clone()).await;
+        self.meter
+            .finish(operation, &span, start.elapsed(), result.as_ref().err());
+        let content = result?;
+        Ok(Box::pin(MeteredStream {
+            inner: content,
+            bytes: counter!(READ_BYTES_METRIC, self.meter.labels(operation)),
+            first_byte: Some((
+                start,
+                histogram!(FIRST_BYTE_METRIC, self.meter.labels(operation)),
+            )),
+            errors: Some((self.meter.clone(), operation, span)),
+        }))
+    }
+}
+
+#[async_trait]
+impl<S> StorageContainer for InstrumentedStorageContainer<S>
+where
+    S: StorageContainer + Send + Sync,
+{
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.read("get_content", path, self.inner.get_content(path))
+            .await
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        self.read(
+            "get_content_range",
+            path,
+            self.inner.get_content_range(path, offset, length),
+        )
+        .await
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        self.meter
+            .observe("get_metadata", path, self.inner.get_metadata(path))
+            .await
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        let content = self.meter.written("set_content", content);
+        self.meter
+            .observe("set_content", path, self.inner.set_content(path, content))
+            .await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        self.meter
+            .observe(
+                "set_metadata",
+                path,
+                self.inner.set_metadata(path, metadata),
+            )
+            .await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.meter
+            .observe("exists", path, self.inner.exists(path))
+            .await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        let content = self.meter.written("set_content_if", content);
+        self.meter
+            .observe(
+                "set_content_if",
+                path,
+                self.inner.set_content_if(path, content, condition),
+            )
+            .await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.meter
+            .observe(
+                "set_metadata_if",
+                path,
+                self.inner.set_metadata_if(path, metadata, condition),
+            )
+            .await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        self.meter
+            .observe("stat", path, self.inner.stat(path))
+            .await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        self.meter
+            .observe("list", prefix, self.inner.list(prefix))
+            .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.meter
+            .observe("delete", path, self.inner.delete(path))
+            .await
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<usize, StorageError> {
+        self.meter
+            .observe("delete_prefix", prefix, self.inner.delete_prefix(prefix))
+            .await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.meter
+            .observe("copy", from, self.inner.copy(from, to))
+            .await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.meter
+            .observe("rename", from, self.inner.rename(from, to))
+            .await
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        let session = self
+            .meter
+            .observe("open_append", path, self.inner.open_append(path))
+            .await?;
+        Ok(Box::new(InstrumentedAppendSession {
+            inner: session,
+            meter: self.meter.clone(),
+            path: path.to_owned(),
+        }))
+    }
+
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.read("get_content_tail", path, self.inner.get_content_tail(path))
+            .await
+    }
+
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        let upload = self
+            .meter
+            .observe(
+                "start_block_upload",
+                path,
+                self.inner.start_block_upload(path),
+            )
+            .await?;
+        Ok(Box::new(InstrumentedBlockUpload {
+            inner: upload,
+            meter: self.meter.clone(),
+            path: path.to_owned(),
+        }))
+    }
+
+    async fn set_content_parallel(
+        &self,
+        path: &str,
+        content: StreamType,
+        policy: &UploadPolicy,
+    ) -> Result<(), StorageError> {
+        let content = self.meter.written("set_content_parallel", content);
+        self.meter
+            .observe(
+                "set_content_parallel",
+                path,
+                self.inner.set_content_parallel(path, content, policy),
+            )
+            .await
+    }
+
+    async fn acquire_lease(&self, path: &str, ttl: Duration) -> Result<Lease, StorageError> {
+        self.meter
+            .observe("acquire_lease", path, self.inner.acquire_lease(path, ttl))
+            .await
+    }
+
+    async fn renew_lease(&self, lease: &Lease) -> Result<(), StorageError> {
+        self.meter
+            .observe("renew_lease", &lease.path, self.inner.renew_lease(lease))
+            .await
+    }
+
+    async fn release_lease(&self, lease: Lease) -> Result<(), StorageError> {
+        let path = lease.path.clone();
+        self.meter
+            .observe("release_lease", &path, self.inner.release_lease(lease))
+            .await
+    }
+
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        self.meter
+            .observe(
+                "presign",
+                path,
+                self.inner.presign(path, permissions, expiry),
+            )
+            .await
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        self.meter
+            .observe("watch", prefix, self.inner.watch(prefix))
+            .await
+    }
+
+    async fn list_all(&self, prefix: &str) -> Result<Vec<ObjectInfo>, StorageError> {
+        self.meter
+            .observe("list_all", prefix, self.inner.list_all(prefix))
+            .await
+    }
+}
+
+struct InstrumentedAppendSession {
+    inner: Box<dyn AppendSession>,
+    meter: Arc<Meter>,
+    path: String,
+}
+
+#[async_trait]
+impl AppendSession for InstrumentedAppendSession {
+    async fn append(&mut self, chunk: Bytes) -> Result<(), StorageError> {
+        let written = counter!(WRITTEN_BYTES_METRIC, self.meter.labels("append"));
+        let length = chunk.len() as u64;
+        self.meter
+            .observe("append", &self.path, self.inner.append(chunk))
+            .await?;
+        written.increment(length);
+        Ok(())
+    }
+
+    async fn seal(self: Box<Self>) -> Result<(), StorageError> {
+        let Self { inner, meter, path } = *self;
+        meter.observe("seal", &path, inner.seal()).await
+    }
+}
+
+struct InstrumentedBlockUpload {
+    inner: Box<dyn BlockUpload>,
+    meter: Arc<Meter>,
+    path: String,
+}
+
+#[async_trait]
+impl BlockUpload for InstrumentedBlockUpload {
+    fn min_block_size(&self) -> usize {
+        self.inner.min_block_size()
+    }
+
+    async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError> {
+        let written = counter!(WRITTEN_BYTES_METRIC, self.meter.labels("stage_block"));
+        let length = block.len() as u64;
+        self.meter
+            .observe(
+                "stage_block",
+                &self.path,
+                self.inner.stage_block(index, block),
+            )
+            .await?;
+        written.increment(length);
+        Ok(())
+    }
+
+    async fn commit(
+        self: Box<Self>,
+        blocks: usize,
+        checksums: Checksums,
+    ) -> Result<(), StorageError> {
+        let Self { inner, meter, path } = *self;
+        meter
+            .observe("commit_blocks", &path, inner.commit(blocks, checksums))
+            .await
+    }
+
+    async fn abort(self: Box<Self>) -> Result<(), StorageError> {
+        self.inner.abort().await
+    }
+}
+
+/// Counts the bytes passing through, and for reads when the first arrived
+/// and whether the stream broke.
+struct MeteredStream {
+    inner: StreamType,
+    bytes: Counter,
+    first_byte: Option<(Instant, Histogram)>,
+    errors: Option<(Arc<Meter>, &'static str, Span)>,
+}
+
+impl Stream for MeteredStream {
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        let _entered = this.errors.as_ref().map(|(_, _, span)| span.enter());
+        let item = this.inner.as_mut().poll_next(cx);
+        match &item {
+            Poll::Ready(Some(Ok(chunk))) => {
+                this.bytes.increment(chunk.len() as u64);
+                if let Some((start, first_byte)) = this.first_byte.take() {
+                    first_byte.record(start.elapsed());
+                }
+            }
+            Poll::Ready(Some(Err(e))) => {
+                if let Some((meter, operation, span)) = &this.errors {
+                    meter.record_error(operation, span, e);
+                }
+            }
+            _ => {}
+        }
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorageContainer;
+    use futures::TryStreamExt;
+    use metrics::{SharedString, Unit};
+    use metrics_util::{
+        debugging::{DebugValue, DebuggingRecorder},
+        CompositeKey,
+    };
+
+    fn body(content: &'static str) -> StreamType {
+        Box::pin(futures::stream::iter([Ok(Bytes::from(content))]))
+    }
+
+    type Snapshot = Vec<(CompositeKey, Option<Unit>, Option<SharedString>, DebugValue)>;
+
+    /// The values of the metric `name` whose labels include `labels`.
+    fn values<'a>(
+        snapshot: &'a Snapshot,
+        name: &str,
+        labels: &[(&str, &str)],
+    ) -> Vec<&'a DebugValue> {
+        let matches = |key: &CompositeKey| {
+            key.key().name() == name
+                && labels.iter().all(|(k, v)| {
+                    key.key()
+                        .labels()
+                        .any(|label| label.key() == *k && label.value() == *v)
+                })
+        };
+        snapshot
+            .iter()
+            .filter(|(key, ..)| matches(key))
+            .map(|(.., value)| value)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn records_operations() {
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        let _guard = metrics::set_default_local_recorder(&recorder);
+
+        let storage =
+            InstrumentedStorageContainer::new(MemoryStorageContainer::new(), "videos", "memory");
+        storage.set_content("a.ts", body("hello")).await.unwrap();
+        let content: Vec<Bytes> = storage
+            .get_content("a.ts")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(content.concat(), b"hello");
+        assert!(matches!(
+            storage.get_content("missing.ts").await,
+            Err(StorageError::NotFound)
+        ));
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let labels = [("account", "videos"), ("backend", "memory")];
+        let written = [labels[0], labels[1], ("operation", "set_content")];
+        assert_eq!(
+            values(&snapshot, WRITTEN_BYTES_METRIC, &written),
+            [&DebugValue::Counter(5)]
+        );
+        let read = [labels[0], labels[1], ("operation", "get_content")];
+        assert_eq!(
+            values(&snapshot, READ_BYTES_METRIC, &read),
+            [&DebugValue::Counter(5)]
+        );
+        assert!(matches!(
+            values(&snapshot, DURATION_METRIC, &read).as_slice(),
+            [DebugValue::Histogram(durations)] if durations.len() == 2
+        ));
+        assert!(matches!(
+            values(&snapshot, FIRST_BYTE_METRIC, &read).as_slice(),
+            [DebugValue::Histogram(durations)] if durations.len() == 1
+        ));
+        assert_eq!(
+            values(&snapshot, ERRORS_METRIC, &[("kind", "not_found")]),
+            [&DebugValue::Counter(1)]
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+use futures::TryStreamExt;
+
+use crate::{StorageContainer, StorageError, StreamType, WriteCondition};
+
+/// A lease on the object at `path`, see [`StorageContainer::acquire_lease`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lease {
+    pub path: String,
+    /// Identifies the holder to the backend.
+    pub id: String,
+    /// How long the lease lasts after it was acquired or last renewed.
+    pub ttl: Duration,
+}
+
+/// The content of a lease object: the holder and when its lease expires.
+struct LeaseRecord {
+    id: String,
+    /// Milliseconds since the unix epoch.
+    expires: u128,
+}
+
+impl LeaseRecord {
+    fn new(id: &str, ttl: Duration) -> Self {
+        Self {
+            id: id.to_owned(),
+            expires: unix_millis(SystemTime::now() + ttl),
+        }
+    }
+
+    fn parse(content: &[u8]) -> Option<Self> {
+        let content = std::str::from_utf8(content).ok()?;
+        let (id, expires) = content.trim_end().split_once('\n')?;
+        Some(Self {
+            id: id.to_owned(),
+            expires: expires.parse().ok()?,
+        })
+    }
+
+    fn is_expired(&self) -> bool {
+        self.expires <= unix_millis(SystemTime::now())
+    }
+
+    fn body(&self) -> StreamType {
+        let content = Bytes::from(format!("{}\n{}\n", self.id, self.expires));
+        Box::pin(futures::stream::iter([Ok(content)]))
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis()
+}
+
+/// The ETag and record of a lease object. Objects that are not lease records
+/// count as expired leases.
+async fn read_record<S>(
+    storage: &S,
+    path: &str,
+) -> Result<Option<(String, Option<LeaseRecord>)>, StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let etag = match storage.stat(path).await {
+        Ok(properties) => properties.etag.ok_or(StorageError::Unsupported)?,
+        Err(StorageError::NotFound) => return Ok(None),
+        Err(e) => return Err(e),
+    };
+    let content: Vec<Bytes> = match storage.get_content(path).await {
+        Ok(content) => content.try_collect().await?,
+        // deleted in between, the write below is conditional anyway.
+        Err(StorageError::NotFound) => Vec::new(),
+        Err(e) => return Err(e),
+    };
+    Ok(Some((etag, LeaseRecord::parse(&content.concat()))))
+}
+
+/// Writes `record` if the object is still at `etag`. Losing the race to
+/// another writer means someone else holds the lease now.
+async fn write_record<S>(
+    storage: &S,
+    path: &str,
+    record: &LeaseRecord,
+    condition: WriteCondition,
+) -> Result<(), StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    match storage.set_content_if(path, record.body(), condition).await {
+        Err(StorageError::PreconditionFailed) => Err(StorageError::Conflict),
+        result => result,
+    }
+}
+
+/// Takes the lease held in the object at `path` with conditional writes. Its
+/// content records the holder and the expiry, which is compared against the
+/// local clock, so clocks should be reasonably in sync.
+pub(crate) async fn acquire<S>(
+    storage: &S,
+    path: &str,
+    ttl: Duration,
+) -> Result<Lease, StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let condition = match read_record(storage, path).await? {
+        None => WriteCondition::IfNoneMatch,
+        Some((_, Some(record))) if !record.is_expired() => return Err(StorageError::Conflict),
+        Some((etag, _)) => WriteCondition::IfMatch(etag),
+    };
+    let id = format!("{:032x}", rand::random::<u128>());
+    write_record(storage, path, &LeaseRecord::new(&id, ttl), condition).await?;
+    Ok(Lease {
+        path: path.to_owned(),
+        id,
+        ttl,
+    })
+}
+
+/// The ETag of the lease object if `lease` still holds it.
+async fn held<S>(storage: &S, lease: &Lease) -> Result<String, StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    match read_record(storage, &lease.path).await? {
+        Some((etag, Some(record))) if record.id == lease.id && !record.is_expired() => Ok(etag),
+        _ => Err(StorageError::Conflict),
+    }
+}
+
+pub(crate) async fn renew<S>(storage: &S, lease: &Lease) -> Result<(), StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let etag = held(storage, lease).await?;
+    let record = LeaseRecord::new(&lease.id, lease.ttl);
+    write_record(storage, &lease.path, &record, WriteCondition::IfMatch(etag)).await
+}
+
+/// Releases by writing an expired record, a delete could not be conditional.
+pub(crate) async fn release<S>(storage: &S, lease: Lease) -> Result<(), StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    let etag = held(storage, &lease).await?;
+    let record = LeaseRecord::new(&lease.id, Duration::ZERO);
+    write_record(storage, &lease.path, &record, WriteCondition::IfMatch(etag)).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FsStorageContainer, MemoryStorageContainer};
+
+    async fn exactly_one_holder(storage: &(dyn StorageContainer + Sync)) {
+        let ttl = Duration::from_secs(30);
+        let lease = storage.acquire_lease("segment5.lock", ttl).await.unwrap();
+        assert!(matches!(
+            storage.acquire_lease("segment5.lock", ttl).await,
+            Err(StorageError::Conflict)
+        ));
+        storage.renew_lease(&lease).await.unwrap();
+        storage.release_lease(lease.clone()).await.unwrap();
+        assert!(matches!(
+            storage.renew_lease(&lease).await,
+            Err(StorageError::Conflict)
+        ));
+
+        let next = storage.acquire_lease("segment5.lock", ttl).await.unwrap();
+        assert_ne!(next.id, lease.id);
+        assert!(matches!(
+            storage.release_lease(lease).await,
+            Err(StorageError::Conflict)
+        ));
+        storage.release_lease(next).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn grants_a_lease_to_one_holder() {
+        exactly_one_holder(&MemoryStorageContainer::new()).await;
+        let dir = tempfile::tempdir().unwrap();
+        exactly_one_holder(&FsStorageContainer::new(dir.path())).await;
+    }
+
+    #[tokio::test]
+    async fn expired_leases_can_be_taken_over() {
+        let storage = MemoryStorageContainer::new();
+        let lease = storage
+            .acquire_lease("a.lock", Duration::from_millis(20))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        let next = storage
+            .acquire_lease("a.lock", Duration::from_secs(30))
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage.renew_lease(&lease).await,
+            Err(StorageError::Conflict)
+        ));
+        storage.renew_lease(&next).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn concurrent_acquires_have_one_winner() {
+        let storage = MemoryStorageContainer::new();
+        let acquires = (0..8).map(|_| storage.acquire_lease("a.lock", Duration::from_secs(30)));
+        let results = futures::future::join_all(acquires).await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 1);
+        assert!(results
+            .iter()
+            .all(|r| matches!(r, Ok(_) | Err(StorageError::Conflict))));
+    }
+}
@@ -1,11 +1,57 @@
 
-use std::pin::Pin;
+mod cache;
+mod checksum;
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+mod content_type;
+mod fs;
+mod instrument;
+mod lease;
+mod memory;
+mod presign;
+mod registry;
+mod retry;
+mod stream;
+mod upload;
+mod watch;
+
+use std::{
+    io::ErrorKind,
+    pin::Pin,
+    time::{Duration, SystemTime},
+};
 
 use async_trait::async_trait;
 use bytes::Bytes;
-use futures::Stream;
+use futures::{Stream, StreamExt, TryStreamExt};
 use thiserror::Error;
 
+pub use cache::{CachePolicy, CachingStorageContainer, StorageCache};
+pub use checksum::{verify_stream, ChecksumHasher, Checksums, VerifyingStorageContainer};
+pub use content_type::guess_content_type;
+pub use fs::FsStorageContainer;
+pub use instrument::{
+    InstrumentedStorageContainer, DURATION_METRIC, ERRORS_METRIC, FIRST_BYTE_METRIC,
+    READ_BYTES_METRIC, WRITTEN_BYTES_METRIC,
+};
+pub use lease::Lease;
+pub use memory::MemoryStorageContainer;
+pub use presign::{
+    Permissions, UrlSigner, DIGEST_QUERY, EXPIRES_QUERY, OPERATION_QUERY, PERMISSIONS_QUERY,
+    SIGNATURE_QUERY,
+};
+pub use registry::{invalid_url, BoxedStorageContainer, StorageFactory, StorageRegistry};
+pub use retry::{RetryPolicy, RetryStorageContainer};
+pub use stream::{enforce_throughput, slice_stream, MinThroughput};
+pub use upload::{upload_blocks, BlockUpload, UploadPolicy};
+pub use watch::{poll_changes, ChangeEvent, ChangeKind, WatchStream};
+pub use url::Url;
+
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// How often [`StorageContainer::wait_for`] checks backends that can't watch.
+pub const WAIT_POLL_INTERVAL: Duration = Duration::from_millis(500);
+
 #[derive(Error, Debug)]
 pub enum StorageError {
     #[error("The file or directory is not found!")]
@@ -14,32 +60,584 @@ pub enum StorageError {
     #[error("Authentication error!")]
     AuthenticationError,
 
+    #[error("The object is in a conflicting state!")]
+    Conflict,
+
+    #[error("The precondition for the operation failed!")]
+    PreconditionFailed,
+
+    #[error("The operation is not supported!")]
+    Unsupported,
+
+    #[error("The content does not match its checksum!")]
+    IntegrityMismatch,
+
+    #[error("The operation timed out!")]
+    Timeout(#[source] BoxError),
+
+    #[error("The request was throttled!")]
+    Throttled(#[source] BoxError),
+
+    #[error("The service is unavailable!")]
+    ServiceUnavailable(#[source] BoxError),
+
     #[error("HTTP error")]
-    HttpError(String),
+    HttpError(#[source] BoxError),
 
     #[error("Other error")]
     Other(#[from]std::io::Error)
 }
 
+impl StorageError {
+    /// Whether the operation may succeed if it is tried again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Timeout(_) | Self::Throttled(_) | Self::ServiceUnavailable(_) => true,
+            Self::Other(e) => matches!(
+                e.kind(),
+                ErrorKind::ConnectionReset
+                    | ErrorKind::ConnectionAborted
+                    | ErrorKind::ConnectionRefused
+                    | ErrorKind::BrokenPipe
+                    | ErrorKind::TimedOut
+                    | ErrorKind::Interrupted
+                    | ErrorKind::UnexpectedEof
+            ),
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    GetContent,
+    GetMetadata,
+    SetContent,
+    SetMetadata,
+    Exists,
+    Stat,
+    List,
+    Delete,
+    Copy,
+    Rename,
+    Append,
+    Watch,
+    Lease,
+}
+
 pub type StreamType = Pin<Box<dyn Stream<Item = std::result::Result<Bytes, StorageError>> + Send + Sync>>;
 
+/// An entry returned when listing a container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectInfo {
+    pub name: String,
+    pub size: u64,
+    pub last_modified: Option<SystemTime>,
+}
+
+/// The properties of a single object.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ObjectProperties {
+    pub content_length: u64,
+    pub etag: Option<String>,
+    pub content_type: Option<String>,
+    pub last_modified: Option<SystemTime>,
+    pub metadata: Option<String>,
+    /// The hashes stored when the content was written, see [`Checksums`].
+    pub checksums: Checksums,
+}
+
+/// A precondition for a conditional write, evaluated against the ETag
+/// returned by [`StorageContainer::stat`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteCondition {
+    /// The object must exist and its ETag must match (`If-Match`).
+    IfMatch(String),
+    /// The object must not exist yet (`If-None-Match: *`). For metadata this
+    /// means the object must not have any metadata yet.
+    IfNoneMatch,
+}
+
+/// A stream of listing pages. Pages are fetched lazily as the stream is polled.
+pub type ListStream = Pin<Box<dyn Stream<Item = Result<Vec<ObjectInfo>, StorageError>> + Send>>;
+
+/// An object that is written incrementally while readers may already tail it,
+/// see [`StorageContainer::open_append`].
+///
+/// Dropping a session without sealing it abandons the object. Readers tailing
+/// it then fail or stop, depending on the backend.
+#[async_trait]
+pub trait AppendSession: Send + Sync {
+    /// Appends `chunk` to the end of the object.
+    async fn append(&mut self, chunk: Bytes) -> Result<(), StorageError>;
+
+    /// Marks the object as complete, so tailing readers finish once they
+    /// reach its end.
+    async fn seal(self: Box<Self>) -> Result<(), StorageError>;
+}
+
 #[async_trait]
 pub trait StorageContainer {
     async fn get_content(&self, path: &str) -> Result<StreamType, StorageError>;
+
+    /// Reads `length` bytes starting at `offset`, or up to the end of the
+    /// object when `length` is `None`.
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        let content = self.get_content(path).await?;
+        Ok(slice_stream(content, offset, length))
+    }
+
     async fn get_metadata(&self, path: &str) -> Result<String, StorageError>;
     async fn set_content(
         &self,
         path: &str,
         content: StreamType,
     ) -> Result<(), StorageError>;
+    /// Replaces the metadata of the object at `path`, failing with
+    /// [`StorageError::NotFound`] when there is no such object.
     async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError>;
-    async fn exists(&self, path:&str) -> bool;
+
+    /// Whether there is an object at `path`. Fails instead of answering
+    /// `false` when the backend can't tell, e.g. because it is unreachable.
+    async fn exists(&self, path: &str) -> Result<bool, StorageError>;
+
+    /// Writes the content only if `condition` holds, failing with
+    /// [`StorageError::PreconditionFailed`] otherwise.
+    async fn set_content_if(
+        &self,
+        _path: &str,
+        _content: StreamType,
+        _condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Writes the metadata only if `condition` holds, failing with
+    /// [`StorageError::PreconditionFailed`] otherwise.
+    async fn set_metadata_if(
+        &self,
+        _path: &str,
+        _metadata: String,
+        _condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Returns the properties of the object at `path`, including its metadata.
+    async fn stat(&self, _path: &str) -> Result<ObjectProperties, StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Lists the objects whose name starts with `prefix`, in lexicographic order.
+    /// The S3 and Azure backends fetch pages as the stream is read, while the
+    /// file system and memory containers collect the whole listing up front.
+    async fn list(&self, _prefix: &str) -> Result<ListStream, StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Deletes the object at `path` together with its metadata.
+    async fn delete(&self, _path: &str) -> Result<(), StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Deletes every object whose name starts with `prefix` and returns how
+    /// many were deleted. The default needs [`StorageContainer::list`] and
+    /// [`StorageContainer::delete`], and fails with
+    /// [`StorageError::Unsupported`] before deleting anything without them.
+    async fn delete_prefix(&self, prefix: &str) -> Result<usize, StorageError> {
+        let mut deleted = 0;
+        for object in self.list_all(prefix).await? {
+            match self.delete(&object.name).await {
+                Ok(()) => deleted += 1,
+                // deleted concurrently by someone else.
+                Err(StorageError::NotFound) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(deleted)
+    }
+
+    /// Copies the content and metadata of `from` to `to`, replacing `to`.
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let content = self.get_content(from).await?;
+        self.set_content(to, content).await?;
+        match self.get_metadata(from).await {
+            Ok(metadata) => self.set_metadata(to, metadata).await,
+            Err(StorageError::NotFound) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Moves `from` to `to`, replacing `to`. The default copies and then
+    /// deletes, so it needs [`StorageContainer::delete`]; without it this
+    /// fails with [`StorageError::Unsupported`] and leaves the copy at `to`.
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.copy(from, to).await?;
+        self.delete(from).await
+    }
+
+    /// Replaces the content at `path` with an empty object that is open for
+    /// appending until the returned session is sealed. The metadata is kept.
+    async fn open_append(&self, _path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Like [`StorageContainer::get_content`], but if the object is still
+    /// open for appending the stream keeps yielding the appended bytes until
+    /// it is sealed. Backends without appends only have complete objects.
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.get_content(path).await
+    }
+
+    /// Starts writing `path` as blocks that are staged separately and
+    /// replace the content once committed, for backends with multipart or
+    /// block uploads. The metadata is kept.
+    async fn start_block_upload(
+        &self,
+        _path: &str,
+    ) -> Result<Box<dyn BlockUpload>, StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Writes complete content by staging blocks of it concurrently, see
+    /// [`UploadPolicy`]. Falls back to [`StorageContainer::set_content`] if
+    /// the backend has no block uploads.
+    async fn set_content_parallel(
+        &self,
+        path: &str,
+        content: StreamType,
+        policy: &UploadPolicy,
+    ) -> Result<(), StorageError> {
+        match self.start_block_upload(path).await {
+            Ok(upload) => upload_blocks(upload, content, policy).await,
+            Err(StorageError::Unsupported) => self.set_content(path, content).await,
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Returns a URL that grants `permissions` on the object at `path` to
+    /// whoever holds it, until `expiry` from now. A `path` ending in `/`
+    /// covers every object below it. Backends that can't scope a URL that way
+    /// either cover more or fail with [`StorageError::Unsupported`].
+    async fn presign(
+        &self,
+        _path: &str,
+        _permissions: Permissions,
+        _expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Takes a lease on the object at `path` that lasts `ttl` unless it is
+    /// renewed, failing with [`StorageError::Conflict`] while someone else
+    /// holds it. The object serves as the lock, its content is managed by
+    /// the lease and should not be written otherwise.
+    ///
+    /// Backends without leases of their own record the holder in the object
+    /// with conditional writes.
+    async fn acquire_lease(&self, path: &str, ttl: Duration) -> Result<Lease, StorageError> {
+        lease::acquire(self, path, ttl).await
+    }
+
+    /// Extends `lease` by its TTL from now, failing with
+    /// [`StorageError::Conflict`] if it was lost in the meantime.
+    async fn renew_lease(&self, lease: &Lease) -> Result<(), StorageError> {
+        lease::renew(self, lease).await
+    }
+
+    /// Gives up `lease` so others can take it right away.
+    async fn release_lease(&self, lease: Lease) -> Result<(), StorageError> {
+        lease::release(self, lease).await
+    }
+
+    /// Streams the changes to objects whose name starts with `prefix` that
+    /// happen after this returns.
+    async fn watch(&self, _prefix: &str) -> Result<WatchStream, StorageError> {
+        Err(StorageError::Unsupported)
+    }
+
+    /// Waits until the object at `path` exists. Transient errors of the
+    /// watch are skipped. Backends without [`StorageContainer::watch`] are
+    /// polled every [`WAIT_POLL_INTERVAL`].
+    async fn wait_for(&self, path: &str) -> Result<(), StorageError> {
+        if self.exists(path).await? {
+            return Ok(());
+        }
+        let mut changes = match self.watch(path).await {
+            Ok(changes) => changes,
+            Err(StorageError::Unsupported) => loop {
+                tokio::time::sleep(WAIT_POLL_INTERVAL).await;
+                if self.exists(path).await? {
+                    return Ok(());
+                }
+            },
+            Err(e) => return Err(e),
+        };
+        // it may have appeared before the watch started.
+        if self.exists(path).await? {
+            return Ok(());
+        }
+        while let Some(change) = changes.next().await {
+            match change {
+                Ok(change) if change.name == path && change.kind != ChangeKind::Deleted => {
+                    return Ok(())
+                }
+                Ok(_) => {}
+                Err(e) if e.is_retryable() => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Err(StorageError::NotFound)
+    }
+
+    /// Collects every page of [`StorageContainer::list`].
+    async fn list_all(&self, prefix: &str) -> Result<Vec<ObjectInfo>, StorageError> {
+        let pages: Vec<_> = self.list(prefix).await?.try_collect().await?;
+        Ok(pages.concat())
+    }
+}
+
+/// Lets decorators wrap containers opened through a [`StorageRegistry`].
+#[async_trait]
+impl<S> StorageContainer for Box<S>
+where
+    S: StorageContainer + Send + Sync + ?Sized,
+{
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        (**self).get_content(path).await
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        (**self).get_content_range(path, offset, length).await
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        (**self).get_metadata(path).await
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        (**self).set_content(path, content).await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        (**self).set_metadata(path, metadata).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        (**self).exists(path).await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        (**self).set_content_if(path, content, condition).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        (**self).set_metadata_if(path, metadata, condition).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        (**self).stat(path).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        (**self).list(prefix).await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        (**self).delete(path).await
+    }
+
+    async fn delete_prefix(&self, prefix: &str) -> Result<usize, StorageError> {
+        (**self).delete_prefix(prefix).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        (**self).copy(from, to).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        (**self).rename(from, to).await
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        (**self).open_append(path).await
+    }
+
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        (**self).get_content_tail(path).await
+    }
+
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        (**self).start_block_upload(path).await
+    }
+
+    async fn set_content_parallel(
+        &self,
+        path: &str,
+        content: StreamType,
+        policy: &UploadPolicy,
+    ) -> Result<(), StorageError> {
+        (**self).set_content_parallel(path, content, policy).await
+    }
+
+    async fn acquire_lease(&self, path: &str, ttl: Duration) -> Result<Lease, StorageError> {
+        (**self).acquire_lease(path, ttl).await
+    }
+
+    async fn renew_lease(&self, lease: &Lease) -> Result<(), StorageError> {
+        (**self).renew_lease(lease).await
+    }
+
+    async fn release_lease(&self, lease: Lease) -> Result<(), StorageError> {
+        (**self).release_lease(lease).await
+    }
+
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        (**self).presign(path, permissions, expiry).await
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        (**self).watch(prefix).await
+    }
+
+    async fn wait_for(&self, path: &str) -> Result<(), StorageError> {
+        (**self).wait_for(path).await
+    }
+
+    async fn list_all(&self, prefix: &str) -> Result<Vec<ObjectInfo>, StorageError> {
+        (**self).list_all(prefix).await
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::error::Error;
 
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn classifies_retryable_errors() {
+        assert!(StorageError::Throttled("slow down".into()).is_retryable());
+        assert!(StorageError::Timeout("deadline".into()).is_retryable());
+        assert!(StorageError::Other(ErrorKind::ConnectionReset.into()).is_retryable());
+        assert!(!StorageError::Other(ErrorKind::InvalidInput.into()).is_retryable());
+        assert!(!StorageError::NotFound.is_retryable());
+        assert!(!StorageError::PreconditionFailed.is_retryable());
+    }
+
+    #[test]
+    fn keeps_the_source_error() {
+        let error = StorageError::ServiceUnavailable("503 from upstream".into());
+        assert_eq!(error.source().unwrap().to_string(), "503 from upstream");
+    }
+
+    /// Implements only what every container has to.
+    #[derive(Default)]
+    struct Minimal {
+        objects: std::sync::Mutex<std::collections::HashMap<String, (Bytes, Option<String>)>>,
+    }
+
+    #[async_trait]
+    impl StorageContainer for Minimal {
+        async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+            let objects = self.objects.lock().unwrap();
+            let (content, _) = objects.get(path).ok_or(StorageError::NotFound)?;
+            Ok(Box::pin(futures::stream::iter([Ok(content.clone())])))
+        }
+
+        async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+            let objects = self.objects.lock().unwrap();
+            objects
+                .get(path)
+                .and_then(|(_, metadata)| metadata.clone())
+                .ok_or(StorageError::NotFound)
+        }
+
+        async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+            let chunks: Vec<Bytes> = content.try_collect().await?;
+            let mut objects = self.objects.lock().unwrap();
+            objects.entry(path.to_owned()).or_default().0 = chunks.concat().into();
+            Ok(())
+        }
+
+        async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+            let mut objects = self.objects.lock().unwrap();
+            let (_, existing) = objects.get_mut(path).ok_or(StorageError::NotFound)?;
+            *existing = Some(metadata);
+            Ok(())
+        }
+
+        async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+            Ok(self.objects.lock().unwrap().contains_key(path))
+        }
+    }
+
+    fn body(content: &'static str) -> StreamType {
+        Box::pin(futures::stream::iter([Ok(Bytes::from(content))]))
+    }
+
+    #[tokio::test]
+    async fn defaults_report_what_a_minimal_container_lacks() {
+        let storage = Minimal::default();
+        storage.set_content("a", body("abc")).await.unwrap();
+        storage.set_metadata("a", "meta".into()).await.unwrap();
+
+        storage.copy("a", "b").await.unwrap();
+        assert_eq!(storage.get_metadata("b").await.unwrap(), "meta");
+        let range = storage.get_content_range("b", 1, Some(1)).await.unwrap();
+        let chunks: Vec<Bytes> = range.try_collect().await.unwrap();
+        assert_eq!(chunks.concat(), b"b");
+        assert!(matches!(
+            storage.delete_prefix("").await,
+            Err(StorageError::Unsupported)
+        ));
+        assert!(matches!(
+            storage.rename("a", "c").await,
+            Err(StorageError::Unsupported)
+        ));
+        assert!(storage.exists("a").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn waits_for_objects_without_a_watch() {
+        let storage = std::sync::Arc::new(Minimal::default());
+        storage.set_content("a", body("abc")).await.unwrap();
+        storage.wait_for("a").await.unwrap();
+
+        let wait = {
+            let storage = storage.clone();
+            tokio::spawn(async move { storage.wait_for("b").await })
+        };
+        tokio::time::sleep(WAIT_POLL_INTERVAL / 2).await;
+        assert!(!wait.is_finished());
+        storage.set_content("b", body("abc")).await.unwrap();
+        wait.await.unwrap().unwrap();
+    }
 }
@@ -0,0 +1,1002 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    io::ErrorKind,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::sync::{broadcast, watch};
+
+use crate::{
+    guess_content_type, stream::paginate, AppendSession, BlockUpload, ChangeEvent, ChangeKind,
+    ChecksumHasher, Checksums, ListStream, ObjectInfo, ObjectProperties, Operation,
+    StorageContainer, StorageError, StreamType, WatchStream, WriteCondition,
+};
+
+/// How many changes a slow watcher may fall behind before it misses some.
+const EVENT_CAPACITY: usize = 1024;
+
+static VERSION: AtomicU64 = AtomicU64::new(1);
+
+fn next_version() -> u64 {
+    VERSION.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ContentStatus {
+    Writing,
+    Complete,
+    Failed,
+}
+
+struct ContentState {
+    chunks: Vec<Bytes>,
+    status: ContentStatus,
+    modified: SystemTime,
+    /// Set once the content is complete.
+    checksums: Checksums,
+}
+
+struct Content {
+    state: watch::Sender<ContentState>,
+}
+
+#[derive(Default)]
+struct Object {
+    content: Option<Arc<Content>>,
+    metadata: Option<String>,
+    /// Changes on every write of the content or the metadata.
+    version: u64,
+}
+
+#[derive(Default)]
+struct Faults {
+    latency: Option<Duration>,
+    errors: HashMap<Operation, VecDeque<StorageError>>,
+}
+
+/// A thread-safe storage container that keeps everything in memory.
+///
+/// A new object becomes visible as soon as its write starts, so readers can
+/// tail it while it is still being streamed in. Overwrites are staged and
+/// replace the previous content only once they complete; a write that fails
+/// or is dropped leaves the previous content, or no object, behind. Listing
+/// returns a snapshot of the matching objects taken when it is called. Cloning
+/// the container shares the underlying objects.
+#[derive(Clone)]
+pub struct MemoryStorageContainer {
+    objects: Objects,
+    faults: Arc<Mutex<Faults>>,
+    events: broadcast::Sender<ChangeEvent>,
+}
+
+impl Default for MemoryStorageContainer {
+    fn default() -> Self {
+        Self {
+            objects: Default::default(),
+            faults: Default::default(),
+            events: broadcast::channel(EVENT_CAPACITY).0,
+        }
+    }
+}
+
+type Objects = Arc<Mutex<HashMap<String, Object>>>;
+
+/// Marks the content as failed if a write is dropped before it completes.
+struct WriteGuard {
+    content: Arc<Content>,
+    /// Where the content was published while still being written, so a
+    /// failed write can take it down again.
+    published: Option<(Objects, String)>,
+}
+
+impl Drop for WriteGuard {
+    fn drop(&mut self) {
+        let failed = self.content.state.send_if_modified(|state| {
+            if state.status == ContentStatus::Writing {
+                state.status = ContentStatus::Failed;
+                return true;
+            }
+            false
+        });
+        let Some((objects, path)) = self.published.as_ref().filter(|_| failed) else {
+            return;
+        };
+        // unless another writer replaced it already.
+        let mut objects = objects.lock().unwrap();
+        if let Some(object) = objects.get_mut(path) {
+            if object
+                .content
+                .as_ref()
+                .is_some_and(|c| Arc::ptr_eq(c, &self.content))
+            {
+                object.content = None;
+                if object.metadata.is_none() {
+                    objects.remove(path);
+                }
+            }
+        }
+    }
+}
+
+impl Content {
+    fn new(status: ContentStatus) -> Self {
+        let (state, _) = watch::channel(ContentState {
+            chunks: Vec::new(),
+            status,
+            modified: SystemTime::now(),
+            checksums: Checksums::default(),
+        });
+        Self { state }
+    }
+
+    fn info(&self, name: &str) -> ObjectInfo {
+        let state = self.state.borrow();
+        ObjectInfo {
+            name: name.to_owned(),
+            size: state.chunks.iter().map(|c| c.len() as u64).sum(),
+            last_modified: Some(state.modified),
+        }
+    }
+
+    fn read(&self) -> StreamType {
+        let receiver = self.state.subscribe();
+        let stream = futures::stream::unfold(Some((receiver, 0)), |state| async move {
+            let (mut receiver, index) = state?;
+            loop {
+                {
+                    let state = receiver.borrow_and_update();
+                    if let Some(chunk) = state.chunks.get(index) {
+                        let chunk = chunk.clone();
+                        drop(state);
+                        return Some((Ok(chunk), Some((receiver, index + 1))));
+                    }
+                    match state.status {
+                        ContentStatus::Complete => return None,
+                        ContentStatus::Failed => return Some((Err(write_failed()), None)),
+                        ContentStatus::Writing => {}
+                    }
+                }
+                if receiver.changed().await.is_err() {
+                    return Some((Err(write_failed()), None));
+                }
+            }
+        });
+        Box::pin(stream)
+    }
+}
+
+/// Appends to content that readers see as still being written.
+struct MemoryAppendSession {
+    storage: MemoryStorageContainer,
+    path: String,
+    guard: WriteGuard,
+    hasher: ChecksumHasher,
+}
+
+#[async_trait]
+impl AppendSession for MemoryAppendSession {
+    async fn append(&mut self, chunk: Bytes) -> Result<(), StorageError> {
+        self.storage.apply_faults(Operation::Append).await?;
+        self.hasher.update(&chunk);
+        self.guard
+            .content
+            .state
+            .send_modify(|state| state.chunks.push(chunk));
+        Ok(())
+    }
+
+    async fn seal(self: Box<Self>) -> Result<(), StorageError> {
+        self.storage.apply_faults(Operation::Append).await?;
+        let checksums = self.hasher.finish();
+        self.guard.content.state.send_modify(|state| {
+            state.status = ContentStatus::Complete;
+            state.modified = SystemTime::now();
+            state.checksums = checksums;
+        });
+        self.storage.notify(&self.path, ChangeKind::Updated);
+        Ok(())
+    }
+}
+
+/// Keeps staged blocks aside until they are committed as a regular write.
+struct MemoryBlockUpload {
+    storage: MemoryStorageContainer,
+    path: String,
+    blocks: Mutex<BTreeMap<usize, Bytes>>,
+}
+
+#[async_trait]
+impl BlockUpload for MemoryBlockUpload {
+    async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError> {
+        self.storage.apply_faults(Operation::SetContent).await?;
+        self.blocks.lock().unwrap().insert(index, block);
+        Ok(())
+    }
+
+    async fn commit(
+        self: Box<Self>,
+        blocks: usize,
+        _checksums: Checksums,
+    ) -> Result<(), StorageError> {
+        let mut staged = self.blocks.into_inner().unwrap();
+        let content: Vec<_> = (0..blocks)
+            .map(|index| {
+                staged.remove(&index).ok_or_else(|| {
+                    StorageError::Other(std::io::Error::new(
+                        ErrorKind::InvalidInput,
+                        format!("block {index} was never staged"),
+                    ))
+                })
+            })
+            .collect::<Result<_, _>>()?;
+        let content = futures::stream::iter(content.into_iter().map(Ok));
+        self.storage
+            .write_content(&self.path, Box::pin(content), None)
+            .await
+    }
+}
+
+fn write_failed() -> StorageError {
+    StorageError::Other(std::io::Error::new(
+        ErrorKind::UnexpectedEof,
+        "the writer failed before completing the content",
+    ))
+}
+
+impl MemoryStorageContainer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays every subsequent operation by `latency`.
+    pub fn set_latency(&self, latency: Option<Duration>) {
+        self.faults.lock().unwrap().latency = latency;
+    }
+
+    /// Fails the next call of `operation` with `error`. Errors queue up in the
+    /// order they are injected.
+    pub fn inject_error(&self, operation: Operation, error: StorageError) {
+        self.faults
+            .lock()
+            .unwrap()
+            .errors
+            .entry(operation)
+            .or_default()
+            .push_back(error);
+    }
+
+    fn notify(&self, path: &str, kind: ChangeKind) {
+        // fails only if nobody is watching.
+        let _ = self.events.send(ChangeEvent::new(path, kind));
+    }
+
+    fn created_or_updated(existed: bool) -> ChangeKind {
+        if existed {
+            ChangeKind::Updated
+        } else {
+            ChangeKind::Created
+        }
+    }
+
+    /// Removes any injected latency and pending errors.
+    pub fn clear_faults(&self) {
+        *self.faults.lock().unwrap() = Faults::default();
+    }
+
+    async fn apply_faults(&self, operation: Operation) -> Result<(), StorageError> {
+        let (latency, error) = {
+            let mut faults = self.faults.lock().unwrap();
+            let error = faults
+                .errors
+                .get_mut(&operation)
+                .and_then(|errors| errors.pop_front());
+            (faults.latency, error)
+        };
+        if let Some(latency) = latency {
+            tokio::time::sleep(latency).await;
+        }
+        match error {
+            Some(error) => Err(error),
+            None => Ok(()),
+        }
+    }
+
+    fn check_condition(
+        object: &Object,
+        condition: Option<WriteCondition>,
+        metadata: bool,
+    ) -> Result<(), StorageError> {
+        let holds = match condition {
+            None => true,
+            Some(WriteCondition::IfNoneMatch) if metadata => object.metadata.is_none(),
+            Some(WriteCondition::IfNoneMatch) => object.content.is_none(),
+            Some(WriteCondition::IfMatch(etag)) => {
+                object.content.is_some() && format!("{:x}", object.version) == etag
+            }
+        };
+        if holds {
+            Ok(())
+        } else {
+            Err(StorageError::PreconditionFailed)
+        }
+    }
+
+    async fn write_content(
+        &self,
+        path: &str,
+        mut content: StreamType,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        let mut guard = WriteGuard {
+            content: Arc::new(Content::new(ContentStatus::Writing)),
+            published: None,
+        };
+        let version = {
+            let mut objects = self.objects.lock().unwrap();
+            let missing = Object::default();
+            let current = objects.get(path).unwrap_or(&missing);
+            Self::check_condition(current, condition.clone(), false)?;
+            let object = objects.entry(path.to_owned()).or_default();
+            if object.content.is_none() {
+                // nothing to keep, so readers may follow the write.
+                object.content = Some(guard.content.clone());
+                object.version = next_version();
+                guard.published = Some((self.objects.clone(), path.to_owned()));
+            }
+            object.version
+        };
+
+        let mut hasher = ChecksumHasher::new();
+        while let Some(chunk) = content.next().await {
+            let chunk = chunk?;
+            hasher.update(&chunk);
+            guard
+                .content
+                .state
+                .send_modify(|state| state.chunks.push(chunk));
+        }
+        let checksums = hasher.finish();
+        let existed = {
+            let mut objects = self.objects.lock().unwrap();
+            let current = objects.get(path);
+            let published = current
+                .and_then(|o| o.content.as_ref())
+                .is_some_and(|c| Arc::ptr_eq(c, &guard.content));
+            // a staged write must not replace what changed since it started.
+            if condition.is_some() && !published && current.map(|o| o.version) != Some(version) {
+                return Err(StorageError::PreconditionFailed);
+            }
+            let object = objects.entry(path.to_owned()).or_default();
+            guard.content.state.send_modify(|state| {
+                state.status = ContentStatus::Complete;
+                state.modified = SystemTime::now();
+                state.checksums = checksums;
+            });
+            let existed = object.content.is_some() && !published;
+            if !published {
+                object.content = Some(guard.content.clone());
+                object.version = next_version();
+            }
+            existed
+        };
+        self.notify(path, Self::created_or_updated(existed));
+        Ok(())
+    }
+
+    fn write_metadata(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        let mut objects = self.objects.lock().unwrap();
+        let object = match objects.get_mut(path) {
+            Some(object) if object.content.is_some() => object,
+            _ => return Err(StorageError::NotFound),
+        };
+        Self::check_condition(object, condition, true)?;
+        object.metadata = Some(metadata);
+        object.version = next_version();
+        self.notify(path, ChangeKind::Updated);
+        Ok(())
+    }
+
+    fn get_object_content(&self, path: &str) -> Option<Arc<Content>> {
+        let objects = self.objects.lock().unwrap();
+        objects.get(path).and_then(|o| o.content.clone())
+    }
+}
+
+#[async_trait]
+impl StorageContainer for MemoryStorageContainer {
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.apply_faults(Operation::GetContent).await?;
+        let content = self
+            .get_object_content(path)
+            .ok_or(StorageError::NotFound)?;
+        Ok(content.read())
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        self.apply_faults(Operation::GetMetadata).await?;
+        let objects = self.objects.lock().unwrap();
+        objects
+            .get(path)
+            .and_then(|o| o.metadata.clone())
+            .ok_or(StorageError::NotFound)
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        self.apply_faults(Operation::SetContent).await?;
+        self.write_content(path, content, None).await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        self.apply_faults(Operation::SetMetadata).await?;
+        self.write_metadata(path, metadata, None)
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.apply_faults(Operation::SetContent).await?;
+        self.write_content(path, content, Some(condition)).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.apply_faults(Operation::SetMetadata).await?;
+        self.write_metadata(path, metadata, Some(condition))
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.apply_faults(Operation::Exists).await?;
+        Ok(self.get_object_content(path).is_some())
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        self.apply_faults(Operation::Stat).await?;
+        let objects = self.objects.lock().unwrap();
+        let object = objects.get(path).ok_or(StorageError::NotFound)?;
+        let content = object.content.as_ref().ok_or(StorageError::NotFound)?;
+        let info = content.info(path);
+        let checksums = content.state.borrow().checksums;
+        Ok(ObjectProperties {
+            content_length: info.size,
+            etag: Some(format!("{:x}", object.version)),
+            content_type: guess_content_type(path).map(str::to_owned),
+            last_modified: info.last_modified,
+            metadata: object.metadata.clone(),
+            checksums,
+        })
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.apply_faults(Operation::Delete).await?;
+        let mut objects = self.objects.lock().unwrap();
+        match objects.get(path) {
+            Some(object) if object.content.is_some() => {
+                objects.remove(path);
+                self.notify(path, ChangeKind::Deleted);
+                Ok(())
+            }
+            _ => Err(StorageError::NotFound),
+        }
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.apply_faults(Operation::Copy).await?;
+        let mut objects = self.objects.lock().unwrap();
+        let source = objects.get(from).ok_or(StorageError::NotFound)?;
+        let content = source.content.clone().ok_or(StorageError::NotFound)?;
+        let metadata = source.metadata.clone();
+        let target = objects.entry(to.to_owned()).or_default();
+        let existed = target.content.is_some();
+        target.content = Some(content);
+        target.metadata = metadata;
+        target.version = next_version();
+        self.notify(to, Self::created_or_updated(existed));
+        Ok(())
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.apply_faults(Operation::Rename).await?;
+        let mut objects = self.objects.lock().unwrap();
+        match objects.get(from) {
+            Some(object) if object.content.is_some() => {}
+            _ => return Err(StorageError::NotFound),
+        }
+        let mut object = objects.remove(from).unwrap_or_default();
+        object.version = next_version();
+        let existed = objects
+            .insert(to.to_owned(), object)
+            .is_some_and(|o| o.content.is_some());
+        self.notify(from, ChangeKind::Deleted);
+        self.notify(to, Self::created_or_updated(existed));
+        Ok(())
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        self.apply_faults(Operation::Append).await?;
+        let guard = WriteGuard {
+            content: Arc::new(Content::new(ContentStatus::Writing)),
+            published: None,
+        };
+        let existed = {
+            let mut objects = self.objects.lock().unwrap();
+            let object = objects.entry(path.to_owned()).or_default();
+            let existed = object.content.is_some();
+            object.content = Some(guard.content.clone());
+            object.version = next_version();
+            existed
+        };
+        self.notify(path, Self::created_or_updated(existed));
+        Ok(Box::new(MemoryAppendSession {
+            storage: self.clone(),
+            path: path.to_owned(),
+            guard,
+            hasher: ChecksumHasher::new(),
+        }))
+    }
+
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        self.apply_faults(Operation::SetContent).await?;
+        Ok(Box::new(MemoryBlockUpload {
+            storage: self.clone(),
+            path: path.to_owned(),
+            blocks: Mutex::default(),
+        }))
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        self.apply_faults(Operation::Watch).await?;
+        let receiver = self.events.subscribe();
+        let prefix = prefix.to_owned();
+        let stream = futures::stream::unfold(receiver, move |mut receiver| {
+            let prefix = prefix.clone();
+            async move {
+                loop {
+                    match receiver.recv().await {
+                        Ok(event) if event.name.starts_with(&prefix) => {
+                            return Some((Ok(event), receiver))
+                        }
+                        Ok(_) => {}
+                        Err(broadcast::error::RecvError::Lagged(missed)) => {
+                            let error = std::io::Error::other(format!(
+                                "the watcher missed {missed} changes"
+                            ));
+                            return Some((Err(error.into()), receiver));
+                        }
+                        Err(broadcast::error::RecvError::Closed) => return None,
+                    }
+                }
+            }
+        });
+        Ok(Box::pin(stream))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        self.apply_faults(Operation::List).await?;
+        let objects: Vec<_> = self
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(name, _)| name.starts_with(prefix))
+            .filter_map(|(name, object)| object.content.as_ref().map(|c| c.info(name)))
+            .collect();
+        Ok(paginate(objects))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{upload_blocks, UploadPolicy};
+    use futures::{SinkExt, TryStreamExt};
+
+    async fn read_all(storage: &MemoryStorageContainer, path: &str) -> Vec<u8> {
+        let chunks: Vec<Bytes> = storage
+            .get_content(path)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat()
+    }
+
+    #[tokio::test]
+    async fn conforms() {
+        crate::conformance::run(&MemoryStorageContainer::new(), "conformance/").await;
+    }
+
+    #[tokio::test]
+    async fn round_trip_content_and_metadata() {
+        let storage = MemoryStorageContainer::new();
+        let content = futures::stream::iter(vec![Ok(Bytes::from("abc")), Ok(Bytes::from("def"))]);
+        storage
+            .set_content("a.ts", Box::pin(content))
+            .await
+            .unwrap();
+        storage.set_metadata("a.ts", "meta".into()).await.unwrap();
+
+        assert!(storage.exists("a.ts").await.unwrap());
+        assert_eq!(read_all(&storage, "a.ts").await, b"abcdef");
+        assert_eq!(storage.get_metadata("a.ts").await.unwrap(), "meta");
+        assert!(matches!(
+            storage.get_content("b.ts").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn readers_follow_an_active_writer() {
+        let storage = MemoryStorageContainer::new();
+        let (mut sender, receiver) =
+            futures::channel::mpsc::channel::<Result<Bytes, StorageError>>(4);
+        let writer = {
+            let storage = storage.clone();
+            tokio::spawn(async move { storage.set_content("live.ts", Box::pin(receiver)).await })
+        };
+
+        sender.send(Ok(Bytes::from("first"))).await.unwrap();
+        while !storage.exists("live.ts").await.unwrap() {
+            tokio::task::yield_now().await;
+        }
+        let reader = {
+            let storage = storage.clone();
+            tokio::spawn(async move { read_all(&storage, "live.ts").await })
+        };
+        sender.send(Ok(Bytes::from("second"))).await.unwrap();
+        drop(sender);
+
+        writer.await.unwrap().unwrap();
+        assert_eq!(reader.await.unwrap(), b"firstsecond");
+    }
+
+    #[tokio::test]
+    async fn failed_writer_fails_readers() {
+        let storage = MemoryStorageContainer::new();
+        let (mut sender, receiver) =
+            futures::channel::mpsc::channel::<Result<Bytes, StorageError>>(4);
+        let writer = {
+            let storage = storage.clone();
+            tokio::spawn(async move { storage.set_content("live.ts", Box::pin(receiver)).await })
+        };
+        sender.send(Ok(Bytes::from("first"))).await.unwrap();
+        while !storage.exists("live.ts").await.unwrap() {
+            tokio::task::yield_now().await;
+        }
+        let stream = storage.get_content("live.ts").await.unwrap();
+        sender
+            .send(Err(StorageError::AuthenticationError))
+            .await
+            .unwrap();
+
+        assert!(writer.await.unwrap().is_err());
+        assert!(stream.try_collect::<Vec<_>>().await.is_err());
+        assert!(!storage.exists("live.ts").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn failed_overwrite_keeps_previous_content() {
+        let storage = MemoryStorageContainer::new();
+        storage
+            .set_content("a.ts", Box::pin(futures::stream::iter([Ok(Bytes::from("old"))])))
+            .await
+            .unwrap();
+        let etag = storage.stat("a.ts").await.unwrap().etag;
+        let (mut sender, receiver) =
+            futures::channel::mpsc::channel::<Result<Bytes, StorageError>>(4);
+        let writer = {
+            let storage = storage.clone();
+            tokio::spawn(async move { storage.set_content("a.ts", Box::pin(receiver)).await })
+        };
+        sender.send(Ok(Bytes::from("new"))).await.unwrap();
+        tokio::task::yield_now().await;
+        assert_eq!(read_all(&storage, "a.ts").await, b"old");
+        sender
+            .send(Err(StorageError::AuthenticationError))
+            .await
+            .unwrap();
+
+        assert!(writer.await.unwrap().is_err());
+        assert_eq!(read_all(&storage, "a.ts").await, b"old");
+        assert_eq!(storage.stat("a.ts").await.unwrap().etag, etag);
+    }
+
+    #[tokio::test]
+    async fn cancelled_writes_leave_the_previous_state() {
+        let storage = MemoryStorageContainer::new();
+        storage
+            .set_content("old.ts", Box::pin(futures::stream::iter([Ok(Bytes::from("old"))])))
+            .await
+            .unwrap();
+        // the write of a new object is visible while it streams, the overwrite is not.
+        for path in ["old.ts", "new.ts"] {
+            let (mut sender, receiver) =
+                futures::channel::mpsc::channel::<Result<Bytes, StorageError>>(4);
+            let writer = {
+                let storage = storage.clone();
+                tokio::spawn(async move { storage.set_content(path, Box::pin(receiver)).await })
+            };
+            sender.send(Ok(Bytes::from("new"))).await.unwrap();
+            tokio::task::yield_now().await;
+            assert_eq!(read_all(&storage, "old.ts").await, b"old");
+            if path == "new.ts" {
+                while !storage.exists(path).await.unwrap() {
+                    tokio::task::yield_now().await;
+                }
+            }
+            writer.abort();
+            assert!(writer.await.unwrap_err().is_cancelled());
+        }
+
+        assert_eq!(read_all(&storage, "old.ts").await, b"old");
+        assert!(!storage.exists("new.ts").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn tails_appends_until_sealed() {
+        let storage = MemoryStorageContainer::new();
+        let mut session = storage.open_append("live.ts").await.unwrap();
+        storage.set_metadata("live.ts", "meta".into()).await.unwrap();
+        session.append(Bytes::from("abc")).await.unwrap();
+
+        let mut tail = storage.get_content_tail("live.ts").await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), "abc");
+        session.append(Bytes::from("def")).await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), "def");
+        session.seal().await.unwrap();
+        assert!(tail.try_next().await.unwrap().is_none());
+
+        assert_eq!(read_all(&storage, "live.ts").await, b"abcdef");
+        assert_eq!(storage.get_metadata("live.ts").await.unwrap(), "meta");
+    }
+
+    #[tokio::test]
+    async fn abandoned_appends_fail_readers() {
+        let storage = MemoryStorageContainer::new();
+        let mut session = storage.open_append("live.ts").await.unwrap();
+        session.append(Bytes::from("abc")).await.unwrap();
+        let tail = storage.get_content_tail("live.ts").await.unwrap();
+        drop(session);
+        let result: Result<Vec<Bytes>, _> = tail.try_collect().await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn watches_changes_by_prefix() {
+        let storage = MemoryStorageContainer::new();
+        let mut changes = storage.watch("level0/").await.unwrap();
+        let body = || Box::pin(futures::stream::iter([Ok(Bytes::from("abc"))])) as StreamType;
+        storage.set_content("level0/a.ts", body()).await.unwrap();
+        storage.set_content("level1/a.ts", body()).await.unwrap();
+        storage.set_content("level0/a.ts", body()).await.unwrap();
+        storage.rename("level0/a.ts", "level0/b.ts").await.unwrap();
+        storage.delete("level0/b.ts").await.unwrap();
+
+        let mut events = Vec::new();
+        for _ in 0..5 {
+            events.push(changes.next().await.unwrap().unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![
+                ChangeEvent::new("level0/a.ts", ChangeKind::Created),
+                ChangeEvent::new("level0/a.ts", ChangeKind::Updated),
+                ChangeEvent::new("level0/a.ts", ChangeKind::Deleted),
+                ChangeEvent::new("level0/b.ts", ChangeKind::Created),
+                ChangeEvent::new("level0/b.ts", ChangeKind::Deleted),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn waits_for_objects() {
+        let storage = MemoryStorageContainer::new();
+        let writer = storage.clone();
+        let wait = tokio::spawn(async move { storage.wait_for("segment0.ts").await });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(!wait.is_finished());
+        let body = futures::stream::iter([Ok(Bytes::from("abc"))]);
+        writer
+            .set_content("segment0.ts", Box::pin(body))
+            .await
+            .unwrap();
+        wait.await.unwrap().unwrap();
+        writer.wait_for("segment0.ts").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn injected_errors_fail_the_next_call() {
+        let storage = MemoryStorageContainer::new();
+        let content = futures::stream::iter(vec![Ok(Bytes::from("abc"))]);
+        storage.set_content("a.ts", Box::pin(content)).await.unwrap();
+        storage.set_metadata("a.ts", "meta".into()).await.unwrap();
+        storage.inject_error(Operation::GetMetadata, StorageError::AuthenticationError);
+
+        assert!(matches!(
+            storage.get_metadata("a.ts").await,
+            Err(StorageError::AuthenticationError)
+        ));
+        assert_eq!(storage.get_metadata("a.ts").await.unwrap(), "meta");
+    }
+
+    #[tokio::test]
+    async fn lists_objects_by_prefix() {
+        let storage = MemoryStorageContainer::new();
+        for path in ["b/2.ts", "b/1.ts", "a/1.ts"] {
+            let content = futures::stream::iter(vec![Ok(Bytes::from("data"))]);
+            storage.set_content(path, Box::pin(content)).await.unwrap();
+        }
+
+        let names: Vec<_> = storage
+            .list_all("b/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| (o.name, o.size))
+            .collect();
+        assert_eq!(
+            names,
+            vec![("b/1.ts".to_owned(), 4), ("b/2.ts".to_owned(), 4)]
+        );
+    }
+
+    #[tokio::test]
+    async fn stat_changes_etag_on_every_write() {
+        let storage = MemoryStorageContainer::new();
+        let content = futures::stream::iter(vec![Ok(Bytes::from("abc"))]);
+        storage
+            .set_content("a.ts", Box::pin(content))
+            .await
+            .unwrap();
+        let first = storage.stat("a.ts").await.unwrap();
+        assert_eq!(first.content_length, 3);
+        assert_eq!(first.content_type.as_deref(), Some("video/mp2t"));
+
+        storage.set_metadata("a.ts", "meta".into()).await.unwrap();
+        let second = storage.stat("a.ts").await.unwrap();
+        assert_eq!(second.metadata.as_deref(), Some("meta"));
+        assert_ne!(first.etag, second.etag);
+    }
+
+    #[tokio::test]
+    async fn conditional_writes_detect_lost_updates() {
+        let storage = MemoryStorageContainer::new();
+        let content =
+            || -> StreamType { Box::pin(futures::stream::iter(vec![Ok(Bytes::from("v"))])) };
+
+        storage
+            .set_content_if("a.ts", content(), WriteCondition::IfNoneMatch)
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .set_content_if("a.ts", content(), WriteCondition::IfNoneMatch)
+                .await,
+            Err(StorageError::PreconditionFailed)
+        ));
+
+        let etag = storage.stat("a.ts").await.unwrap().etag.unwrap();
+        storage
+            .set_metadata_if("a.ts", "meta".into(), WriteCondition::IfMatch(etag.clone()))
+            .await
+            .unwrap();
+        assert!(matches!(
+            storage
+                .set_content_if("a.ts", content(), WriteCondition::IfMatch(etag))
+                .await,
+            Err(StorageError::PreconditionFailed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn failed_conditions_leave_no_object() {
+        let storage = MemoryStorageContainer::new();
+        let content = Box::pin(futures::stream::iter(vec![Ok(Bytes::from("v"))]));
+        let etag = WriteCondition::IfMatch("1".into());
+        assert!(matches!(
+            storage.set_content_if("a.ts", content, etag.clone()).await,
+            Err(StorageError::PreconditionFailed)
+        ));
+        assert!(matches!(
+            storage.set_metadata_if("a.ts", "meta".into(), etag).await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(storage.objects.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn delete_copy_and_rename() {
+        let storage = MemoryStorageContainer::new();
+        let content = futures::stream::iter(vec![Ok(Bytes::from("data"))]);
+        storage
+            .set_content("a.ts", Box::pin(content))
+            .await
+            .unwrap();
+        storage.set_metadata("a.ts", "meta".into()).await.unwrap();
+
+        storage.copy("a.ts", "b.ts").await.unwrap();
+        storage.rename("b.ts", "c.ts").await.unwrap();
+        assert!(!storage.exists("b.ts").await.unwrap());
+        assert_eq!(read_all(&storage, "c.ts").await, b"data");
+        assert_eq!(storage.get_metadata("c.ts").await.unwrap(), "meta");
+
+        storage.delete("a.ts").await.unwrap();
+        assert!(matches!(
+            storage.get_metadata("a.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(matches!(
+            storage.delete("a.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert_eq!(storage.delete_prefix("").await.unwrap(), 1);
+        assert!(storage.list_all("").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn commits_staged_blocks() {
+        let storage = MemoryStorageContainer::new();
+        let content: Vec<u8> = (0..250).map(|i| i as u8).collect();
+        let body = |content: &[u8]| -> StreamType {
+            let chunks: Vec<_> = content
+                .chunks(50)
+                .map(|c| Ok(Bytes::copy_from_slice(c)))
+                .collect();
+            Box::pin(futures::stream::iter(chunks))
+        };
+        let policy = UploadPolicy {
+            block_size: 64,
+            concurrency: 2,
+        };
+        storage.set_content("a.ts", body(b"old")).await.unwrap();
+        storage.set_metadata("a.ts", "meta".into()).await.unwrap();
+        storage
+            .set_content_parallel("a.ts", body(&content), &policy)
+            .await
+            .unwrap();
+        assert_eq!(read_all(&storage, "a.ts").await, content);
+        let properties = storage.stat("a.ts").await.unwrap();
+        assert_eq!(properties.metadata.as_deref(), Some("meta"));
+        assert_eq!(properties.checksums, Checksums::of(&content));
+
+        // a block that fails to stage leaves the previous content in place.
+        let upload = storage.start_block_upload("a.ts").await.unwrap();
+        storage.inject_error(Operation::SetContent, StorageError::AuthenticationError);
+        let result = upload_blocks(upload, body(b"new content"), &policy).await;
+        assert!(matches!(result, Err(StorageError::AuthenticationError)));
+        assert_eq!(read_all(&storage, "a.ts").await, content);
+    }
+
+    #[tokio::test]
+    async fn injected_latency_delays_calls() {
+        let storage = MemoryStorageContainer::new();
+        storage.set_latency(Some(Duration::from_millis(50)));
+        let start = std::time::Instant::now();
+        storage.exists("a.ts").await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(50));
+
+        storage.clear_faults();
+        let start = std::time::Instant::now();
+        storage.exists("a.ts").await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,343 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use url::Url;
+
+use crate::StorageError;
+
+/// The query parameters of a URL signed by [`UrlSigner`].
+pub const EXPIRES_QUERY: &str = "expires";
+pub const PERMISSIONS_QUERY: &str = "permissions";
+pub const SIGNATURE_QUERY: &str = "signature";
+/// Names the operation a URL is signed for, when it is more than reading,
+/// writing or deleting the object at its path.
+pub const OPERATION_QUERY: &str = "operation";
+/// Marks a URL whose signature also covers the `Content-MD5` header sent
+/// with it, see [`UrlSigner::sign_digest`].
+pub const DIGEST_QUERY: &str = "digest";
+
+/// What a presigned URL allows, see [`crate::StorageContainer::presign`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Permissions {
+    pub read: bool,
+    pub write: bool,
+    pub delete: bool,
+}
+
+impl Permissions {
+    pub const READ: Self = Self {
+        read: true,
+        write: false,
+        delete: false,
+    };
+    pub const WRITE: Self = Self {
+        read: false,
+        write: true,
+        delete: false,
+    };
+    pub const READ_WRITE: Self = Self {
+        read: true,
+        write: true,
+        delete: false,
+    };
+
+    /// Whether everything `other` allows is allowed.
+    pub fn contains(&self, other: Permissions) -> bool {
+        (self.read || !other.read) && (self.write || !other.write) && (self.delete || !other.delete)
+    }
+
+    /// The permissions as a subset of `rwd`, as in SAS tokens.
+    pub fn as_str(&self) -> String {
+        [(self.read, 'r'), (self.write, 'w'), (self.delete, 'd')]
+            .into_iter()
+            .filter_map(|(allowed, c)| allowed.then_some(c))
+            .collect()
+    }
+
+    /// Parses [`Permissions::as_str`].
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut permissions = Self::default();
+        for c in value.chars() {
+            match c {
+                'r' => permissions.read = true,
+                'w' => permissions.write = true,
+                'd' => permissions.delete = true,
+                _ => return None,
+            }
+        }
+        Some(permissions)
+    }
+}
+
+fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+/// Signs URLs with an HMAC-SHA256 over their path, permissions, expiry,
+/// operation and body digest, for servers that share the key to check. A
+/// signed path ending in `/` covers every object below it.
+#[derive(Clone)]
+pub struct UrlSigner {
+    key: Vec<u8>,
+}
+
+impl UrlSigner {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+
+    fn mac(
+        &self,
+        path: &str,
+        permissions: &str,
+        expires: u64,
+        operation: Option<&str>,
+        digest: Option<&str>,
+    ) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("any key length works");
+        mac.update(format!("{permissions}\n{path}\n{expires}").as_bytes());
+        if let Some(operation) = operation {
+            mac.update(format!("\n{operation}").as_bytes());
+        }
+        if let Some(digest) = digest {
+            mac.update(format!("\n{DIGEST_QUERY}={digest}").as_bytes());
+        }
+        mac
+    }
+
+    /// Adds the signature for `permissions` until `expiry` from now to the
+    /// query of `url`.
+    pub fn sign(&self, url: Url, permissions: Permissions, expiry: Duration) -> Url {
+        let expires = unix_time(SystemTime::now() + expiry);
+        self.sign_until(url, permissions, None, None, expires)
+    }
+
+    /// Like [`UrlSigner::sign`], for `operation` only.
+    pub fn sign_operation(
+        &self,
+        url: Url,
+        permissions: Permissions,
+        operation: &str,
+        expiry: Duration,
+    ) -> Url {
+        let expires = unix_time(SystemTime::now() + expiry);
+        self.sign_until(url, permissions, Some(operation), None, expires)
+    }
+
+    /// Like [`UrlSigner::sign_operation`], for the request body whose
+    /// `Content-MD5` is `content_md5` only, so that the URL can't be replayed
+    /// with another body.
+    pub fn sign_digest(
+        &self,
+        url: Url,
+        permissions: Permissions,
+        operation: Option<&str>,
+        content_md5: &str,
+        expiry: Duration,
+    ) -> Url {
+        let expires = unix_time(SystemTime::now() + expiry);
+        self.sign_until(url, permissions, operation, Some(content_md5), expires)
+    }
+
+    fn sign_until(
+        &self,
+        mut url: Url,
+        permissions: Permissions,
+        operation: Option<&str>,
+        digest: Option<&str>,
+        expires: u64,
+    ) -> Url {
+        let permissions = permissions.as_str();
+        let signature = self
+            .mac(url.path(), &permissions, expires, operation, digest)
+            .finalize();
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair(EXPIRES_QUERY, &expires.to_string())
+                .append_pair(PERMISSIONS_QUERY, &permissions);
+            if let Some(operation) = operation {
+                query.append_pair(OPERATION_QUERY, operation);
+            }
+            if digest.is_some() {
+                query.append_pair(DIGEST_QUERY, "");
+            }
+            query.append_pair(
+                SIGNATURE_QUERY,
+                &URL_SAFE_NO_PAD.encode(signature.into_bytes()),
+            );
+        }
+        url
+    }
+
+    /// Checks that `url` carries a signature for its path, or a prefix of it,
+    /// that has not expired and allows `required`. URLs signed for an
+    /// operation are refused.
+    pub fn verify(&self, url: &Url, required: Permissions) -> Result<(), StorageError> {
+        self.verify_operation(url, required, None)
+    }
+
+    /// Like [`UrlSigner::verify`], for a URL that has to be signed for
+    /// `operation`, or for no operation if it is `None`. URLs signed for a
+    /// body digest are refused.
+    pub fn verify_operation(
+        &self,
+        url: &Url,
+        required: Permissions,
+        operation: Option<&str>,
+    ) -> Result<(), StorageError> {
+        self.verify_digest(url, required, operation, None)
+    }
+
+    /// Like [`UrlSigner::verify_operation`], for a request that came with the
+    /// `Content-MD5` header `content_md5`. URLs signed for a body digest have
+    /// to be signed for that one, and it is up to the caller to check that
+    /// the body matches it.
+    pub fn verify_digest(
+        &self,
+        url: &Url,
+        required: Permissions,
+        operation: Option<&str>,
+        content_md5: Option<&str>,
+    ) -> Result<(), StorageError> {
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+        };
+        let (Some(expires), Some(permissions), Some(signature)) = (
+            query(EXPIRES_QUERY).and_then(|e| e.parse::<u64>().ok()),
+            query(PERMISSIONS_QUERY),
+            query(SIGNATURE_QUERY).and_then(|s| URL_SAFE_NO_PAD.decode(s).ok()),
+        ) else {
+            return Err(StorageError::AuthenticationError);
+        };
+        let now = unix_time(SystemTime::now());
+        let allowed = Permissions::parse(&permissions)
+            .is_some_and(|permissions| permissions.contains(required));
+        if now > expires || !allowed || query(OPERATION_QUERY).as_deref() != operation {
+            return Err(StorageError::AuthenticationError);
+        }
+        let digest = match query(DIGEST_QUERY) {
+            Some(_) if content_md5.is_none() => return Err(StorageError::AuthenticationError),
+            Some(_) => content_md5,
+            None => None,
+        };
+        let path = url.path();
+        let signed = |signed: &str| {
+            self.mac(signed, &permissions, expires, operation, digest)
+                .verify_slice(&signature)
+                .is_ok()
+        };
+        // the URL may be signed for the object or any directory above it.
+        let matches = signed(path) || path.match_indices('/').any(|(i, _)| signed(&path[..=i]));
+        if matches {
+            Ok(())
+        } else {
+            Err(StorageError::AuthenticationError)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_permissions() {
+        assert_eq!(Permissions::READ_WRITE.as_str(), "rw");
+        assert_eq!(Permissions::parse("rd").unwrap().as_str(), "rd");
+        assert!(Permissions::parse("rx").is_none());
+        assert!(Permissions::READ_WRITE.contains(Permissions::READ));
+        assert!(!Permissions::READ.contains(Permissions::WRITE));
+    }
+
+    #[test]
+    fn verifies_signed_urls() {
+        let signer = UrlSigner::new("secret");
+        let url = Url::parse("http://proxy/account/video/level0/segment1.ts").unwrap();
+        let signed = signer.sign(url.clone(), Permissions::READ, Duration::from_secs(60));
+        signer.verify(&signed, Permissions::READ).unwrap();
+
+        let wrong_key = UrlSigner::new("other");
+        assert!(wrong_key.verify(&signed, Permissions::READ).is_err());
+        assert!(signer.verify(&signed, Permissions::WRITE).is_err());
+        assert!(signer.verify(&url, Permissions::READ).is_err());
+        let mut moved = signed.clone();
+        moved.set_path("/account/video/level0/segment2.ts");
+        assert!(signer.verify(&moved, Permissions::READ).is_err());
+
+        let expired = signer.sign_until(url, Permissions::READ, None, None, 1);
+        assert!(signer.verify(&expired, Permissions::READ).is_err());
+    }
+
+    #[test]
+    fn operation_signatures_cover_that_operation_only() {
+        let signer = UrlSigner::new("secret");
+        let url = Url::parse("http://proxy/account/video/level0/").unwrap();
+        let expiry = Duration::from_secs(60);
+        let list = signer.sign_operation(url.clone(), Permissions::READ, "list", expiry);
+        signer
+            .verify_operation(&list, Permissions::READ, Some("list"))
+            .unwrap();
+        assert!(signer.verify(&list, Permissions::READ).is_err());
+        assert!(signer
+            .verify_operation(&list, Permissions::READ, Some("watch"))
+            .is_err());
+
+        let read = signer.sign(url, Permissions::READ, expiry);
+        assert!(signer
+            .verify_operation(&read, Permissions::READ, Some("list"))
+            .is_err());
+        let mut forged = read.clone();
+        forged
+            .query_pairs_mut()
+            .append_pair(OPERATION_QUERY, "list");
+        assert!(signer
+            .verify_operation(&forged, Permissions::READ, Some("list"))
+            .is_err());
+    }
+
+    #[test]
+    fn digest_signatures_cover_that_body_only() {
+        let signer = UrlSigner::new("secret");
+        let url = Url::parse("http://proxy/account/video/a.ts/metadata").unwrap();
+        let expiry = Duration::from_secs(60);
+        let signed = signer.sign_digest(
+            url.clone(),
+            Permissions::WRITE,
+            Some("metadata"),
+            "md5",
+            expiry,
+        );
+        let verify = |url: &Url, content_md5| {
+            signer.verify_digest(url, Permissions::WRITE, Some("metadata"), content_md5)
+        };
+        verify(&signed, Some("md5")).unwrap();
+        assert!(verify(&signed, Some("other")).is_err());
+        assert!(verify(&signed, None).is_err());
+
+        // the digest can't be dropped from the signature.
+        let mut stripped = url;
+        stripped
+            .query_pairs_mut()
+            .extend_pairs(signed.query_pairs().filter(|(key, _)| key != DIGEST_QUERY));
+        assert!(verify(&stripped, Some("md5")).is_err());
+        assert!(verify(&stripped, None).is_err());
+    }
+
+    #[test]
+    fn prefix_signatures_cover_objects_below() {
+        let signer = UrlSigner::new("secret");
+        let prefix = Url::parse("http://proxy/account/video/level0/").unwrap();
+        let mut signed = signer.sign(prefix, Permissions::WRITE, Duration::from_secs(60));
+        signed.set_path("/account/video/level0/segment1.ts");
+        signer.verify(&signed, Permissions::WRITE).unwrap();
+        signed.set_path("/account/video/level1/segment1.ts");
+        assert!(signer.verify(&signed, Permissions::WRITE).is_err());
+    }
+}
@@ -0,0 +1,158 @@
+use std::{
+    collections::HashMap,
+    io::ErrorKind,
+    sync::{Arc, Mutex},
+};
+
+use url::Url;
+
+use crate::{FsStorageContainer, MemoryStorageContainer, StorageContainer, StorageError};
+
+pub type BoxedStorageContainer = Box<dyn StorageContainer + Send + Sync>;
+
+/// Opens the container a URL of a registered scheme points to.
+pub type StorageFactory =
+    Arc<dyn Fn(&Url) -> Result<BoxedStorageContainer, StorageError> + Send + Sync>;
+
+/// Maps URL schemes to the backends serving them.
+///
+/// `file:///path` and `memory://name` are registered out of the box. Opening
+/// the same `memory://` name twice shares the objects. Other backends register
+/// their own schemes, e.g. `storage_s3::register`.
+#[derive(Clone)]
+pub struct StorageRegistry {
+    factories: HashMap<String, StorageFactory>,
+}
+
+impl Default for StorageRegistry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry.register("file", |url| {
+            let root = url
+                .to_file_path()
+                .map_err(|_| invalid_url(url, "expected a local absolute path"))?;
+            Ok(Box::new(FsStorageContainer::new(root)))
+        });
+        let memory: Arc<Mutex<HashMap<String, MemoryStorageContainer>>> = Default::default();
+        registry.register("memory", move |url| {
+            let name = url.host_str().unwrap_or_default().to_owned();
+            let mut containers = memory.lock().unwrap();
+            Ok(Box::new(containers.entry(name).or_default().clone()))
+        });
+        registry
+    }
+}
+
+impl StorageRegistry {
+    /// A registry with the built-in schemes.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A registry without any scheme.
+    pub fn empty() -> Self {
+        Self {
+            factories: HashMap::new(),
+        }
+    }
+
+    /// Registers `factory` for `scheme`, replacing any earlier registration.
+    pub fn register<F>(&mut self, scheme: &str, factory: F)
+    where
+        F: Fn(&Url) -> Result<BoxedStorageContainer, StorageError> + Send + Sync + 'static,
+    {
+        self.factories
+            .insert(scheme.to_ascii_lowercase(), Arc::new(factory));
+    }
+
+    pub fn is_registered(&self, scheme: &str) -> bool {
+        self.factories.contains_key(&scheme.to_ascii_lowercase())
+    }
+
+    pub fn open(&self, url: &Url) -> Result<BoxedStorageContainer, StorageError> {
+        match self.factories.get(url.scheme()) {
+            Some(factory) => factory(url),
+            None => Err(invalid_url(url, "no storage is registered for the scheme")),
+        }
+    }
+
+    pub fn open_str(&self, url: &str) -> Result<BoxedStorageContainer, StorageError> {
+        let url = Url::parse(url)
+            .map_err(|e| StorageError::Other(std::io::Error::new(ErrorKind::InvalidInput, e)))?;
+        self.open(&url)
+    }
+}
+
+/// The error a factory returns for a URL it can't open.
+pub fn invalid_url(url: &Url, reason: &str) -> StorageError {
+    StorageError::Other(std::io::Error::new(
+        ErrorKind::InvalidInput,
+        format!("invalid storage URL {url}: {reason}"),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    async fn write(storage: &BoxedStorageContainer, path: &str, content: &'static str) {
+        let stream = futures::stream::iter([Ok(Bytes::from(content))]);
+        storage.set_content(path, Box::pin(stream)).await.unwrap();
+    }
+
+    async fn read(storage: &BoxedStorageContainer, path: &str) -> Bytes {
+        let chunks: Vec<Bytes> = storage
+            .get_content(path)
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        chunks.concat().into()
+    }
+
+    #[tokio::test]
+    async fn opens_file_urls() {
+        let dir = tempfile::tempdir().unwrap();
+        let url = Url::from_directory_path(dir.path()).unwrap();
+        let storage = StorageRegistry::new().open(&url).unwrap();
+        write(&storage, "video/manifest.mpd", "<MPD/>").await;
+        assert!(dir.path().join("video/manifest.mpd").exists());
+    }
+
+    #[tokio::test]
+    async fn shares_memory_containers_by_name() {
+        let registry = StorageRegistry::new();
+        let first = registry.open_str("memory://videos").unwrap();
+        let second = registry.open_str("memory://videos").unwrap();
+        let other = registry.open_str("memory://other").unwrap();
+        write(&first, "a", "hello").await;
+        assert_eq!(read(&second, "a").await, "hello");
+        assert!(!other.exists("a").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn decorates_opened_containers() {
+        let registry = StorageRegistry::new();
+        let cached = crate::CachingStorageContainer::new(
+            registry.open_str("memory://videos").unwrap(),
+            crate::StorageCache::new(Default::default()),
+        );
+        let storage: BoxedStorageContainer = Box::new(cached);
+        write(&storage, "a", "hello").await;
+        let plain = registry.open_str("memory://videos").unwrap();
+        assert_eq!(read(&plain, "a").await, "hello");
+    }
+
+    #[test]
+    fn rejects_unknown_schemes() {
+        let mut registry = StorageRegistry::empty();
+        assert!(registry.open_str("memory://videos").is_err());
+        registry.register("custom", |_| Ok(Box::new(MemoryStorageContainer::new())));
+        assert!(registry.is_registered("CUSTOM"));
+        assert!(registry.open_str("custom://anything").is_ok());
+        assert!(registry.open_str("not a url").is_err());
+    }
+}
@@ -0,0 +1,907 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use tokio::time::Instant;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use futures::{future::BoxFuture, FutureExt, Stream, StreamExt};
+use rand::Rng;
+
+use crate::{
+    AppendSession, BlockUpload, Checksums, Lease, ListStream, ObjectProperties, Operation,
+    Permissions, StorageContainer, StorageError, StreamType, Url, WatchStream, WriteCondition,
+};
+
+/// How [`RetryStorageContainer`] retries failed operations.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// The maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+    /// The delay before the first retry.
+    pub initial_backoff: Duration,
+    /// The upper bound for the delay between two attempts.
+    pub max_backoff: Duration,
+    /// The time after which an operation gives up with [`StorageError::Timeout`],
+    /// including all of its retries. For reads this bounds opening or resuming
+    /// the stream, not consuming it. Writes of content are never cut off while
+    /// they stream their body, which may take any time to produce; the deadline
+    /// only stops retrying them.
+    pub deadline: Option<Duration>,
+    /// How much of a `set_content` body is kept to replay it on a retry.
+    /// Larger bodies are not retried once the first attempt has consumed them.
+    pub replay_buffer_size: usize,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(5),
+            deadline: Some(Duration::from_secs(60)),
+            replay_buffer_size: 16 * 1024 * 1024,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// The delay before retry number `retry` (starting at 1), with jitter
+    /// between half and all of the exponential backoff.
+    fn backoff(&self, retry: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1 << retry.saturating_sub(1).min(16))
+            .min(self.max_backoff);
+        backoff.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+
+    async fn run<T, F, Fut>(&self, operation: Operation, f: F) -> Result<T, StorageError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, StorageError>>,
+    {
+        let attempts = self.attempts(f, || true, None);
+        match self.deadline {
+            Some(deadline) => tokio::time::timeout(deadline, attempts)
+                .await
+                .unwrap_or_else(|_| {
+                    Err(StorageError::Timeout(
+                        format!("{operation:?} did not complete within {deadline:?}").into(),
+                    ))
+                }),
+            None => attempts.await,
+        }
+    }
+
+    /// Like `run` for operations that stream a body: attempts are not cut
+    /// off, but none starts after the deadline, and retries only happen while
+    /// `can_retry` returns true.
+    async fn run_streaming<T, F, Fut, C>(&self, f: F, can_retry: C) -> Result<T, StorageError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, StorageError>>,
+        C: Fn() -> bool,
+    {
+        let deadline = self.deadline.map(|deadline| Instant::now() + deadline);
+        self.attempts(f, can_retry, deadline).await
+    }
+
+    async fn attempts<T, F, Fut, C>(
+        &self,
+        mut f: F,
+        can_retry: C,
+        deadline: Option<Instant>,
+    ) -> Result<T, StorageError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, StorageError>>,
+        C: Fn() -> bool,
+    {
+        let mut retry = 0;
+        loop {
+            match f().await {
+                Err(e) if e.is_retryable() && retry + 1 < self.max_attempts && can_retry() => {
+                    retry += 1;
+                    let backoff = self.backoff(retry);
+                    if deadline.is_some_and(|deadline| Instant::now() + backoff >= deadline) {
+                        return Err(e);
+                    }
+                    tokio::time::sleep(backoff).await;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+/// The ETag of `path`, if the backend reports one.
+async fn etag<S>(storage: &S, path: &str) -> Result<Option<String>, StorageError>
+where
+    S: StorageContainer + Sync + ?Sized,
+{
+    match storage.stat(path).await {
+        Ok(properties) => Ok(properties.etag),
+        Err(StorageError::Unsupported) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+/// Wraps another container and retries its retryable errors with exponential
+/// backoff and jitter.
+///
+/// Reads resume from the last byte delivered when the stream breaks. They
+/// look up the ETag of the object first, and fail with
+/// [`StorageError::PreconditionFailed`] rather than resume a stream once the
+/// object was replaced. Note that
+/// a retried non-idempotent operation (a conditional write or a rename) may
+/// report a failure if the first attempt succeeded but its response was lost.
+pub struct RetryStorageContainer<S> {
+    inner: Arc<S>,
+    policy: RetryPolicy,
+}
+
+impl<S> RetryStorageContainer<S> {
+    pub fn new(inner: S, policy: RetryPolicy) -> Self {
+        Self {
+            inner: Arc::new(inner),
+            policy,
+        }
+    }
+
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+}
+
+impl<S> RetryStorageContainer<S>
+where
+    S: StorageContainer + Send + Sync + 'static,
+{
+    async fn read(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        // looked up before opening, a replacement in between fails resumes.
+        let etag = self
+            .policy
+            .run(Operation::Stat, || etag(&*self.inner, path))
+            .await?;
+        let stream = self
+            .policy
+            .run(Operation::GetContent, || async {
+                if offset == 0 && length.is_none() {
+                    self.inner.get_content(path).await
+                } else {
+                    self.inner.get_content_range(path, offset, length).await
+                }
+            })
+            .await?;
+        Ok(Box::pin(ResumableStream {
+            inner: self.inner.clone(),
+            policy: self.policy.clone(),
+            path: path.to_owned(),
+            position: offset,
+            end: length.map(|length| offset.saturating_add(length)),
+            etag,
+            failures: 0,
+            state: ReadState::Reading(stream),
+        }))
+    }
+
+    async fn write(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        let replay = Arc::new(Mutex::new(Replay {
+            source: content,
+            chunks: Vec::new(),
+            buffered: 0,
+            limit: self.policy.replay_buffer_size,
+            overflowed: false,
+            source_failed: false,
+        }));
+        self.policy
+            .run_streaming(
+                || {
+                    let content: StreamType = Box::pin(ReplayStream {
+                        replay: replay.clone(),
+                        index: 0,
+                    });
+                    let condition = condition.clone();
+                    async move {
+                        match condition {
+                            Some(condition) => {
+                                self.inner.set_content_if(path, content, condition).await
+                            }
+                            None => self.inner.set_content(path, content).await,
+                        }
+                    }
+                },
+                || replay.lock().unwrap().can_replay(),
+            )
+            .await
+    }
+}
+
+#[async_trait]
+impl<S> StorageContainer for RetryStorageContainer<S>
+where
+    S: StorageContainer + Send + Sync + 'static,
+{
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.read(path, 0, None).await
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        self.read(path, offset, length).await
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        self.policy
+            .run(Operation::GetMetadata, || self.inner.get_metadata(path))
+            .await
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        self.write(path, content, None).await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::SetMetadata, || {
+                self.inner.set_metadata(path, metadata.clone())
+            })
+            .await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        self.policy
+            .run(Operation::Exists, || self.inner.exists(path))
+            .await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.write(path, content, Some(condition)).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::SetMetadata, || {
+                self.inner
+                    .set_metadata_if(path, metadata.clone(), condition.clone())
+            })
+            .await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        self.policy
+            .run(Operation::Stat, || self.inner.stat(path))
+            .await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        self.policy
+            .run(Operation::List, || self.inner.list(prefix))
+            .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::Delete, || self.inner.delete(path))
+            .await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::Copy, || self.inner.copy(from, to))
+            .await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::Rename, || self.inner.rename(from, to))
+            .await
+    }
+
+    /// Opening is retried since it only (re)creates an empty object. Appends
+    /// are not, as a failed append may still have been applied.
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        self.policy
+            .run(Operation::Append, || self.inner.open_append(path))
+            .await
+    }
+
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        self.policy
+            .run(Operation::GetContent, || self.inner.get_content_tail(path))
+            .await
+    }
+
+    /// Staging a block is retried since it only replaces that block. The
+    /// commit is not, as it consumes the upload.
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        let inner = self
+            .policy
+            .run(Operation::SetContent, || {
+                self.inner.start_block_upload(path)
+            })
+            .await?;
+        Ok(Box::new(RetryBlockUpload {
+            inner,
+            policy: self.policy.clone(),
+        }))
+    }
+
+    /// A retried acquire may fail with [`StorageError::Conflict`] if the
+    /// first attempt took the lease but its response was lost.
+    async fn acquire_lease(&self, path: &str, ttl: Duration) -> Result<Lease, StorageError> {
+        self.policy
+            .run(Operation::Lease, || self.inner.acquire_lease(path, ttl))
+            .await
+    }
+
+    async fn renew_lease(&self, lease: &Lease) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::Lease, || self.inner.renew_lease(lease))
+            .await
+    }
+
+    async fn release_lease(&self, lease: Lease) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::Lease, || self.inner.release_lease(lease.clone()))
+            .await
+    }
+
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        self.inner.presign(path, permissions, expiry).await
+    }
+
+    /// Only opening the stream is retried, errors it yields are passed on.
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        self.policy
+            .run(Operation::Watch, || self.inner.watch(prefix))
+            .await
+    }
+}
+
+struct RetryBlockUpload {
+    inner: Box<dyn BlockUpload>,
+    policy: RetryPolicy,
+}
+
+#[async_trait]
+impl BlockUpload for RetryBlockUpload {
+    fn min_block_size(&self) -> usize {
+        self.inner.min_block_size()
+    }
+
+    async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError> {
+        self.policy
+            .run(Operation::SetContent, || {
+                self.inner.stage_block(index, block.clone())
+            })
+            .await
+    }
+
+    async fn commit(
+        self: Box<Self>,
+        blocks: usize,
+        checksums: Checksums,
+    ) -> Result<(), StorageError> {
+        self.inner.commit(blocks, checksums).await
+    }
+
+    async fn abort(self: Box<Self>) -> Result<(), StorageError> {
+        self.inner.abort().await
+    }
+}
+
+enum ReadState {
+    Reading(StreamType),
+    // the mutex only makes the future `Sync`, it is never contended.
+    Resuming(Mutex<BoxFuture<'static, Result<StreamType, StorageError>>>),
+    Done,
+}
+
+/// Reopens the object from the current position when the stream fails.
+struct ResumableStream<S> {
+    inner: Arc<S>,
+    policy: RetryPolicy,
+    path: String,
+    position: u64,
+    end: Option<u64>,
+    /// The version being read, if the backend reports it.
+    etag: Option<String>,
+    /// Failures since the last chunk was delivered.
+    failures: u32,
+    state: ReadState,
+}
+
+impl<S> ResumableStream<S>
+where
+    S: StorageContainer + Send + Sync + 'static,
+{
+    fn resume(&self) -> ReadState {
+        let inner = self.inner.clone();
+        let policy = self.policy.clone();
+        let path = self.path.clone();
+        let offset = self.position;
+        let length = self.end.map(|end| end - offset);
+        let expected = self.etag.clone();
+        let backoff = self.policy.backoff(self.failures);
+        let future = async move {
+            tokio::time::sleep(backoff).await;
+            policy
+                .run(Operation::GetContent, || async {
+                    let stream = inner.get_content_range(&path, offset, length).await?;
+                    // checked after opening, so that the stream is of the
+                    // version that was current then.
+                    if expected.is_some() && etag(&*inner, &path).await? != expected {
+                        return Err(StorageError::PreconditionFailed);
+                    }
+                    Ok(stream)
+                })
+                .await
+        };
+        ReadState::Resuming(Mutex::new(future.boxed()))
+    }
+}
+
+impl<S> Stream for ResumableStream<S>
+where
+    S: StorageContainer + Send + Sync + 'static,
+{
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match &mut self.state {
+                ReadState::Reading(stream) => match stream.poll_next_unpin(cx) {
+                    Poll::Ready(Some(Ok(chunk))) => {
+                        self.position += chunk.len() as u64;
+                        self.failures = 0;
+                        return Poll::Ready(Some(Ok(chunk)));
+                    }
+                    Poll::Ready(Some(Err(e)))
+                        if e.is_retryable() && self.failures + 1 < self.policy.max_attempts =>
+                    {
+                        self.failures += 1;
+                        self.state = self.resume();
+                    }
+                    Poll::Ready(Some(Err(e))) => {
+                        self.state = ReadState::Done;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    Poll::Ready(None) => {
+                        self.state = ReadState::Done;
+                        return Poll::Ready(None);
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                ReadState::Resuming(future) => match future.get_mut().unwrap().poll_unpin(cx) {
+                    Poll::Ready(Ok(stream)) => self.state = ReadState::Reading(stream),
+                    Poll::Ready(Err(e)) => {
+                        self.state = ReadState::Done;
+                        return Poll::Ready(Some(Err(e)));
+                    }
+                    Poll::Pending => return Poll::Pending,
+                },
+                ReadState::Done => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+/// The body of a `set_content` call, shared between its attempts.
+struct Replay {
+    source: StreamType,
+    chunks: Vec<Bytes>,
+    buffered: usize,
+    limit: usize,
+    overflowed: bool,
+    source_failed: bool,
+}
+
+impl Replay {
+    /// Whether every chunk consumed so far is buffered. Errors of the
+    /// caller's own stream are never retried.
+    fn can_replay(&self) -> bool {
+        !self.overflowed && !self.source_failed
+    }
+}
+
+/// Replays the chunks buffered by earlier attempts before reading on from the source.
+struct ReplayStream {
+    replay: Arc<Mutex<Replay>>,
+    index: usize,
+}
+
+impl Stream for ReplayStream {
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let replay = self.replay.clone();
+        let mut replay = replay.lock().unwrap();
+        if let Some(chunk) = replay.chunks.get(self.index) {
+            let chunk = chunk.clone();
+            self.index += 1;
+            return Poll::Ready(Some(Ok(chunk)));
+        }
+        let result = replay.source.poll_next_unpin(cx);
+        match &result {
+            Poll::Ready(Some(Ok(chunk))) if !replay.overflowed => {
+                replay.buffered += chunk.len();
+                if replay.buffered > replay.limit {
+                    replay.overflowed = true;
+                    replay.chunks = Vec::new();
+                } else {
+                    replay.chunks.push(chunk.clone());
+                    self.index += 1;
+                }
+            }
+            Poll::Ready(Some(Err(_))) => replay.source_failed = true,
+            _ => {}
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemoryStorageContainer;
+    use futures::TryStreamExt;
+    use std::{
+        io::ErrorKind,
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    };
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(10),
+            ..Default::default()
+        }
+    }
+
+    fn reset() -> StorageError {
+        StorageError::Other(ErrorKind::ConnectionReset.into())
+    }
+
+    fn body(chunks: &[&'static str]) -> StreamType {
+        let chunks: Vec<_> = chunks.iter().map(|c| Ok(Bytes::from(*c))).collect();
+        Box::pin(futures::stream::iter(chunks))
+    }
+
+    async fn read_all(stream: StreamType) -> Result<Vec<u8>, StorageError> {
+        let chunks: Vec<Bytes> = stream.try_collect().await?;
+        Ok(chunks.concat())
+    }
+
+    /// Breaks the first `breaks` reads after their first chunk.
+    struct FlakyReads {
+        inner: MemoryStorageContainer,
+        breaks: AtomicUsize,
+        opened: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl StorageContainer for FlakyReads {
+        async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+            self.get_content_range(path, 0, None).await
+        }
+
+        async fn get_content_range(
+            &self,
+            path: &str,
+            offset: u64,
+            length: Option<u64>,
+        ) -> Result<StreamType, StorageError> {
+            self.opened.fetch_add(1, Ordering::SeqCst);
+            let stream = self.inner.get_content_range(path, offset, length).await?;
+            let breaks = self
+                .breaks
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |b| b.checked_sub(1))
+                .is_ok();
+            if breaks {
+                Ok(Box::pin(
+                    stream
+                        .take(1)
+                        .chain(futures::stream::once(async { Err(reset()) })),
+                ))
+            } else {
+                Ok(stream)
+            }
+        }
+
+        async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+            self.inner.get_metadata(path).await
+        }
+
+        async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+            self.inner.set_content(path, content).await
+        }
+
+        async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+            self.inner.set_metadata(path, metadata).await
+        }
+
+        async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+            self.inner.exists(path).await
+        }
+
+        async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+            self.inner.stat(path).await
+        }
+    }
+
+    #[tokio::test]
+    async fn retries_retryable_errors() {
+        let memory = MemoryStorageContainer::new();
+        let storage = RetryStorageContainer::new(memory.clone(), policy());
+        memory.set_content("a", body(&["abc"])).await.unwrap();
+        memory.set_metadata("a", "meta".into()).await.unwrap();
+        memory.inject_error(
+            Operation::GetMetadata,
+            StorageError::Throttled("busy".into()),
+        );
+        memory.inject_error(Operation::GetMetadata, reset());
+        assert_eq!(storage.get_metadata("a").await.unwrap(), "meta");
+
+        memory.inject_error(Operation::GetMetadata, StorageError::AuthenticationError);
+        memory.inject_error(Operation::GetMetadata, reset());
+        assert!(matches!(
+            storage.get_metadata("a").await,
+            Err(StorageError::AuthenticationError)
+        ));
+        // the second error is still pending.
+        memory.clear_faults();
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_attempts() {
+        let memory = MemoryStorageContainer::new();
+        let storage = RetryStorageContainer::new(
+            memory.clone(),
+            RetryPolicy {
+                max_attempts: 2,
+                ..policy()
+            },
+        );
+        for _ in 0..3 {
+            memory.inject_error(Operation::Stat, StorageError::Throttled("busy".into()));
+        }
+        assert!(matches!(
+            storage.stat("a").await,
+            Err(StorageError::Throttled(_))
+        ));
+        // the remaining error is retried before the call reaches the backend.
+        assert!(matches!(
+            storage.stat("a").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+
+    #[tokio::test]
+    async fn times_out_at_the_deadline() {
+        let memory = MemoryStorageContainer::new();
+        memory.set_latency(Some(Duration::from_secs(10)));
+        let storage = RetryStorageContainer::new(
+            memory,
+            RetryPolicy {
+                deadline: Some(Duration::from_millis(20)),
+                ..policy()
+            },
+        );
+        assert!(matches!(
+            storage.get_metadata("a").await,
+            Err(StorageError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn writes_stream_past_the_deadline() {
+        let memory = MemoryStorageContainer::new();
+        let storage = RetryStorageContainer::new(
+            memory.clone(),
+            RetryPolicy {
+                deadline: Some(Duration::from_millis(20)),
+                ..policy()
+            },
+        );
+        let slow = futures::stream::once(async {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+            Ok(Bytes::from("slow"))
+        });
+        storage.set_content("a", Box::pin(slow)).await.unwrap();
+        assert_eq!(
+            read_all(memory.get_content("a").await.unwrap())
+                .await
+                .unwrap(),
+            b"slow"
+        );
+    }
+
+    #[tokio::test]
+    async fn fails_resumed_reads_of_replaced_objects() {
+        let memory = MemoryStorageContainer::new();
+        memory
+            .set_content("a", body(&["abc", "def", "ghi"]))
+            .await
+            .unwrap();
+        let flaky = FlakyReads {
+            inner: memory.clone(),
+            breaks: AtomicUsize::new(1),
+            opened: AtomicUsize::new(0),
+        };
+        let storage = RetryStorageContainer::new(flaky, policy());
+        let mut content = storage.get_content("a").await.unwrap();
+        assert_eq!(content.next().await.unwrap().unwrap(), "abc");
+        memory
+            .set_content("a", body(&["ABC", "DEF", "GHI"]))
+            .await
+            .unwrap();
+        assert!(matches!(
+            content.next().await,
+            Some(Err(StorageError::PreconditionFailed))
+        ));
+    }
+
+    #[tokio::test]
+    async fn resumes_broken_reads() {
+        let memory = MemoryStorageContainer::new();
+        memory
+            .set_content("a", body(&["abc", "def", "ghi"]))
+            .await
+            .unwrap();
+        let flaky = FlakyReads {
+            inner: memory,
+            breaks: AtomicUsize::new(2),
+            opened: AtomicUsize::new(0),
+        };
+        let storage = RetryStorageContainer::new(flaky, policy());
+        let content = read_all(storage.get_content("a").await.unwrap()).await;
+        assert_eq!(content.unwrap(), b"abcdefghi");
+        assert_eq!(storage.inner().opened.load(Ordering::SeqCst), 3);
+
+        storage.inner().breaks.store(1, Ordering::SeqCst);
+        let range = storage.get_content_range("a", 2, Some(5)).await.unwrap();
+        assert_eq!(read_all(range).await.unwrap(), b"cdefg");
+    }
+
+    #[tokio::test]
+    async fn fails_reads_that_keep_breaking() {
+        let memory = MemoryStorageContainer::new();
+        memory
+            .set_content("a", body(&["abc", "def"]))
+            .await
+            .unwrap();
+        let flaky = FlakyReads {
+            inner: memory,
+            breaks: AtomicUsize::new(usize::MAX),
+            opened: AtomicUsize::new(0),
+        };
+        let storage = RetryStorageContainer::new(flaky, policy());
+        let content = read_all(storage.get_content("a").await.unwrap()).await;
+        assert!(matches!(content, Err(StorageError::Other(_))));
+    }
+
+    #[tokio::test]
+    async fn replays_the_body_of_failed_writes() {
+        let memory = MemoryStorageContainer::new();
+        let storage = RetryStorageContainer::new(
+            FailFirstWrite {
+                inner: memory.clone(),
+                first: AtomicBool::new(true),
+            },
+            policy(),
+        );
+        storage
+            .set_content("a", body(&["abc", "def"]))
+            .await
+            .unwrap();
+        let content = read_all(memory.get_content("a").await.unwrap()).await;
+        assert_eq!(content.unwrap(), b"abcdef");
+
+        // errors of the caller's body are not retried.
+        storage.inner().first.store(true, Ordering::SeqCst);
+        let broken: StreamType =
+            Box::pin(futures::stream::iter([Ok(Bytes::from("x")), Err(reset())]));
+        assert!(storage.set_content("b", broken).await.is_err());
+        assert!(!memory.exists("b").await.unwrap());
+    }
+
+    /// Consumes part of the body on the first write and then fails it.
+    struct FailFirstWrite {
+        inner: MemoryStorageContainer,
+        first: AtomicBool,
+    }
+
+    #[async_trait]
+    impl StorageContainer for FailFirstWrite {
+        async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+            self.inner.get_content(path).await
+        }
+
+        async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+            self.inner.get_metadata(path).await
+        }
+
+        async fn set_content(
+            &self,
+            path: &str,
+            mut content: StreamType,
+        ) -> Result<(), StorageError> {
+            if self.first.swap(false, Ordering::SeqCst) {
+                // the first attempt fails in the middle of the body.
+                if let Some(Err(e)) = content.next().await {
+                    return Err(e);
+                }
+                return Err(reset());
+            }
+            self.inner.set_content(path, content).await
+        }
+
+        async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+            self.inner.set_metadata(path, metadata).await
+        }
+
+        async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+            self.inner.exists(path).await
+        }
+    }
+
+    #[tokio::test]
+    async fn does_not_replay_bodies_beyond_the_buffer() {
+        let memory = MemoryStorageContainer::new();
+        let storage = RetryStorageContainer::new(
+            FailFirstWrite {
+                inner: memory.clone(),
+                first: AtomicBool::new(true),
+            },
+            RetryPolicy {
+                replay_buffer_size: 2,
+                ..policy()
+            },
+        );
+        assert!(storage
+            .set_content("a", body(&["abc", "def"]))
+            .await
+            .is_err());
+        assert!(!memory.exists("a").await.unwrap());
+    }
+}
@@ -0,0 +1,272 @@
+use std::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use tokio::time::{Instant, Sleep};
+
+use crate::{ListStream, ObjectInfo, StorageError, StreamType};
+
+const LIST_PAGE_SIZE: usize = 1000;
+
+/// Splits an already collected listing into pages. The backends that use it
+/// list eagerly: every object under the prefix is gathered and sorted before
+/// the first page is handed out, so the memory and the time to the first page
+/// grow with the size of the listing.
+pub(crate) fn paginate(mut objects: Vec<ObjectInfo>) -> ListStream {
+    objects.sort_by(|a, b| a.name.cmp(&b.name));
+    let pages: Vec<_> = objects
+        .chunks(LIST_PAGE_SIZE)
+        .map(|page| Ok(page.to_vec()))
+        .collect();
+    Box::pin(futures::stream::iter(pages))
+}
+
+/// Skips the first `offset` bytes of `stream` and truncates it to `length`
+/// bytes. The source is dropped as soon as the requested range is complete.
+pub fn slice_stream(stream: StreamType, offset: u64, length: Option<u64>) -> StreamType {
+    let end = length.map(|length| offset.saturating_add(length));
+    if end == Some(offset) {
+        return Box::pin(futures::stream::empty());
+    }
+    let stream = futures::stream::unfold(Some((stream, 0u64)), move |state| async move {
+        let (mut stream, mut position) = state?;
+        loop {
+            let chunk = match stream.next().await? {
+                Ok(chunk) => chunk,
+                Err(e) => return Some((Err(e), None)),
+            };
+            let start = position;
+            position += chunk.len() as u64;
+            if position <= offset {
+                continue;
+            }
+            let from = offset.saturating_sub(start) as usize;
+            return match end {
+                Some(end) if position >= end => {
+                    let to = (end - start) as usize;
+                    Some((Ok(chunk.slice(from..to)), None))
+                }
+                _ => Some((Ok(chunk.slice(from..)), Some((stream, position)))),
+            };
+        }
+    });
+    Box::pin(stream)
+}
+
+/// The slowest a stream may deliver data, see [`enforce_throughput`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MinThroughput {
+    /// The bytes that have to arrive in every `window` of waiting.
+    pub bytes: u64,
+    pub window: Duration,
+}
+
+/// Fails `stream` with [`StorageError::Timeout`] once it delivers less than
+/// the minimum in a window of waiting for it. The time the consumer takes
+/// between polls does not count, so slow readers don't fail fast sources.
+pub fn enforce_throughput(stream: StreamType, minimum: MinThroughput) -> StreamType {
+    Box::pin(ThroughputStream {
+        inner: stream,
+        minimum,
+        received: 0,
+        waited: Duration::ZERO,
+        waiting_since: None,
+        timer: Box::pin(tokio::time::sleep(minimum.window)),
+        timed_out: false,
+        done: false,
+    })
+}
+
+struct ThroughputStream {
+    inner: StreamType,
+    minimum: MinThroughput,
+    /// What arrived, and how long it took, in the current window.
+    received: u64,
+    waited: Duration,
+    waiting_since: Option<Instant>,
+    /// Fires when the current window is over while waiting.
+    timer: Pin<Box<Sleep>>,
+    /// Set when a window fell short but ended with a delivery, which is
+    /// handed out before the timeout.
+    timed_out: bool,
+    /// Set once the stream ended or failed, after which it stays ended.
+    done: bool,
+}
+
+impl ThroughputStream {
+    /// Closes the current window, returning whether enough arrived in it.
+    fn end_window(&mut self) -> bool {
+        if self.received < self.minimum.bytes {
+            return false;
+        }
+        self.received = 0;
+        self.waited = Duration::ZERO;
+        true
+    }
+
+    fn timeout(&mut self) -> StorageError {
+        self.done = true;
+        StorageError::Timeout(
+            format!(
+                "less than {} bytes arrived within {:?}",
+                self.minimum.bytes, self.minimum.window
+            )
+            .into(),
+        )
+    }
+}
+
+impl Stream for ThroughputStream {
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+        if this.done {
+            return Poll::Ready(None);
+        }
+        if this.timed_out {
+            return Poll::Ready(Some(Err(this.timeout())));
+        }
+        if let Poll::Ready(item) = this.inner.poll_next_unpin(cx) {
+            if let Some(since) = this.waiting_since.take() {
+                this.waited += since.elapsed();
+            }
+            if let Some(Ok(chunk)) = &item {
+                this.received += chunk.len() as u64;
+            }
+            if item.is_none() {
+                this.done = true;
+            } else if this.waited >= this.minimum.window && !this.end_window() {
+                this.timed_out = true;
+            }
+            return Poll::Ready(item);
+        }
+
+        let now = Instant::now();
+        if this.waiting_since.is_none() {
+            this.waiting_since = Some(now);
+            let left = this.minimum.window.saturating_sub(this.waited);
+            this.timer.as_mut().reset(now + left);
+        }
+        if this.timer.as_mut().poll(cx).is_ready() {
+            this.waited = this.minimum.window;
+            if !this.end_window() {
+                return Poll::Ready(Some(Err(this.timeout())));
+            }
+            this.waiting_since = Some(now);
+            this.timer.as_mut().reset(now + this.minimum.window);
+            // registers the waker for the next window.
+            let _ = this.timer.as_mut().poll(cx);
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use futures::TryStreamExt;
+
+    async fn slice(offset: u64, length: Option<u64>) -> Vec<u8> {
+        let chunks = ["abc", "def", "ghi"].map(|c| Ok(Bytes::from(c)));
+        let stream: StreamType = Box::pin(futures::stream::iter(chunks));
+        let result: Vec<Bytes> = slice_stream(stream, offset, length)
+            .try_collect()
+            .await
+            .unwrap();
+        result.concat()
+    }
+
+    #[tokio::test]
+    async fn slices_across_chunks() {
+        assert_eq!(slice(0, None).await, b"abcdefghi");
+        assert_eq!(slice(2, Some(5)).await, b"cdefg");
+        assert_eq!(slice(3, Some(3)).await, b"def");
+        assert_eq!(slice(7, None).await, b"hi");
+        assert_eq!(slice(4, Some(0)).await, b"");
+        assert_eq!(slice(20, None).await, b"");
+        assert_eq!(slice(8, Some(10)).await, b"i");
+    }
+
+    #[tokio::test]
+    async fn stops_reading_once_the_range_is_complete() {
+        let chunks =
+            futures::stream::iter([Ok(Bytes::from("abc"))]).chain(futures::stream::pending());
+        let stream: StreamType = Box::pin(chunks);
+        let result: Vec<Bytes> = slice_stream(stream, 1, Some(2))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(result.concat(), b"bc");
+    }
+
+    const MINIMUM: MinThroughput = MinThroughput {
+        bytes: 1,
+        window: Duration::from_millis(50),
+    };
+
+    #[tokio::test]
+    async fn fails_stalled_streams() {
+        let chunks =
+            futures::stream::iter([Ok(Bytes::from("abc"))]).chain(futures::stream::pending());
+        let mut stream = enforce_throughput(Box::pin(chunks), MINIMUM);
+        assert_eq!(stream.next().await.unwrap().unwrap(), "abc");
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(StorageError::Timeout(_)))
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn delivers_chunks_that_end_a_short_window() {
+        let minimum = MinThroughput {
+            bytes: 10,
+            ..MINIMUM
+        };
+        let chunks = futures::stream::once(async {
+            tokio::task::yield_now().await;
+            Ok(Bytes::from("abc"))
+        });
+        let mut stream = enforce_throughput(Box::pin(chunks), minimum);
+        assert!(futures::poll!(stream.next()).is_pending());
+        // the chunk shows up after the window, before the timer is polled.
+        tokio::time::advance(minimum.window * 2).await;
+        assert_eq!(stream.next().await.unwrap().unwrap(), "abc");
+        assert!(matches!(
+            stream.next().await,
+            Some(Err(StorageError::Timeout(_)))
+        ));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn stays_ended() {
+        // unfold panics when it is polled after it ended.
+        let chunks = futures::stream::unfold(true, |first| async move {
+            first.then(|| (Ok(Bytes::from("abc")), false))
+        });
+        let mut stream = enforce_throughput(Box::pin(chunks), MINIMUM);
+        assert_eq!(stream.next().await.unwrap().unwrap(), "abc");
+        assert!(stream.next().await.is_none());
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn slow_readers_are_not_slow_streams() {
+        let chunks = ["abc", "def", "ghi"].map(|c| Ok(Bytes::from(c)));
+        let mut stream = enforce_throughput(Box::pin(futures::stream::iter(chunks)), MINIMUM);
+        let mut read = Vec::new();
+        while let Some(chunk) = stream.next().await {
+            read.extend_from_slice(&chunk.unwrap());
+            tokio::time::sleep(MINIMUM.window * 2).await;
+        }
+        assert_eq!(read, b"abcdefghi");
+    }
+}
@@ -0,0 +1,236 @@
+use async_trait::async_trait;
+use bytes::{Bytes, BytesMut};
+use futures::{StreamExt, TryStreamExt};
+
+use crate::{ChecksumHasher, Checksums, StorageError, StreamType};
+
+/// How [`crate::StorageContainer::set_content_parallel`] splits and sends
+/// content.
+#[derive(Debug, Clone)]
+pub struct UploadPolicy {
+    /// The size of the staged blocks. Raised to what the backend accepts.
+    pub block_size: usize,
+    /// How many blocks are staged at the same time.
+    pub concurrency: usize,
+}
+
+impl Default for UploadPolicy {
+    fn default() -> Self {
+        Self {
+            block_size: 8 * 1024 * 1024,
+            concurrency: 4,
+        }
+    }
+}
+
+/// An object written as numbered blocks that are staged independently and
+/// become its content when they are committed, see
+/// [`crate::StorageContainer::start_block_upload`].
+///
+/// Dropping an upload without committing it leaves the object untouched.
+#[async_trait]
+pub trait BlockUpload: Send + Sync {
+    /// The smallest block the backend accepts, except for the last one.
+    fn min_block_size(&self) -> usize {
+        1
+    }
+
+    /// Stages block number `index`, starting at 0. Blocks may be staged
+    /// concurrently and in any order, staging an index again replaces it.
+    async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError>;
+
+    /// Replaces the content with blocks `0..blocks` in order. `checksums`
+    /// are those of the whole content.
+    async fn commit(
+        self: Box<Self>,
+        blocks: usize,
+        checksums: Checksums,
+    ) -> Result<(), StorageError>;
+
+    /// Discards the staged blocks.
+    async fn abort(self: Box<Self>) -> Result<(), StorageError> {
+        Ok(())
+    }
+}
+
+/// Splits `content` into blocks of exactly `block_size` bytes but the last.
+/// Empty content is a single empty block, so it can still be committed.
+fn blocks(
+    content: StreamType,
+    block_size: usize,
+) -> impl futures::Stream<Item = Result<Bytes, StorageError>> {
+    let state = (Some(content), BytesMut::new(), false);
+    futures::stream::try_unfold(
+        state,
+        move |(mut content, mut buffer, started)| async move {
+            while buffer.len() < block_size {
+                let Some(chunk) = content.as_mut() else {
+                    break;
+                };
+                match chunk.next().await {
+                    Some(chunk) => buffer.extend_from_slice(&chunk?),
+                    None => content = None,
+                }
+            }
+            if buffer.is_empty() && started {
+                return Ok(None);
+            }
+            let block = buffer.split_to(block_size.min(buffer.len())).freeze();
+            Ok(Some((block, (content, buffer, true))))
+        },
+    )
+}
+
+/// Stages `content` through `upload` with up to `policy.concurrency` blocks
+/// in flight, and commits them once all are staged. The upload is aborted if
+/// anything fails.
+pub async fn upload_blocks(
+    upload: Box<dyn BlockUpload>,
+    content: StreamType,
+    policy: &UploadPolicy,
+) -> Result<(), StorageError> {
+    let block_size = policy.block_size.max(upload.min_block_size()).max(1);
+    let mut hasher = ChecksumHasher::new();
+    let staged = {
+        let upload = &upload;
+        let hasher = &mut hasher;
+        blocks(content, block_size)
+            .enumerate()
+            .map(|(index, block)| {
+                let block = block.inspect(|block| hasher.update(block));
+                async move { upload.stage_block(index, block?).await }
+            })
+            .buffer_unordered(policy.concurrency.max(1))
+            .try_fold(0, |staged, ()| async move { Ok(staged + 1) })
+            .await
+    };
+    match staged {
+        Ok(blocks) => upload.commit(blocks, hasher.finish()).await,
+        Err(e) => {
+            // the error of the upload matters more than that of cleaning up.
+            let _ = upload.abort().await;
+            Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{
+        collections::BTreeMap,
+        sync::{Arc, Mutex},
+    };
+
+    fn chunks(content: &[u8], size: usize) -> StreamType {
+        let chunks: Vec<_> = content
+            .chunks(size)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        Box::pin(futures::stream::iter(chunks))
+    }
+
+    #[derive(Default)]
+    struct Recorded {
+        staged: BTreeMap<usize, Bytes>,
+        in_flight: usize,
+        max_in_flight: usize,
+        committed: Option<(usize, Checksums)>,
+        aborted: bool,
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingUpload(Arc<Mutex<Recorded>>);
+
+    #[async_trait]
+    impl BlockUpload for RecordingUpload {
+        async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError> {
+            {
+                let mut recorded = self.0.lock().unwrap();
+                recorded.in_flight += 1;
+                recorded.max_in_flight = recorded.max_in_flight.max(recorded.in_flight);
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+            let mut recorded = self.0.lock().unwrap();
+            recorded.in_flight -= 1;
+            recorded.staged.insert(index, block);
+            Ok(())
+        }
+
+        async fn commit(
+            self: Box<Self>,
+            blocks: usize,
+            checksums: Checksums,
+        ) -> Result<(), StorageError> {
+            self.0.lock().unwrap().committed = Some((blocks, checksums));
+            Ok(())
+        }
+
+        async fn abort(self: Box<Self>) -> Result<(), StorageError> {
+            self.0.lock().unwrap().aborted = true;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn stages_blocks_concurrently() {
+        let content: Vec<u8> = (0..1000).map(|i| i as u8).collect();
+        let upload = RecordingUpload::default();
+        let policy = UploadPolicy {
+            block_size: 100,
+            concurrency: 3,
+        };
+        upload_blocks(Box::new(upload.clone()), chunks(&content, 33), &policy)
+            .await
+            .unwrap();
+
+        let recorded = upload.0.lock().unwrap();
+        assert_eq!(recorded.max_in_flight, 3);
+        assert_eq!(recorded.staged.len(), 10);
+        assert!(recorded.staged.values().all(|b| b.len() == 100));
+        assert_eq!(
+            recorded
+                .staged
+                .values()
+                .cloned()
+                .collect::<Vec<_>>()
+                .concat(),
+            content
+        );
+        assert_eq!(recorded.committed, Some((10, Checksums::of(&content))));
+    }
+
+    #[tokio::test]
+    async fn commits_empty_content_as_one_block() {
+        let upload = RecordingUpload::default();
+        upload_blocks(
+            Box::new(upload.clone()),
+            chunks(&[], 1),
+            &Default::default(),
+        )
+        .await
+        .unwrap();
+        let recorded = upload.0.lock().unwrap();
+        assert_eq!(recorded.staged.get(&0).map(Bytes::len), Some(0));
+        assert_eq!(recorded.committed.map(|c| c.0), Some(1));
+    }
+
+    #[tokio::test]
+    async fn aborts_when_the_content_fails() {
+        let upload = RecordingUpload::default();
+        let content: StreamType = Box::pin(futures::stream::iter([
+            Ok(Bytes::from("abc")),
+            Err(StorageError::Other(std::io::ErrorKind::BrokenPipe.into())),
+        ]));
+        let policy = UploadPolicy {
+            block_size: 2,
+            concurrency: 2,
+        };
+        assert!(upload_blocks(Box::new(upload.clone()), content, &policy)
+            .await
+            .is_err());
+        let recorded = upload.0.lock().unwrap();
+        assert!(recorded.aborted);
+        assert!(recorded.committed.is_none());
+    }
+}
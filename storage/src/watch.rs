@@ -0,0 +1,142 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    future::Future,
+    pin::Pin,
+    time::{Duration, SystemTime},
+};
+
+use futures::Stream;
+
+use crate::{ObjectInfo, StorageError};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChangeKind {
+    Created,
+    /// The content or the metadata changed.
+    Updated,
+    Deleted,
+}
+
+/// A change to a single object, see [`crate::StorageContainer::watch`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChangeEvent {
+    pub name: String,
+    pub kind: ChangeKind,
+}
+
+impl ChangeEvent {
+    pub fn new(name: impl Into<String>, kind: ChangeKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+        }
+    }
+}
+
+pub type WatchStream = Pin<Box<dyn Stream<Item = Result<ChangeEvent, StorageError>> + Send>>;
+
+type Snapshot = HashMap<String, (u64, Option<SystemTime>)>;
+
+fn snapshot(objects: Vec<ObjectInfo>) -> Snapshot {
+    objects
+        .into_iter()
+        .map(|o| (o.name, (o.size, o.last_modified)))
+        .collect()
+}
+
+fn diff(old: &Snapshot, new: &Snapshot, events: &mut VecDeque<ChangeEvent>) {
+    let mut changed: Vec<_> = new
+        .iter()
+        .filter_map(|(name, state)| match old.get(name) {
+            None => Some(ChangeEvent::new(name, ChangeKind::Created)),
+            Some(old) if old != state => Some(ChangeEvent::new(name, ChangeKind::Updated)),
+            Some(_) => None,
+        })
+        .chain(
+            old.keys()
+                .filter(|name| !new.contains_key(*name))
+                .map(|name| ChangeEvent::new(name, ChangeKind::Deleted)),
+        )
+        .collect();
+    changed.sort_by(|a, b| a.name.cmp(&b.name));
+    events.extend(changed);
+}
+
+/// Watches by listing every `interval` and comparing sizes and modification
+/// times, for backends without change notifications. Metadata-only changes
+/// are only seen if they touch the modification time.
+///
+/// The first listing happens before this returns, so only later changes are
+/// reported. Failed listings are yielded as errors and polling goes on.
+pub async fn poll_changes<F, Fut>(
+    interval: Duration,
+    mut list: F,
+) -> Result<WatchStream, StorageError>
+where
+    F: FnMut() -> Fut + Send + 'static,
+    Fut: Future<Output = Result<Vec<ObjectInfo>, StorageError>> + Send,
+{
+    let known = snapshot(list().await?);
+    let state = (list, known, VecDeque::new());
+    let stream =
+        futures::stream::unfold(state, move |(mut list, mut known, mut events)| async move {
+            loop {
+                if let Some(event) = events.pop_front() {
+                    return Some((Ok(event), (list, known, events)));
+                }
+                tokio::time::sleep(interval).await;
+                match list().await {
+                    Ok(objects) => {
+                        let current = snapshot(objects);
+                        diff(&known, &current, &mut events);
+                        known = current;
+                    }
+                    Err(e) => return Some((Err(e), (list, known, events))),
+                }
+            }
+        });
+    Ok(Box::pin(stream))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MemoryStorageContainer, StorageContainer};
+    use bytes::Bytes;
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    fn body(content: &'static str) -> crate::StreamType {
+        Box::pin(futures::stream::iter([Ok(Bytes::from(content))]))
+    }
+
+    #[tokio::test]
+    async fn polls_for_changes() {
+        let storage = Arc::new(MemoryStorageContainer::new());
+        storage.set_content("a", body("1")).await.unwrap();
+        storage.set_content("b", body("1")).await.unwrap();
+        let lister = storage.clone();
+        let mut changes = poll_changes(Duration::from_millis(10), move || {
+            let lister = lister.clone();
+            async move { lister.list_all("").await }
+        })
+        .await
+        .unwrap();
+
+        storage.set_content("a", body("22")).await.unwrap();
+        storage.delete("b").await.unwrap();
+        storage.set_content("c", body("1")).await.unwrap();
+        let mut events = Vec::new();
+        while events.len() < 3 {
+            events.push(changes.next().await.unwrap().unwrap());
+        }
+        assert_eq!(
+            events,
+            vec![
+                ChangeEvent::new("a", ChangeKind::Updated),
+                ChangeEvent::new("b", ChangeKind::Deleted),
+                ChangeEvent::new("c", ChangeKind::Created),
+            ]
+        );
+    }
+}
@@ -0,0 +1,1089 @@
+use std::{
+    collections::HashMap,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+use async_trait::async_trait;
+use azure_core::{
+    error::ErrorKind,
+    request_options::{IfMatchCondition, LeaseDuration, LeaseId, Metadata},
+    StatusCode, Url,
+};
+use azure_storage::{prelude::BlobSasPermissions, ConnectionString, StorageCredentials};
+use azure_storage_blobs::{
+    blob::{BlobBlockType, BlockList, CopyStatus},
+    prelude::*,
+};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt, TryStreamExt};
+use storage::{
+    guess_content_type, invalid_url, poll_changes, AppendSession, BlockUpload, ChecksumHasher,
+    Checksums, Lease, ListStream, ObjectInfo, ObjectProperties, Permissions, StorageContainer,
+    StorageError, StorageRegistry, StreamType, WatchStream, WriteCondition,
+};
+use time::OffsetDateTime;
+
+pub use azure_storage_blobs;
+
+/// The account name of the Azurite emulator.
+const EMULATOR_ACCOUNT: &str = "devstoreaccount1";
+
+/// Registers `az://account/container` URLs, see
+/// [`AzureStorageContainer::from_url`].
+pub fn register(registry: &mut StorageRegistry) {
+    registry.register("az", |url| {
+        Ok(Box::new(AzureStorageContainer::from_url(url)?))
+    });
+}
+
+/// The blob metadata key holding the (base64 encoded) container metadata.
+const METADATA_KEY: &str = "metadata";
+/// Marks an append blob that has not been sealed yet.
+const APPENDING_KEY: &str = "appending";
+/// Holds the CRC64 of the content, encoded as [`Checksums::crc64_base64`]
+/// (big-endian). Azure only keeps the MD5 itself; its `x-ms-content-crc64`
+/// header is checked per request and sent little-endian.
+const CRC64_KEY: &str = "crc64";
+const COPY_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+/// The size of the requests a blob is downloaded with.
+const GET_CHUNK_SIZE: u64 = 1024 * 1024;
+const DEFAULT_BLOCK_SIZE: usize = 4 * 1024 * 1024;
+/// The largest block a single append may carry.
+const MAX_APPEND_SIZE: usize = 4 * 1024 * 1024;
+/// How often tailing readers check an append blob for new blocks.
+const TAIL_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+/// The range of lease durations Azure accepts, other than infinite.
+const MIN_LEASE_SECONDS: u64 = 15;
+const MAX_LEASE_SECONDS: u64 = 60;
+const DEFAULT_WATCH_INTERVAL: std::time::Duration = std::time::Duration::from_secs(2);
+
+/// A container backed by an Azure blob container.
+///
+/// The metadata is kept base64 encoded in the blob metadata, so it survives
+/// the restrictions Azure puts on metadata values.
+#[derive(Clone)]
+pub struct AzureStorageContainer {
+    container: ContainerClient,
+    block_size: usize,
+    watch_interval: std::time::Duration,
+}
+
+impl AzureStorageContainer {
+    pub fn new(container: ContainerClient) -> Self {
+        Self {
+            container,
+            block_size: DEFAULT_BLOCK_SIZE,
+            watch_interval: DEFAULT_WATCH_INTERVAL,
+        }
+    }
+
+    pub fn from_connection_string(
+        connection_string: &str,
+        container: &str,
+    ) -> azure_core::Result<Self> {
+        let connection_string = ConnectionString::new(connection_string)?;
+        let account = connection_string
+            .account_name
+            .ok_or_else(|| azure_core::Error::message(ErrorKind::Other, "missing account name"))?;
+        let blob_service =
+            BlobServiceClient::new(account, connection_string.storage_credentials()?);
+        Ok(Self::new(blob_service.container_client(container)))
+    }
+
+    /// Opens the container of an `az://account/container` URL. A query string
+    /// is used as SAS token, otherwise the account key is read from
+    /// `AZURE_STORAGE_KEY`. The `devstoreaccount1` account is served by a local
+    /// Azurite emulator.
+    pub fn from_url(url: &storage::Url) -> Result<Self, StorageError> {
+        let account = url
+            .host_str()
+            .ok_or_else(|| invalid_url(url, "missing account"))?;
+        let segments: Vec<_> = url
+            .path_segments()
+            .map(|s| s.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        let [container] = segments[..] else {
+            return Err(invalid_url(url, "expected az://account/container"));
+        };
+        if account == EMULATOR_ACCOUNT && url.query().is_none() {
+            return Ok(Self::emulator(container));
+        }
+        let credentials = match (url.query(), std::env::var("AZURE_STORAGE_KEY")) {
+            (Some(sas), _) => StorageCredentials::sas_token(sas)
+                .map_err(|_| invalid_url(url, "invalid SAS token"))?,
+            (None, Ok(key)) => StorageCredentials::access_key(account, key),
+            (None, Err(_)) => StorageCredentials::anonymous(),
+        };
+        let blob_service = BlobServiceClient::new(account, credentials);
+        Ok(Self::new(blob_service.container_client(container)))
+    }
+
+    /// A container on a local Azurite emulator with its default settings.
+    pub fn emulator(container: &str) -> Self {
+        Self::new(ClientBuilder::emulator().container_client(container))
+    }
+
+    /// Sets the size of the blocks streamed content is uploaded in. Content
+    /// smaller than one block is uploaded with a single request.
+    pub fn with_block_size(mut self, block_size: usize) -> Self {
+        self.block_size = block_size.max(1);
+        self
+    }
+
+    /// Sets how often [`StorageContainer::watch`] lists the container, blob
+    /// storage has no change notifications of its own.
+    pub fn with_watch_interval(mut self, interval: std::time::Duration) -> Self {
+        self.watch_interval = interval;
+        self
+    }
+
+    pub fn container_client(&self) -> &ContainerClient {
+        &self.container
+    }
+
+    fn lease_client(&self, lease: &Lease) -> Result<BlobLeaseClient, StorageError> {
+        let id = lease.id.parse::<LeaseId>().map_err(|e| {
+            StorageError::Other(std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+        })?;
+        Ok(self
+            .container
+            .blob_client(&lease.path)
+            .blob_lease_client(id))
+    }
+
+    fn decode_metadata(metadata: &HashMap<String, String>) -> Result<Option<String>, StorageError> {
+        let Some(value) = metadata.get(METADATA_KEY) else {
+            return Ok(None);
+        };
+        let bytes = azure_core::base64::decode(value).map_err(Self::from_azure_error)?;
+        String::from_utf8(bytes)
+            .map(Some)
+            .map_err(|e| StorageError::HttpError(e.into()))
+    }
+
+    fn encode_metadata(metadata: &str) -> Metadata {
+        let mut encoded = Metadata::new();
+        encoded.insert(METADATA_KEY, azure_core::base64::encode(metadata));
+        encoded
+    }
+
+    fn to_if_match(condition: WriteCondition) -> IfMatchCondition {
+        match condition {
+            WriteCondition::IfMatch(etag) => IfMatchCondition::Match(etag),
+            WriteCondition::IfNoneMatch => IfMatchCondition::NotMatch("*".to_owned()),
+        }
+    }
+
+    /// Azure answers a failed `If-None-Match: *` with a conflict rather than
+    /// a failed precondition.
+    fn from_conditional_error(error: azure_core::Error) -> StorageError {
+        match Self::from_azure_error(error) {
+            StorageError::Conflict => StorageError::PreconditionFailed,
+            error => error,
+        }
+    }
+
+    pub fn from_azure_error(error: azure_core::Error) -> StorageError {
+        let status = match error.kind() {
+            ErrorKind::HttpResponse { status, .. } => *status,
+            ErrorKind::Io => {
+                return StorageError::Other(std::io::Error::new(
+                    std::io::ErrorKind::ConnectionAborted,
+                    error,
+                ))
+            }
+            ErrorKind::Credential => return StorageError::AuthenticationError,
+            _ => return StorageError::HttpError(error.into()),
+        };
+        match status {
+            StatusCode::NotFound => StorageError::NotFound,
+            StatusCode::Forbidden | StatusCode::Unauthorized => StorageError::AuthenticationError,
+            StatusCode::Conflict => StorageError::Conflict,
+            StatusCode::PreconditionFailed => StorageError::PreconditionFailed,
+            StatusCode::RequestTimeout | StatusCode::GatewayTimeout => {
+                StorageError::Timeout(error.into())
+            }
+            StatusCode::TooManyRequests => StorageError::Throttled(error.into()),
+            StatusCode::InternalServerError
+            | StatusCode::BadGateway
+            | StatusCode::ServiceUnavailable => StorageError::ServiceUnavailable(error.into()),
+            _ => StorageError::HttpError(error.into()),
+        }
+    }
+
+    async fn write_content(
+        &self,
+        path: &str,
+        mut content: StreamType,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        // replacing a blob drops its metadata, so carry it over like the other containers do.
+        let blob_client = self.container.blob_client(path);
+        let existing = head(&blob_client).await?;
+        let if_match = match (condition, &existing) {
+            (Some(WriteCondition::IfNoneMatch), Some(_)) => {
+                return Err(StorageError::PreconditionFailed)
+            }
+            (Some(WriteCondition::IfMatch(_)), None) => {
+                return Err(StorageError::PreconditionFailed)
+            }
+            (Some(condition), _) => Some(Self::to_if_match(condition)),
+            (None, _) => None,
+        };
+        let mut metadata = Metadata::new();
+        if let Some(value) = existing.and_then(|(_, mut metadata)| metadata.remove(METADATA_KEY)) {
+            metadata.insert(METADATA_KEY, value);
+        }
+        let content_type = guess_content_type(path).map(BlobContentType::from);
+
+        let mut hasher = ChecksumHasher::new();
+        let (first, done) = read_block(&mut content, self.block_size).await?;
+        hasher.update(&first);
+        if done {
+            let checksums = hasher.finish();
+            insert_crc64(&mut metadata, &checksums);
+            // Azure checks the MD5 on receipt and keeps it as the blob's Content-MD5.
+            let mut builder = blob_client.put_block_blob(first).metadata(metadata);
+            if let Some(md5) = checksums.md5 {
+                builder = builder.hash(Hash::MD5(md5));
+            }
+            if let Some(content_type) = content_type {
+                builder = builder.content_type(content_type);
+            }
+            if let Some(if_match) = if_match {
+                builder = builder.if_match(if_match);
+            }
+            builder
+                .into_future()
+                .await
+                .map_err(Self::from_conditional_error)?;
+            return Ok(());
+        }
+
+        let upload: u64 = rand::random();
+        let mut block_list = BlockList::default();
+        let mut block = first;
+        let mut done = false;
+        while !block.is_empty() {
+            let block_id = block_id(upload, block_list.blocks.len());
+            blob_client
+                .put_block(block_id.clone(), block)
+                .into_future()
+                .await
+                .map_err(Self::from_azure_error)?;
+            block_list
+                .blocks
+                .push(BlobBlockType::new_uncommitted(block_id));
+            if done {
+                break;
+            }
+            (block, done) = read_block(&mut content, self.block_size).await?;
+            hasher.update(&block);
+        }
+        let checksums = hasher.finish();
+        commit_blocks(&blob_client, block_list, metadata, &checksums, if_match).await
+    }
+
+    async fn write_metadata(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        // setting metadata replaces all of it, so look at what is there to
+        // keep an unsealed append blob unsealed and the CRC64.
+        let blob_client = self.container.blob_client(path);
+        let (etag, existing) = head(&blob_client).await?.ok_or(StorageError::NotFound)?;
+        let if_match = match condition {
+            None => None,
+            Some(WriteCondition::IfNoneMatch) if existing.contains_key(METADATA_KEY) => {
+                return Err(StorageError::PreconditionFailed)
+            }
+            // pin the ETag we looked at.
+            Some(WriteCondition::IfNoneMatch) => Some(IfMatchCondition::Match(etag)),
+            Some(condition) => Some(Self::to_if_match(condition)),
+        };
+        let mut encoded = Self::encode_metadata(&metadata);
+        for key in [APPENDING_KEY, CRC64_KEY] {
+            if let Some(value) = existing.get(key) {
+                encoded.insert(key, value.clone());
+            }
+        }
+        let mut builder = blob_client.set_metadata().metadata(encoded);
+        if let Some(if_match) = if_match {
+            builder = builder.if_match(if_match);
+        }
+        builder
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        Ok(())
+    }
+}
+
+/// Concurrent uploads to the same blob must not share uncommitted block ids,
+/// so they are prefixed with a random id per upload. All ids of a blob have
+/// the same length.
+fn block_id(upload: u64, index: usize) -> Bytes {
+    Bytes::from(format!("{upload:016x}{index:08}"))
+}
+
+/// Makes the staged `block_list` the content of the blob, with the given
+/// metadata and checksums of the whole content.
+async fn commit_blocks(
+    blob_client: &BlobClient,
+    block_list: BlockList,
+    mut metadata: Metadata,
+    checksums: &Checksums,
+    if_match: Option<IfMatchCondition>,
+) -> Result<(), StorageError> {
+    insert_crc64(&mut metadata, checksums);
+    let mut builder = blob_client.put_block_list(block_list).metadata(metadata);
+    if let Some(md5) = checksums.md5 {
+        builder = builder.content_md5(BlobContentMD5::from(md5));
+    }
+    if let Some(content_type) = guess_content_type(blob_client.blob_name()) {
+        builder = builder.content_type(BlobContentType::from(content_type));
+    }
+    if let Some(if_match) = if_match {
+        builder = builder.if_match(if_match);
+    }
+    builder
+        .into_future()
+        .await
+        .map_err(AzureStorageContainer::from_conditional_error)?;
+    Ok(())
+}
+
+fn insert_crc64(metadata: &mut Metadata, checksums: &Checksums) {
+    if let Some(crc64) = checksums.crc64_base64() {
+        metadata.insert(CRC64_KEY, crc64);
+    }
+}
+
+/// The ETag and metadata of an existing blob, if any.
+async fn head(
+    blob_client: &BlobClient,
+) -> Result<Option<(String, HashMap<String, String>)>, StorageError> {
+    match blob_client.get_properties().into_future().await {
+        Ok(properties) => Ok(Some((
+            properties.blob.properties.etag.to_string(),
+            properties.blob.metadata.unwrap_or_default(),
+        ))),
+        Err(e) => match AzureStorageContainer::from_azure_error(e) {
+            StorageError::NotFound => Ok(None),
+            e => Err(e),
+        },
+    }
+}
+
+async fn read(
+    blob_client: &BlobClient,
+    range: Option<std::ops::Range<u64>>,
+) -> Result<StreamType, StorageError> {
+    let mut builder = blob_client.get().chunk_size(GET_CHUNK_SIZE);
+    if let Some(range) = range {
+        builder = builder.range(range);
+    }
+    let mut chunks = builder
+        .into_stream()
+        .map_ok(|response| response.data)
+        .try_flatten()
+        .map_err(AzureStorageContainer::from_azure_error);
+    // fail right away if the blob can't be read.
+    let first = match chunks.next().await {
+        Some(Ok(first)) => first,
+        Some(Err(e)) => return Err(e),
+        None => return Ok(Box::pin(futures::stream::empty())),
+    };
+    let stream = futures::stream::once(async { Ok(first) }).chain(chunks);
+    Ok(Box::pin(SyncStream(Mutex::new(Box::pin(stream)))))
+}
+
+struct Tail {
+    blob_client: BlobClient,
+    offset: u64,
+    current: Option<StreamType>,
+    sealed: bool,
+}
+
+/// Reads a blob and then polls it for appended blocks until it is sealed.
+fn tail(blob_client: BlobClient) -> StreamType {
+    let tail = Tail {
+        blob_client,
+        offset: 0,
+        current: None,
+        sealed: false,
+    };
+    let stream = futures::stream::unfold(Some(tail), |state| async move {
+        let mut tail = state?;
+        loop {
+            if let Some(current) = &mut tail.current {
+                match current.next().await {
+                    Some(Ok(chunk)) => {
+                        tail.offset += chunk.len() as u64;
+                        return Some((Ok(chunk), Some(tail)));
+                    }
+                    Some(Err(e)) => return Some((Err(e), None)),
+                    None => tail.current = None,
+                }
+            }
+            if tail.sealed {
+                return None;
+            }
+            let (length, metadata) = match tail.blob_client.get_properties().into_future().await {
+                Ok(properties) => (
+                    properties.blob.properties.content_length,
+                    properties.blob.metadata,
+                ),
+                Err(e) => return Some((Err(AzureStorageContainer::from_azure_error(e)), None)),
+            };
+            // the last blocks may have been appended right before sealing,
+            // so read up to the length seen together with the seal.
+            tail.sealed = !metadata.is_some_and(|m| m.contains_key(APPENDING_KEY));
+            if tail.offset < length {
+                match read(&tail.blob_client, Some(tail.offset..length)).await {
+                    Ok(stream) => tail.current = Some(stream),
+                    Err(e) => return Some((Err(e), None)),
+                }
+            } else if !tail.sealed {
+                tokio::time::sleep(TAIL_POLL_INTERVAL).await;
+            }
+        }
+    });
+    Box::pin(SyncStream(Mutex::new(Box::pin(stream))))
+}
+
+/// Appends blocks to an append blob, which is unsealed while the
+/// [`APPENDING_KEY`] is in its metadata.
+struct AzureAppendSession {
+    blob_client: BlobClient,
+    hasher: ChecksumHasher,
+}
+
+#[async_trait]
+impl AppendSession for AzureAppendSession {
+    async fn append(&mut self, chunk: Bytes) -> Result<(), StorageError> {
+        let mut offset = 0;
+        while offset < chunk.len() {
+            let end = chunk.len().min(offset + MAX_APPEND_SIZE);
+            self.blob_client
+                .append_block(chunk.slice(offset..end))
+                .into_future()
+                .await
+                .map_err(AzureStorageContainer::from_azure_error)?;
+            offset = end;
+        }
+        self.hasher.update(&chunk);
+        Ok(())
+    }
+
+    async fn seal(self: Box<Self>) -> Result<(), StorageError> {
+        // the metadata may be set concurrently, so only remove the marker
+        // from the metadata we looked at.
+        loop {
+            let (etag, mut existing) = head(&self.blob_client)
+                .await?
+                .ok_or(StorageError::NotFound)?;
+            existing.remove(APPENDING_KEY);
+            let mut metadata = Metadata::new();
+            for (key, value) in existing {
+                metadata.insert(key, value);
+            }
+            insert_crc64(&mut metadata, &self.hasher.clone().finish());
+            let result = self
+                .blob_client
+                .set_metadata()
+                .metadata(metadata)
+                .if_match(IfMatchCondition::Match(etag))
+                .into_future()
+                .await
+                .map_err(AzureStorageContainer::from_azure_error);
+            match result {
+                Err(StorageError::PreconditionFailed) => continue,
+                result => return result.map(|_| ()),
+            }
+        }
+    }
+}
+
+/// Stages blocks of a block blob, which keeps its content until the block
+/// list is committed.
+struct AzureBlockUpload {
+    blob_client: BlobClient,
+    upload: u64,
+}
+
+#[async_trait]
+impl BlockUpload for AzureBlockUpload {
+    async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError> {
+        self.blob_client
+            .put_block(block_id(self.upload, index), block)
+            .into_future()
+            .await
+            .map_err(AzureStorageContainer::from_azure_error)?;
+        Ok(())
+    }
+
+    async fn commit(
+        self: Box<Self>,
+        blocks: usize,
+        checksums: Checksums,
+    ) -> Result<(), StorageError> {
+        // committing replaces the metadata, carry it over from the blob as
+        // it is now.
+        let mut metadata = Metadata::new();
+        if let Some((_, mut existing)) = head(&self.blob_client).await? {
+            if let Some(value) = existing.remove(METADATA_KEY) {
+                metadata.insert(METADATA_KEY, value);
+            }
+        }
+        let block_list = BlockList {
+            blocks: (0..blocks)
+                .map(|index| BlobBlockType::new_uncommitted(block_id(self.upload, index)))
+                .collect(),
+        };
+        commit_blocks(&self.blob_client, block_list, metadata, &checksums, None).await
+    }
+
+    // uncommitted blocks are garbage collected by Azure after a week.
+}
+
+/// Reads up to `size` bytes and reports whether the stream is exhausted.
+async fn read_block(content: &mut StreamType, size: usize) -> Result<(Bytes, bool), StorageError> {
+    let mut block = BytesMut::new();
+    while block.len() < size {
+        match content.next().await {
+            Some(chunk) => block.extend_from_slice(&chunk?),
+            None => return Ok((block.freeze(), true)),
+        }
+    }
+    Ok((block.freeze(), false))
+}
+
+/// The SDK streams are `Send` but not `Sync`. The mutex is never contended,
+/// since polling needs a mutable reference anyway.
+struct SyncStream<S>(Mutex<Pin<Box<S>>>);
+
+impl<S: Stream + Send> Stream for SyncStream<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.0.get_mut().unwrap().as_mut().poll_next(cx)
+    }
+}
+
+#[async_trait]
+impl StorageContainer for AzureStorageContainer {
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        read(&self.container.blob_client(path), None).await
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        let end = match length {
+            Some(length) => offset.saturating_add(length),
+            None => {
+                let properties = self
+                    .container
+                    .blob_client(path)
+                    .get_properties()
+                    .into_future()
+                    .await
+                    .map_err(Self::from_azure_error)?;
+                properties.blob.properties.content_length
+            }
+        };
+        if end <= offset {
+            return Ok(Box::pin(futures::stream::empty()));
+        }
+        read(&self.container.blob_client(path), Some(offset..end)).await
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        let properties = self
+            .container
+            .blob_client(path)
+            .get_properties()
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        match &properties.blob.metadata {
+            Some(metadata) => Self::decode_metadata(metadata)?.ok_or(StorageError::NotFound),
+            None => Err(StorageError::NotFound),
+        }
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        self.write_content(path, content, None).await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        self.write_metadata(path, metadata, None).await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.write_content(path, content, Some(condition)).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.write_metadata(path, metadata, Some(condition)).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        let blob_client = self.container.blob_client(path);
+        match blob_client.get_properties().into_future().await {
+            Ok(_) => Ok(true),
+            Err(e) => match Self::from_azure_error(e) {
+                StorageError::NotFound => Ok(false),
+                e => Err(e),
+            },
+        }
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        let blob_client = self.container.blob_client(path);
+        let response = blob_client
+            .get_properties()
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        let blob = response.blob;
+        let metadata = match &blob.metadata {
+            Some(metadata) => Self::decode_metadata(metadata)?,
+            None => None,
+        };
+        let crc64 = blob
+            .metadata
+            .as_ref()
+            .and_then(|metadata| metadata.get(CRC64_KEY));
+        let checksums = Checksums {
+            md5: blob.properties.content_md5.map(|md5| *md5.as_slice()),
+            ..Checksums::from_base64(None, crc64.map(String::as_str))
+        };
+        Ok(ObjectProperties {
+            content_length: blob.properties.content_length,
+            etag: Some(blob.properties.etag.to_string()),
+            content_type: Some(blob.properties.content_type),
+            last_modified: Some(blob.properties.last_modified.into()),
+            metadata,
+            checksums,
+        })
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        self.container
+            .blob_client(path)
+            .delete()
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        Ok(())
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let source = self
+            .container
+            .blob_client(from)
+            .url()
+            .map_err(Self::from_azure_error)?;
+        let target = self.container.blob_client(to);
+        let response = target
+            .copy(source)
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+
+        // copies within an account usually finish right away, but may be pending.
+        let mut status = response.copy_status;
+        while status == CopyStatus::Pending {
+            tokio::time::sleep(COPY_POLL_INTERVAL).await;
+            let properties = target
+                .get_properties()
+                .into_future()
+                .await
+                .map_err(Self::from_azure_error)?;
+            status = properties
+                .blob
+                .properties
+                .copy_status
+                .unwrap_or(CopyStatus::Success);
+        }
+        match status {
+            CopyStatus::Success => Ok(()),
+            _ => Err(StorageError::HttpError(
+                format!("copy from {from} to {to} finished with status {status:?}").into(),
+            )),
+        }
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.copy(from, to).await?;
+        self.delete(from).await
+    }
+
+    async fn open_append(&self, path: &str) -> Result<Box<dyn AppendSession>, StorageError> {
+        let blob_client = self.container.blob_client(path);
+        let mut metadata = Metadata::new();
+        if let Some((_, mut existing)) = head(&blob_client).await? {
+            if let Some(value) = existing.remove(METADATA_KEY) {
+                metadata.insert(METADATA_KEY, value);
+            }
+        }
+        metadata.insert(APPENDING_KEY, "true");
+        let mut builder = blob_client.put_append_blob().metadata(metadata);
+        if let Some(content_type) = guess_content_type(path) {
+            builder = builder.content_type(BlobContentType::from(content_type));
+        }
+        builder
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        Ok(Box::new(AzureAppendSession {
+            blob_client,
+            hasher: ChecksumHasher::new(),
+        }))
+    }
+
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        Ok(Box::new(AzureBlockUpload {
+            blob_client: self.container.blob_client(path),
+            upload: rand::random(),
+        }))
+    }
+
+    /// Uses a blob lease on the object, creating an empty blob if there is
+    /// none. Azure leases last between 15 and 60 seconds.
+    async fn acquire_lease(
+        &self,
+        path: &str,
+        ttl: std::time::Duration,
+    ) -> Result<Lease, StorageError> {
+        let seconds = ttl.as_secs();
+        if !(MIN_LEASE_SECONDS..=MAX_LEASE_SECONDS).contains(&seconds) {
+            return Err(StorageError::Other(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("Azure leases last between {MIN_LEASE_SECONDS} and {MAX_LEASE_SECONDS}s"),
+            )));
+        }
+        let blob_client = self.container.blob_client(path);
+        if head(&blob_client).await?.is_none() {
+            let created = blob_client
+                .put_block_blob(Bytes::new())
+                .if_match(Self::to_if_match(WriteCondition::IfNoneMatch))
+                .into_future()
+                .await
+                .map_err(Self::from_conditional_error);
+            match created {
+                // created concurrently by someone else.
+                Ok(_) | Err(StorageError::PreconditionFailed) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        let response = blob_client
+            .acquire_lease(LeaseDuration::Seconds(seconds as u8))
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        Ok(Lease {
+            path: path.to_owned(),
+            id: response.lease_id.to_string(),
+            ttl,
+        })
+    }
+
+    async fn renew_lease(&self, lease: &Lease) -> Result<(), StorageError> {
+        self.lease_client(lease)?
+            .renew()
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        Ok(())
+    }
+
+    async fn release_lease(&self, lease: Lease) -> Result<(), StorageError> {
+        self.lease_client(&lease)?
+            .release()
+            .into_future()
+            .await
+            .map_err(Self::from_azure_error)?;
+        Ok(())
+    }
+
+    /// Returns a blob SAS URL. Blob SAS can't be scoped to a prefix, so a
+    /// `path` ending in `/` gets a container SAS instead, which grants
+    /// `permissions` on every blob of the container and not only on those
+    /// below `path`.
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: std::time::Duration,
+    ) -> Result<Url, StorageError> {
+        let blob_client = self.container.blob_client(path);
+        let permissions = BlobSasPermissions {
+            read: permissions.read,
+            write: permissions.write,
+            delete: permissions.delete,
+            ..Default::default()
+        };
+        let expiry = OffsetDateTime::now_utc() + expiry;
+        let sas = if path.ends_with('/') {
+            self.container.shared_access_signature(permissions, expiry)
+        } else {
+            blob_client.shared_access_signature(permissions, expiry)
+        };
+        // only account keys can sign, containers opened with a SAS token or
+        // anonymously can't.
+        let sas = sas.map_err(|e| match e.kind() {
+            ErrorKind::Credential => StorageError::Unsupported,
+            _ => Self::from_azure_error(e),
+        })?;
+        blob_client
+            .generate_signed_blob_url(&sas)
+            .map_err(Self::from_azure_error)
+    }
+
+    async fn get_content_tail(&self, path: &str) -> Result<StreamType, StorageError> {
+        let blob_client = self.container.blob_client(path);
+        if head(&blob_client).await?.is_none() {
+            return Err(StorageError::NotFound);
+        }
+        Ok(tail(blob_client))
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        let pages = self
+            .container
+            .list_blobs()
+            .prefix(prefix.to_owned())
+            .into_stream()
+            .map(|page| {
+                page.map(|p| {
+                    p.blobs
+                        .blobs()
+                        .map(|b| ObjectInfo {
+                            name: b.name.clone(),
+                            size: b.properties.content_length,
+                            last_modified: Some(b.properties.last_modified.into()),
+                        })
+                        .collect()
+                })
+                .map_err(Self::from_azure_error)
+            });
+        Ok(Box::pin(pages))
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        let storage = self.clone();
+        let prefix = prefix.to_owned();
+        poll_changes(self.watch_interval, move || {
+            let storage = storage.clone();
+            let prefix = prefix.clone();
+            async move { storage.list_all(&prefix).await }
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_metadata() {
+        let encoded = AzureStorageContainer::encode_metadata("{\"duration\": 1.5}\n");
+        let value = encoded.get(METADATA_KEY).unwrap();
+        let metadata = HashMap::from([(
+            METADATA_KEY.to_owned(),
+            String::from_utf8(value.to_vec()).unwrap(),
+        )]);
+        let decoded = AzureStorageContainer::decode_metadata(&metadata).unwrap();
+        assert_eq!(decoded.as_deref(), Some("{\"duration\": 1.5}\n"));
+    }
+
+    #[test]
+    fn opens_az_urls() {
+        let url = storage::Url::parse("az://account/videos?sv=2022-11-02&sig=abc").unwrap();
+        let storage = AzureStorageContainer::from_url(&url).unwrap();
+        assert_eq!(storage.container_client().container_name(), "videos");
+        let url = storage::Url::parse("az://account/videos/extra").unwrap();
+        assert!(AzureStorageContainer::from_url(&url).is_err());
+    }
+
+    #[tokio::test]
+    async fn presigns_blob_urls() {
+        let storage = AzureStorageContainer::from_connection_string(
+            "AccountName=account;AccountKey=a2V5;EndpointSuffix=core.windows.net",
+            "videos",
+        )
+        .unwrap();
+        let expiry = std::time::Duration::from_secs(600);
+        let url = storage
+            .presign("video.mp4", Permissions::READ, expiry)
+            .await
+            .unwrap();
+        assert_eq!(url.path(), "/videos/video.mp4");
+        assert!(url.query_pairs().any(|(k, v)| k == "sp" && v == "r"));
+        assert!(url.query_pairs().any(|(k, v)| k == "sr" && v == "b"));
+        let url = storage
+            .presign("level0/", Permissions::WRITE, expiry)
+            .await
+            .unwrap();
+        assert_eq!(url.path(), "/videos/level0/");
+        assert!(url.query_pairs().any(|(k, v)| k == "sp" && v == "w"));
+        assert!(url.query_pairs().any(|(k, v)| k == "sr" && v == "c"));
+
+        // a SAS token can't sign further URLs.
+        let url = storage::Url::parse("az://account/videos?sv=2022-11-02&sig=abc").unwrap();
+        let storage = AzureStorageContainer::from_url(&url).unwrap();
+        assert!(matches!(
+            storage
+                .presign("video.mp4", Permissions::READ, expiry)
+                .await,
+            Err(StorageError::Unsupported)
+        ));
+    }
+
+    /// Runs the conformance suite against Azurite when `AZURITE_TEST` is set.
+    #[tokio::test]
+    async fn conforms_on_azurite() {
+        if std::env::var("AZURITE_TEST").is_err() {
+            return;
+        }
+        let storage = AzureStorageContainer::emulator("storage-azure-conformance");
+        let _ = storage.container_client().create().into_future().await;
+        storage.delete_prefix("").await.unwrap();
+        storage::conformance::run(&storage, "").await;
+    }
+
+    /// Runs against a local Azurite emulator when `AZURITE_TEST` is set.
+    #[tokio::test]
+    async fn round_trip_against_azurite() {
+        if std::env::var("AZURITE_TEST").is_err() {
+            return;
+        }
+        let storage = AzureStorageContainer::emulator("storage-azure-test").with_block_size(1000);
+        let _ = storage.container_client().create().into_future().await;
+        storage.delete_prefix("").await.unwrap();
+
+        let content: Vec<u8> = (0..2500).map(|i| i as u8).collect();
+        let chunks: Vec<_> = content
+            .chunks(300)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        storage
+            .set_content("video.mp4", Box::pin(futures::stream::iter(chunks)))
+            .await
+            .unwrap();
+        storage
+            .set_metadata("video.mp4", "{}".to_owned())
+            .await
+            .unwrap();
+        let read: Vec<Bytes> = storage
+            .get_content("video.mp4")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(read.concat(), content);
+        let range: Vec<Bytes> = storage
+            .get_content_range("video.mp4", 10, Some(5))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(range.concat(), &content[10..15]);
+        assert_eq!(storage.get_metadata("video.mp4").await.unwrap(), "{}");
+        let properties = storage.stat("video.mp4").await.unwrap();
+        assert_eq!(properties.checksums, Checksums::of(&content));
+
+        // staged blocks replace the content once committed.
+        let reversed: Vec<u8> = content.iter().rev().copied().collect();
+        let policy = storage::UploadPolicy {
+            block_size: 1000,
+            concurrency: 2,
+        };
+        let body = futures::stream::iter([Ok(Bytes::from(reversed.clone()))]);
+        storage
+            .set_content_parallel("video.mp4", Box::pin(body), &policy)
+            .await
+            .unwrap();
+        let read: Vec<Bytes> = storage
+            .get_content("video.mp4")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(read.concat(), reversed);
+        assert_eq!(storage.get_metadata("video.mp4").await.unwrap(), "{}");
+        let properties = storage.stat("video.mp4").await.unwrap();
+        assert_eq!(properties.checksums, Checksums::of(&reversed));
+
+        // replacing the content keeps the metadata.
+        let small = Box::pin(futures::stream::iter([Ok(Bytes::from("abc"))]));
+        storage.set_content("video.mp4", small).await.unwrap();
+        assert_eq!(storage.get_metadata("video.mp4").await.unwrap(), "{}");
+        let small = Box::pin(futures::stream::iter([Ok(Bytes::from("abc"))]));
+        assert!(matches!(
+            storage
+                .set_content_if("video.mp4", small, WriteCondition::IfNoneMatch)
+                .await,
+            Err(StorageError::PreconditionFailed)
+        ));
+        assert!(matches!(
+            storage.get_content("missing.mp4").await,
+            Err(StorageError::NotFound)
+        ));
+
+        // appends are tailed until the blob is sealed, and keep the metadata.
+        let mut session = storage.open_append("video.mp4").await.unwrap();
+        session.append(Bytes::from("abc")).await.unwrap();
+        let mut tail = storage.get_content_tail("video.mp4").await.unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), "abc");
+        session.append(Bytes::from("def")).await.unwrap();
+        storage
+            .set_metadata("video.mp4", "{\"live\": true}".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(tail.try_next().await.unwrap().unwrap(), "def");
+        session.seal().await.unwrap();
+        assert!(tail.try_next().await.unwrap().is_none());
+        assert_eq!(
+            storage.get_metadata("video.mp4").await.unwrap(),
+            "{\"live\": true}"
+        );
+
+        // one holder at a time, with a blob lease.
+        let ttl = std::time::Duration::from_secs(15);
+        let lease = storage.acquire_lease("video.lock", ttl).await.unwrap();
+        assert!(matches!(
+            storage.acquire_lease("video.lock", ttl).await,
+            Err(StorageError::Conflict)
+        ));
+        storage.renew_lease(&lease).await.unwrap();
+        storage.release_lease(lease).await.unwrap();
+        let lease = storage.acquire_lease("video.lock", ttl).await.unwrap();
+        storage.release_lease(lease).await.unwrap();
+        assert_eq!(storage.delete_prefix("").await.unwrap(), 2);
+    }
+}
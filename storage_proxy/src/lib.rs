@@ -1,9 +1,44 @@
+pub mod protocol;
+mod server;
 mod storage_client;
 
+use storage::StorageRegistry;
+
+pub use server::{ProxyServer, VideoOpener};
+pub use storage_client::{StorageClient, StorageConfig};
+
+/// Registers `http://host:port/account/video` URLs for videos served by a
+/// storage proxy.
+pub fn register(registry: &mut StorageRegistry) {
+    register_with(registry, StorageConfig::default());
+}
+
+/// Registers `http://host:port/account/video` and `https://host:port/account/video`
+/// URLs for videos served by a storage proxy, with the CA certificate and the
+/// signer of `config`.
+pub fn register_with(registry: &mut StorageRegistry, config: StorageConfig) {
+    for scheme in ["http", "https"] {
+        let config = config.clone();
+        registry.register(scheme, move |url| {
+            Ok(Box::new(StorageClient::from_url_with(url, config.clone())?))
+        });
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     #[test]
     fn it_works() {}
+
+    #[test]
+    fn opens_proxy_urls() {
+        let mut registry = StorageRegistry::empty();
+        register(&mut registry);
+        assert!(registry.open_str("http://proxy:8080/account/video").is_ok());
+        assert!(registry.open_str("http://proxy:8080/account").is_err());
+        assert!(registry.open_str("http://proxy/account/video/extra").is_err());
+        assert!(registry.open_str("https://proxy/account/video").is_ok());
+    }
 }
@@ -0,0 +1,45 @@
+use log::info;
+use storage::{StorageRegistry, UrlSigner};
+use storage_proxy::ProxyServer;
+
+/// Serves the storage a URL template points to, for example
+/// `storage_proxy 'file:///data/{account}/{video}'` or
+/// `storage_proxy 's3://{account}'`. Listens on `BIND_ENDPOINT`, which
+/// defaults to `0.0.0.0:8080`.
+///
+/// With `SIGNING_KEY` set, only signed requests are answered. With
+/// `TLS_CERTIFICATE` and `TLS_KEY` pointing to a PEM certificate chain and
+/// its PKCS #8 key, the proxy serves HTTPS.
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let mut args = std::env::args();
+    args.next();
+    let Some(template) = args.next() else {
+        anyhow::bail!("usage: storage_proxy <storage url template>");
+    };
+    let mut registry = StorageRegistry::new();
+    storage_azure::register(&mut registry);
+    storage_s3::register(&mut registry);
+    let mut server = ProxyServer::from_template(registry, &template);
+    if let Ok(key) = std::env::var("SIGNING_KEY") {
+        server = server.with_signer(UrlSigner::new(key));
+    }
+    if let (Ok(certificate), Ok(key)) = (std::env::var("TLS_CERTIFICATE"), std::env::var("TLS_KEY"))
+    {
+        server = server.with_tls(&std::fs::read(certificate)?, &std::fs::read(key)?)?;
+    }
+
+    let addr = std::env::var("BIND_ENDPOINT").unwrap_or_else(|_| "0.0.0.0:8080".into());
+    let listener = tokio::net::TcpListener::bind(&addr).await?;
+    info!("Serving {} on {}", template, addr);
+    let shutdown = async {
+        tokio::signal::ctrl_c()
+            .await
+            .expect("failed to install CTRL+C signal handler");
+        info!("Received Ctrl+C. Terminating...");
+    };
+    server.serve(listener, shutdown).await?;
+    Ok(())
+}
@@ -0,0 +1,299 @@
+//! Wire types shared by the storage proxy client and server.
+//!
+//! Objects live at `/{account}/{video}/{path}` and their metadata at
+//! `/{account}/{video}/{path}/metadata`. Listing is a `GET` on
+//! `/{account}/{video}/{prefix}?list` which answers with one JSON array of
+//! [`ListEntry`] per line, one line per page.
+//!
+//! `DELETE` on an object removes it, and `DELETE` on
+//! `/{account}/{video}/{prefix}?recursive` removes everything under the
+//! prefix and answers with the number of deleted objects. A `POST` with an
+//! empty body and `?copy_from={path}` or `?move_from={path}` copies or moves
+//! another object of the same video onto the target path.
+//!
+//! A `GET` on `/{account}/{video}/{prefix}?watch` keeps the response open and
+//! pushes one [`WatchEntry`] per line for every change under the prefix.
+//!
+//! Reads and `HEAD` requests report the stored checksums of an object in the
+//! `Content-MD5` and `x-content-crc64` headers, both base64 encoded as in
+//! [`storage::Checksums`].
+//!
+//! Conditional writes send `If-Match: "<etag>"` or `If-None-Match: *` and the
+//! server answers `412 Precondition Failed` when the condition does not hold.
+//!
+//! Servers with a key only answer requests whose query is signed as by
+//! [`storage::UrlSigner`], for the path or a directory above it and the
+//! permissions of [`permissions_for`] the method. Metadata requests and the
+//! query flags that change what a method does are signed for that operation,
+//! see [`operation_of`], so that a signature to delete an object does not
+//! delete a prefix. Copies and moves also need a signature for the source
+//! that allows reading it, and deleting it for moves; one signature for a
+//! directory above both covers them. Bodies that are not streamed are sent
+//! with a `Content-MD5` header the signature covers as well, and the server
+//! refuses bodies that don't match the header of a request.
+
+use std::time::{Duration, UNIX_EPOCH};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use hyper::Method;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use storage::{
+    ChangeEvent, ChangeKind, ListStream, ObjectInfo, Permissions, StorageError, Url, WatchStream,
+};
+
+pub const METADATA_SUFFIX: &str = "/metadata";
+pub const LIST_QUERY: &str = "list";
+pub const RECURSIVE_QUERY: &str = "recursive";
+pub const COPY_FROM_QUERY: &str = "copy_from";
+pub const MOVE_FROM_QUERY: &str = "move_from";
+pub const WATCH_QUERY: &str = "watch";
+pub const CONTENT_MD5_HEADER: &str = "content-md5";
+pub const CONTENT_CRC64_HEADER: &str = "x-content-crc64";
+/// The operation of requests for metadata, see [`operation_of`].
+pub const METADATA_OPERATION: &str = "metadata";
+
+/// What the signature of a request with `method` must allow.
+pub fn permissions_for(method: &Method) -> Permissions {
+    match *method {
+        Method::POST => Permissions::WRITE,
+        Method::DELETE => Permissions {
+            delete: true,
+            ..Permissions::default()
+        },
+        _ => Permissions::READ,
+    }
+}
+
+/// The operation the signature of a request with `method` for `url` has to
+/// be for, if any: metadata, or the query flag the server acts on.
+pub fn operation_of(method: &Method, url: &Url) -> Option<&'static str> {
+    if url.path().ends_with(METADATA_SUFFIX) {
+        return Some(METADATA_OPERATION);
+    }
+    let flags: &[&'static str] = match *method {
+        Method::GET => &[LIST_QUERY, WATCH_QUERY],
+        Method::POST => &[COPY_FROM_QUERY, MOVE_FROM_QUERY],
+        Method::DELETE => &[RECURSIVE_QUERY],
+        _ => &[],
+    };
+    flags
+        .iter()
+        .copied()
+        .find(|flag| url.query_pairs().any(|(key, _)| key == *flag))
+}
+
+/// What the signature of a copy or move must allow on its source.
+pub fn source_permissions(query: &str) -> Option<Permissions> {
+    match query {
+        COPY_FROM_QUERY => Some(Permissions::READ),
+        MOVE_FROM_QUERY => Some(Permissions {
+            read: true,
+            delete: true,
+            ..Permissions::default()
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ListEntry {
+    pub name: String,
+    pub size: u64,
+    /// Milliseconds since the unix epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub last_modified: Option<u64>,
+}
+
+impl From<ObjectInfo> for ListEntry {
+    fn from(info: ObjectInfo) -> Self {
+        Self {
+            name: info.name,
+            size: info.size,
+            last_modified: info
+                .last_modified
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .map(|d| d.as_millis() as u64),
+        }
+    }
+}
+
+impl From<ListEntry> for ObjectInfo {
+    fn from(entry: ListEntry) -> Self {
+        Self {
+            name: entry.name,
+            size: entry.size,
+            last_modified: entry
+                .last_modified
+                .map(|ms| UNIX_EPOCH + Duration::from_millis(ms)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WatchKind {
+    Created,
+    Updated,
+    Deleted,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct WatchEntry {
+    pub name: String,
+    pub kind: WatchKind,
+}
+
+impl From<ChangeEvent> for WatchEntry {
+    fn from(event: ChangeEvent) -> Self {
+        let kind = match event.kind {
+            ChangeKind::Created => WatchKind::Created,
+            ChangeKind::Updated => WatchKind::Updated,
+            ChangeKind::Deleted => WatchKind::Deleted,
+        };
+        Self {
+            name: event.name,
+            kind,
+        }
+    }
+}
+
+impl From<WatchEntry> for ChangeEvent {
+    fn from(entry: WatchEntry) -> Self {
+        let kind = match entry.kind {
+            WatchKind::Created => ChangeKind::Created,
+            WatchKind::Updated => ChangeKind::Updated,
+            WatchKind::Deleted => ChangeKind::Deleted,
+        };
+        ChangeEvent::new(entry.name, kind)
+    }
+}
+
+fn invalid_data(error: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> StorageError {
+    StorageError::Other(std::io::Error::new(std::io::ErrorKind::InvalidData, error))
+}
+
+fn encode_line(value: &impl Serialize) -> Result<Bytes, StorageError> {
+    let mut line = serde_json::to_vec(value).map_err(invalid_data)?;
+    line.push(b'\n');
+    Ok(line.into())
+}
+
+/// Encodes one listing page as a single line.
+pub fn encode_page(page: Vec<ObjectInfo>) -> Result<Bytes, StorageError> {
+    let entries: Vec<ListEntry> = page.into_iter().map(ListEntry::from).collect();
+    encode_line(&entries)
+}
+
+/// Encodes one change as a single line.
+pub fn encode_event(event: ChangeEvent) -> Result<Bytes, StorageError> {
+    encode_line(&WatchEntry::from(event))
+}
+
+/// Decodes a body of JSON lines, `what` names the lines in errors.
+fn decode_lines<S, T>(
+    body: S,
+    what: &'static str,
+) -> impl Stream<Item = Result<T, StorageError>> + Send
+where
+    S: Stream<Item = Result<Bytes, StorageError>> + Send + 'static,
+    T: DeserializeOwned + Send,
+{
+    let state = (Box::pin(body), BytesMut::new(), false);
+    futures::stream::unfold(state, move |(mut body, mut buffer, mut done)| async move {
+        loop {
+            if let Some(end) = buffer.iter().position(|b| *b == b'\n') {
+                let line = buffer.split_to(end + 1);
+                let value = serde_json::from_slice::<T>(&line[..end]).map_err(invalid_data);
+                return Some((value, (body, buffer, done)));
+            }
+            if done {
+                if buffer.iter().all(u8::is_ascii_whitespace) {
+                    return None;
+                }
+                let error = invalid_data(format!("truncated {what}"));
+                buffer.clear();
+                return Some((Err(error), (body, buffer, done)));
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => return Some((Err(e), (body, buffer, true))),
+                None => done = true,
+            }
+        }
+    })
+}
+
+/// Decodes a body produced by [`encode_page`] back into listing pages.
+pub fn decode_pages<S>(body: S) -> ListStream
+where
+    S: Stream<Item = Result<Bytes, StorageError>> + Send + 'static,
+{
+    let pages = decode_lines::<_, Vec<ListEntry>>(body, "listing page")
+        .map(|page| page.map(|entries| entries.into_iter().map(ObjectInfo::from).collect()));
+    Box::pin(pages)
+}
+
+/// Decodes a body produced by [`encode_event`] back into changes.
+pub fn decode_events<S>(body: S) -> WatchStream
+where
+    S: Stream<Item = Result<Bytes, StorageError>> + Send + 'static,
+{
+    let events = decode_lines::<_, WatchEntry>(body, "change event")
+        .map(|entry| entry.map(ChangeEvent::from));
+    Box::pin(events)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+
+    #[tokio::test]
+    async fn pages_round_trip_across_chunk_boundaries() {
+        let first = vec![ObjectInfo {
+            name: "level0/segment0.ts".into(),
+            size: 10,
+            last_modified: Some(UNIX_EPOCH + Duration::from_millis(1_000)),
+        }];
+        let second = vec![ObjectInfo {
+            name: "level0/segment1.ts".into(),
+            size: 20,
+            last_modified: None,
+        }];
+        let mut body = encode_page(first.clone()).unwrap().to_vec();
+        body.extend_from_slice(&encode_page(second.clone()).unwrap());
+
+        let chunks: Vec<Result<Bytes, StorageError>> = body
+            .chunks(7)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        let pages: Vec<_> = decode_pages(futures::stream::iter(chunks))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(pages, vec![first, second]);
+    }
+
+    #[tokio::test]
+    async fn events_round_trip() {
+        let events = vec![
+            ChangeEvent::new("level0/segment0.ts", ChangeKind::Created),
+            ChangeEvent::new("manifest.mpd", ChangeKind::Updated),
+            ChangeEvent::new("old.ts", ChangeKind::Deleted),
+        ];
+        let mut body = Vec::new();
+        for event in &events {
+            body.extend_from_slice(&encode_event(event.clone()).unwrap());
+        }
+        assert!(String::from_utf8_lossy(&body).contains("\"kind\":\"created\""));
+
+        let chunk: Result<Bytes, StorageError> = Ok(body.into());
+        let decoded: Vec<_> = decode_events(futures::stream::iter([chunk]))
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(decoded, events);
+    }
+}
@@ -0,0 +1,1021 @@
+use std::{convert::Infallible, future::Future, sync::Arc, time::Duration};
+
+use bytes::Bytes;
+use futures::{Stream, StreamExt, TryStreamExt};
+use hyper::{
+    header::{
+        HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_MATCH,
+        IF_NONE_MATCH, LAST_MODIFIED, RANGE,
+    },
+    server::{
+        accept::{self, Accept},
+        conn::AddrIncoming,
+    },
+    service::{make_service_fn, service_fn},
+    Body, HeaderMap, Method, Request, Response, Server, StatusCode,
+};
+use log::{error, info};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use storage::{
+    verify_stream, BoxedStorageContainer, Checksums, ObjectProperties, StorageError,
+    StorageRegistry, StreamType, Url, UrlSigner, WriteCondition,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, TcpStream},
+};
+use tokio_native_tls::{native_tls, TlsAcceptor, TlsStream};
+
+use crate::protocol::{
+    self, CONTENT_CRC64_HEADER, CONTENT_MD5_HEADER, COPY_FROM_QUERY, LIST_QUERY, METADATA_SUFFIX,
+    MOVE_FROM_QUERY, RECURSIVE_QUERY, WATCH_QUERY,
+};
+
+/// Opens the container of the video of an account.
+pub type VideoOpener =
+    Arc<dyn Fn(&str, &str) -> Result<BoxedStorageContainer, StorageError> + Send + Sync>;
+
+/// Serves storage containers over the protocol of [`crate::protocol`], for
+/// [`crate::StorageClient`] to talk to.
+#[derive(Clone)]
+pub struct ProxyServer {
+    open: VideoOpener,
+    signer: Option<UrlSigner>,
+    tls: Option<TlsAcceptor>,
+}
+
+/// How many TLS handshakes run at once, and how long each may take.
+const TLS_HANDSHAKES: usize = 64;
+const TLS_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What is escaped in account and video names before they go into a storage
+/// URL template: everything that could end or restructure a URL component.
+const TEMPLATE_ESCAPES: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'/')
+    .add(b':')
+    .add(b'<')
+    .add(b'>')
+    .add(b'?')
+    .add(b'@')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}');
+
+/// Whether a path segment is one URLs resolve, `..` or `.` and their
+/// percent-encoded forms.
+fn is_dot_segment(segment: &str) -> bool {
+    matches!(
+        segment.to_ascii_lowercase().as_str(),
+        "." | ".." | "%2e" | "%2e%2e" | ".%2e" | "%2e."
+    )
+}
+
+/// What a request addresses: `/{account}/{video}/{path}[/metadata]`.
+struct Target {
+    account: String,
+    video: String,
+    path: String,
+    metadata: bool,
+}
+
+impl Target {
+    fn parse(path: &str) -> Option<Self> {
+        let decode = |s: &str| percent_decode_str(s).decode_utf8().ok().map(String::from);
+        let mut segments = path.strip_prefix('/')?.splitn(3, '/');
+        let account = decode(segments.next()?)?;
+        let video = decode(segments.next()?)?;
+        // the names end up in storage URLs, keep them to a single segment.
+        let valid =
+            |name: &str| !name.is_empty() && !name.contains(['/', '\\']) && !is_dot_segment(name);
+        if !valid(&account) || !valid(&video) {
+            return None;
+        }
+        let path = segments.next().unwrap_or_default();
+        let (path, metadata) = match path.strip_suffix(METADATA_SUFFIX) {
+            Some(object) if !object.is_empty() => (object, true),
+            _ => (path, false),
+        };
+        // nor may the path leave the directories a signature covers.
+        let path = decode(path)?;
+        if path.split('/').any(is_dot_segment) {
+            return None;
+        }
+        Some(Self {
+            account,
+            video,
+            path,
+            metadata,
+        })
+    }
+}
+
+/// Parses a single `bytes=start-[end]` range into an offset and a length.
+/// Other ranges are ignored, which serves the whole object. A range that ends
+/// past what a length can hold reads to the end.
+fn parse_range(value: &HeaderValue) -> Option<(u64, Option<u64>)> {
+    let (start, end) = value
+        .to_str()
+        .ok()?
+        .strip_prefix("bytes=")?
+        .split_once('-')?;
+    let start: u64 = start.trim().parse().ok()?;
+    match end.trim() {
+        "" => Some((start, None)),
+        end => {
+            let end: u64 = end.parse().ok()?;
+            (end >= start).then_some((start, (end - start).checked_add(1)))
+        }
+    }
+}
+
+fn condition(headers: &HeaderMap) -> Option<WriteCondition> {
+    if let Some(etag) = headers.get(IF_MATCH).and_then(|v| v.to_str().ok()) {
+        return Some(WriteCondition::IfMatch(
+            etag.trim().trim_matches('"').to_owned(),
+        ));
+    }
+    headers
+        .get(IF_NONE_MATCH)
+        .filter(|v| v.as_bytes() == b"*")
+        .map(|_| WriteCondition::IfNoneMatch)
+}
+
+fn status_of(error: &StorageError) -> StatusCode {
+    match error {
+        StorageError::NotFound => StatusCode::NOT_FOUND,
+        StorageError::AuthenticationError => StatusCode::FORBIDDEN,
+        StorageError::Conflict => StatusCode::CONFLICT,
+        StorageError::PreconditionFailed => StatusCode::PRECONDITION_FAILED,
+        StorageError::Unsupported => StatusCode::NOT_IMPLEMENTED,
+        StorageError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+        StorageError::Throttled(_) => StatusCode::TOO_MANY_REQUESTS,
+        StorageError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+        StorageError::HttpError(_) => StatusCode::BAD_GATEWAY,
+        StorageError::Other(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+            StatusCode::BAD_REQUEST
+        }
+        StorageError::IntegrityMismatch | StorageError::Other(_) => {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+fn invalid_input(error: native_tls::Error) -> StorageError {
+    StorageError::Other(std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+}
+
+fn response(status: StatusCode, body: impl Into<Body>) -> Response<Body> {
+    let mut response = Response::new(body.into());
+    *response.status_mut() = status;
+    response
+}
+
+fn streaming(
+    stream: impl futures::Stream<Item = Result<Bytes, StorageError>> + Send + 'static,
+) -> Response<Body> {
+    Response::new(Body::wrap_stream(stream))
+}
+
+/// Sets a header, skipping values that can't be sent.
+fn set_header(headers: &mut HeaderMap, name: impl Into<HeaderName>, value: &str) {
+    if let Ok(value) = HeaderValue::from_str(value) {
+        headers.insert(name.into(), value);
+    }
+}
+
+fn set_properties(headers: &mut HeaderMap, properties: &ObjectProperties) {
+    if let Some(etag) = &properties.etag {
+        set_header(headers, ETAG, &format!("\"{etag}\""));
+    }
+    if let Some(content_type) = &properties.content_type {
+        set_header(headers, CONTENT_TYPE, content_type);
+    }
+    if let Some(last_modified) = properties.last_modified {
+        set_header(
+            headers,
+            LAST_MODIFIED,
+            &httpdate::fmt_http_date(last_modified),
+        );
+    }
+}
+
+/// The checksums cover the whole content, so only full reads carry them.
+fn set_checksums(headers: &mut HeaderMap, properties: &ObjectProperties) {
+    if let Some(md5) = properties.checksums.md5_base64() {
+        set_header(headers, HeaderName::from_static(CONTENT_MD5_HEADER), &md5);
+    }
+    if let Some(crc64) = properties.checksums.crc64_base64() {
+        set_header(
+            headers,
+            HeaderName::from_static(CONTENT_CRC64_HEADER),
+            &crc64,
+        );
+    }
+}
+
+fn request_body(body: Body) -> StreamType {
+    let body = body.map_err(|e| StorageError::Other(std::io::Error::other(e)));
+    Box::pin(body)
+}
+
+fn content_md5(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(CONTENT_MD5_HEADER)
+        .and_then(|v| v.to_str().ok())
+}
+
+fn body_mismatch() -> StorageError {
+    StorageError::Other(std::io::Error::new(
+        std::io::ErrorKind::InvalidInput,
+        "the body does not match its Content-MD5",
+    ))
+}
+
+/// The checksums the `Content-MD5` header of a request promises for its body,
+/// which signatures for a body digest rely on the server to check.
+fn expected_checksums(headers: &HeaderMap) -> Result<Option<Checksums>, StorageError> {
+    let Some(md5) = content_md5(headers) else {
+        return Ok(None);
+    };
+    let checksums = Checksums::from_base64(Some(md5), None);
+    match checksums.md5 {
+        Some(_) => Ok(Some(checksums)),
+        None => Err(body_mismatch()),
+    }
+}
+
+/// The properties of `path`, if the backend reports them.
+async fn properties(
+    storage: &BoxedStorageContainer,
+    path: &str,
+) -> Result<Option<ObjectProperties>, StorageError> {
+    match storage.stat(path).await {
+        Ok(properties) => Ok(Some(properties)),
+        Err(StorageError::Unsupported) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl ProxyServer {
+    /// Serves the containers `open` returns for an account and a video.
+    pub fn new<F>(open: F) -> Self
+    where
+        F: Fn(&str, &str) -> Result<BoxedStorageContainer, StorageError> + Send + Sync + 'static,
+    {
+        Self {
+            open: Arc::new(open),
+            signer: None,
+            tls: None,
+        }
+    }
+
+    /// Only answers requests signed with the key of `signer`, as
+    /// [`crate::StorageClient`]s with the same key and presigned URLs do, see
+    /// [`crate::protocol`].
+    pub fn with_signer(mut self, signer: UrlSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// Serves HTTPS with a PEM certificate chain and its PKCS #8 PEM key.
+    pub fn with_tls(mut self, certificate: &[u8], key: &[u8]) -> Result<Self, StorageError> {
+        let identity = native_tls::Identity::from_pkcs8(certificate, key).map_err(invalid_input)?;
+        let acceptor = native_tls::TlsAcceptor::new(identity).map_err(invalid_input)?;
+        self.tls = Some(acceptor.into());
+        Ok(self)
+    }
+
+    /// Serves the containers of `registry` a URL template points to. The
+    /// template may contain `{account}` and `{video}` placeholders, e.g.
+    /// `file:///data/{account}/{video}`. The names are percent-encoded before
+    /// they are substituted.
+    pub fn from_template(registry: StorageRegistry, template: &str) -> Self {
+        let template = template.to_owned();
+        Self::new(move |account, video| {
+            let escape = |name| utf8_percent_encode(name, TEMPLATE_ESCAPES).to_string();
+            let url = template
+                .replace("{account}", &escape(account))
+                .replace("{video}", &escape(video));
+            registry.open_str(&url)
+        })
+    }
+
+    /// Serves connections from `listener` until `shutdown` completes, then
+    /// lets the open requests finish.
+    pub async fn serve(
+        self,
+        listener: TcpListener,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), hyper::Error> {
+        match self.tls.clone() {
+            Some(tls) => {
+                let connections = accept::from_stream(accept_tls(listener, tls));
+                self.serve_connections(connections, shutdown).await
+            }
+            None => {
+                let incoming = AddrIncoming::from_listener(listener)?;
+                self.serve_connections(incoming, shutdown).await
+            }
+        }
+    }
+
+    async fn serve_connections<I>(
+        self,
+        incoming: I,
+        shutdown: impl Future<Output = ()>,
+    ) -> Result<(), hyper::Error>
+    where
+        I: Accept,
+        I::Conn: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+        I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    {
+        let make_service = make_service_fn(move |_: &I::Conn| {
+            let server = self.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| {
+                    let server = server.clone();
+                    async move { Ok::<_, Infallible>(server.handle(request).await) }
+                }))
+            }
+        });
+        Server::builder(incoming)
+            .serve(make_service)
+            .with_graceful_shutdown(shutdown)
+            .await
+    }
+
+    /// Answers a single request.
+    pub async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let method = request.method().clone();
+        let uri = request.uri().clone();
+        match self.dispatch(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                let status = status_of(&e);
+                if status.is_server_error() {
+                    error!("{} {} failed: {:?}", method, uri, e);
+                } else {
+                    info!("{} {} failed: {:?}", method, uri, e);
+                }
+                response(status, e.to_string())
+            }
+        }
+    }
+
+    /// Checks the signature of a request when the server has a signer.
+    fn authorize(&self, request: &Request<Body>) -> Result<(), StorageError> {
+        let Some(signer) = &self.signer else {
+            return Ok(());
+        };
+        let uri = request.uri();
+        let path_and_query = uri.path_and_query().map_or("/", |p| p.as_str());
+        let url = Url::parse(&format!("http://proxy{path_and_query}"))
+            .map_err(|_| StorageError::AuthenticationError)?;
+        // the signature is for the path as URLs normalize it, which has to be
+        // the path the request is served from.
+        if url.path() != uri.path() {
+            return Err(StorageError::AuthenticationError);
+        }
+        let operation = protocol::operation_of(request.method(), &url);
+        signer.verify_digest(
+            &url,
+            protocol::permissions_for(request.method()),
+            operation,
+            content_md5(request.headers()),
+        )?;
+
+        let video: Vec<_> = url.path().splitn(4, '/').take(3).collect();
+        for (key, from) in url.query_pairs() {
+            let Some(permissions) = protocol::source_permissions(&key) else {
+                continue;
+            };
+            if from.split('/').any(is_dot_segment) {
+                return Err(StorageError::AuthenticationError);
+            }
+            let mut source = url.clone();
+            source.set_path(&format!("{}/{}", video.join("/"), from));
+            signer.verify_operation(&source, permissions, operation)?;
+        }
+        Ok(())
+    }
+
+    async fn dispatch(&self, request: Request<Body>) -> Result<Response<Body>, StorageError> {
+        let Some(target) = Target::parse(request.uri().path()) else {
+            return Ok(response(
+                StatusCode::BAD_REQUEST,
+                "expected /account/video/path",
+            ));
+        };
+        self.authorize(&request)?;
+        let query: Vec<(String, String)> =
+            url::form_urlencoded::parse(request.uri().query().unwrap_or_default().as_bytes())
+                .into_owned()
+                .collect();
+        let flag = |name: &str| query.iter().any(|(key, _)| key == name);
+        let value = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let storage = (self.open)(&target.account, &target.video)?;
+        let path = target.path.as_str();
+        match (request.method().clone(), target.metadata) {
+            (Method::GET, true) => {
+                let metadata = storage.get_metadata(path).await?;
+                Ok(Response::new(metadata.into()))
+            }
+            (Method::POST, true) => {
+                let condition = condition(request.headers());
+                let expected = expected_checksums(request.headers())?;
+                let body = hyper::body::to_bytes(request.into_body())
+                    .await
+                    .map_err(|e| StorageError::Other(std::io::Error::other(e)))?;
+                if expected.is_some_and(|expected| !Checksums::of(&body).matches(&expected)) {
+                    return Err(body_mismatch());
+                }
+                let metadata = String::from_utf8(body.to_vec()).map_err(|e| {
+                    StorageError::Other(std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+                })?;
+                match condition {
+                    Some(condition) => storage.set_metadata_if(path, metadata, condition).await?,
+                    None => storage.set_metadata(path, metadata).await?,
+                }
+                Ok(response(StatusCode::OK, Body::empty()))
+            }
+            (Method::GET, false) if flag(LIST_QUERY) => {
+                let pages = storage.list(path).await?;
+                Ok(streaming(pages.and_then(|page| async move {
+                    protocol::encode_page(page)
+                })))
+            }
+            (Method::GET, false) if flag(WATCH_QUERY) => {
+                let changes = storage.watch(path).await?;
+                Ok(streaming(changes.and_then(|change| async move {
+                    protocol::encode_event(change)
+                })))
+            }
+            (Method::GET, false) => self.get(storage, path, request.headers()).await,
+            (Method::HEAD, false) => match properties(&storage, path).await? {
+                Some(properties) => {
+                    let mut response = response(StatusCode::OK, Body::empty());
+                    let headers = response.headers_mut();
+                    set_properties(headers, &properties);
+                    set_checksums(headers, &properties);
+                    set_header(
+                        headers,
+                        CONTENT_LENGTH,
+                        &properties.content_length.to_string(),
+                    );
+                    Ok(response)
+                }
+                None if storage.exists(path).await? => Ok(response(StatusCode::OK, Body::empty())),
+                None => Err(StorageError::NotFound),
+            },
+            (Method::POST, false) => {
+                if let Some(from) = value(COPY_FROM_QUERY) {
+                    storage.copy(&from, path).await?;
+                } else if let Some(from) = value(MOVE_FROM_QUERY) {
+                    storage.rename(&from, path).await?;
+                } else {
+                    let condition = condition(request.headers());
+                    let expected = expected_checksums(request.headers())?;
+                    let mut content = request_body(request.into_body());
+                    if let Some(expected) = expected {
+                        content = Box::pin(verify_stream(content, expected).map_err(|e| match e {
+                            StorageError::IntegrityMismatch => body_mismatch(),
+                            e => e,
+                        }));
+                    }
+                    match condition {
+                        Some(condition) => storage.set_content_if(path, content, condition).await?,
+                        None => storage.set_content(path, content).await?,
+                    }
+                }
+                Ok(response(StatusCode::OK, Body::empty()))
+            }
+            (Method::DELETE, false) if flag(RECURSIVE_QUERY) => {
+                let deleted = storage.delete_prefix(path).await?;
+                Ok(response(StatusCode::OK, deleted.to_string()))
+            }
+            (Method::DELETE, false) => {
+                storage.delete(path).await?;
+                Ok(response(StatusCode::OK, Body::empty()))
+            }
+            _ => Ok(response(StatusCode::METHOD_NOT_ALLOWED, Body::empty())),
+        }
+    }
+
+    async fn get(
+        &self,
+        storage: BoxedStorageContainer,
+        path: &str,
+        headers: &HeaderMap,
+    ) -> Result<Response<Body>, StorageError> {
+        let properties = properties(&storage, path).await?;
+        let Some((offset, length)) = headers.get(RANGE).and_then(parse_range) else {
+            let content = storage.get_content(path).await?;
+            let mut response = streaming(content);
+            if let Some(properties) = &properties {
+                set_properties(response.headers_mut(), properties);
+                set_checksums(response.headers_mut(), properties);
+            }
+            return Ok(response);
+        };
+
+        let size = properties.as_ref().map(|p| p.content_length);
+        let length = match (length, size) {
+            (Some(length), Some(size)) => Some(length.min(size.saturating_sub(offset))),
+            (length, _) => length,
+        };
+        let end = match (length, size) {
+            (Some(length), _) => length.checked_sub(1).and_then(|l| offset.checked_add(l)),
+            (None, Some(size)) => size.checked_sub(1),
+            (None, None) => None,
+        };
+        let satisfiable = match size {
+            Some(size) => offset < size,
+            None => length.is_none() || end.is_some(),
+        };
+        if !satisfiable {
+            let mut response = response(StatusCode::RANGE_NOT_SATISFIABLE, Body::empty());
+            set_header(
+                response.headers_mut(),
+                CONTENT_RANGE,
+                &format!("bytes */{}", size.unwrap_or_default()),
+            );
+            return Ok(response);
+        }
+        let content = storage.get_content_range(path, offset, length).await?;
+        let mut response = streaming(content);
+        *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+        if let Some(end) = end {
+            let size = size.map_or("*".to_owned(), |size| size.to_string());
+            set_header(
+                response.headers_mut(),
+                CONTENT_RANGE,
+                &format!("bytes {offset}-{end}/{size}"),
+            );
+        }
+        if let Some(properties) = &properties {
+            set_properties(response.headers_mut(), properties);
+        }
+        Ok(response)
+    }
+}
+
+/// The TLS connections of `listener`, skipping the ones whose handshake
+/// fails.
+fn accept_tls(
+    listener: TcpListener,
+    tls: TlsAcceptor,
+) -> impl Stream<Item = Result<TlsStream<TcpStream>, std::io::Error>> {
+    futures::stream::unfold(listener, |listener| async move {
+        let accepted = listener.accept().await;
+        Some((accepted, listener))
+    })
+    .filter_map(|accepted| async move {
+        match accepted {
+            Ok((stream, _)) => Some(stream),
+            Err(e) => {
+                // as hyper does, back off when running out of file descriptors.
+                error!("Accepting a connection failed: {:?}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                None
+            }
+        }
+    })
+    .map(move |stream| {
+        let tls = tls.clone();
+        async move { tokio::time::timeout(TLS_HANDSHAKE_TIMEOUT, tls.accept(stream)).await }
+    })
+    .buffer_unordered(TLS_HANDSHAKES)
+    .filter_map(|handshake| async move {
+        match handshake {
+            Ok(Ok(stream)) => Some(Ok(stream)),
+            Ok(Err(e)) => {
+                info!("TLS handshake failed: {:?}", e);
+                None
+            }
+            Err(_) => {
+                info!("TLS handshake timed out");
+                None
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{protocol::METADATA_OPERATION, StorageClient, StorageConfig};
+    use storage::{ChangeKind, MemoryStorageContainer, Permissions, StorageContainer};
+
+    fn serving(storage: MemoryStorageContainer) -> ProxyServer {
+        ProxyServer::new(move |_, _| Ok(Box::new(storage.clone())))
+    }
+
+    /// Serves `server` on a local port and returns the port.
+    async fn serve(server: ProxyServer) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(server.serve(listener, std::future::pending()));
+        port
+    }
+
+    fn local() -> StorageConfig {
+        StorageConfig {
+            node_address: "127.0.0.1".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn client(port: u16, config: StorageConfig) -> StorageClient {
+        let config = StorageConfig {
+            storage_port: port.into(),
+            ..config
+        };
+        StorageClient::new(config, "account", "video").unwrap()
+    }
+
+    async fn start(storage: MemoryStorageContainer) -> StorageClient {
+        client(serve(serving(storage)).await, local())
+    }
+
+    fn body(content: &'static str) -> StreamType {
+        Box::pin(futures::stream::iter([Ok(Bytes::from(content))]))
+    }
+
+    async fn read_all(stream: StreamType) -> Vec<u8> {
+        let chunks: Vec<Bytes> = stream.try_collect().await.unwrap();
+        chunks.concat()
+    }
+
+    #[test]
+    fn parses_targets() {
+        let target = Target::parse("/account/video/level0/segment%201.ts/metadata").unwrap();
+        assert_eq!(target.account, "account");
+        assert_eq!(target.video, "video");
+        assert_eq!(target.path, "level0/segment 1.ts");
+        assert!(target.metadata);
+        assert_eq!(Target::parse("/account/video/").unwrap().path, "");
+        assert!(Target::parse("/account").is_none());
+        assert!(Target::parse("/../video/a.ts").is_none());
+        assert!(Target::parse("/%2E%2E%2F%2E%2E%2Fetc/x/a.ts").is_none());
+        assert!(Target::parse("/account/..%5C..%5Cetc/a.ts").is_none());
+        assert!(Target::parse("/%252e%252e/video/a.ts").is_none());
+        assert!(Target::parse("/account/video/level0/../a.ts").is_none());
+        assert!(Target::parse("/account/video/level0/%2e%2e/a.ts").is_none());
+
+        let range = |value: &'static str| parse_range(&HeaderValue::from_static(value));
+        assert_eq!(range("bytes=10-14"), Some((10, Some(5))));
+        assert_eq!(range("bytes=10-"), Some((10, None)));
+        assert_eq!(range("bytes=-10"), None);
+        assert_eq!(range("bytes=0-18446744073709551615"), Some((0, None)));
+        assert_eq!(
+            range("bytes=1-18446744073709551615"),
+            Some((1, Some(u64::MAX)))
+        );
+    }
+
+    #[tokio::test]
+    async fn escapes_names_in_templates() {
+        let root = tempfile::tempdir().unwrap();
+        let template = format!("file://{}/{{account}}/{{video}}", root.path().display());
+        let server = ProxyServer::from_template(StorageRegistry::new(), &template);
+        let storage = (server.open)("an account", "%2e%2e").unwrap();
+        storage.set_content("a.ts", body("a")).await.unwrap();
+        assert!(root.path().join("an account/%2e%2e/a.ts").exists());
+    }
+
+    #[tokio::test]
+    async fn clamps_ranges_past_the_end() {
+        let memory = MemoryStorageContainer::new();
+        memory
+            .set_content("a.ts", body("hello world"))
+            .await
+            .unwrap();
+        let port = serve(serving(memory)).await;
+        let url = format!("http://127.0.0.1:{port}/account/video/a.ts");
+        let http = reqwest::Client::new();
+        let get = |range: String| http.get(&url).header(RANGE, range).send();
+
+        for start in [0, 6] {
+            let response = get(format!("bytes={start}-{}", u64::MAX)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(
+                response.headers()[CONTENT_RANGE],
+                format!("bytes {start}-10/11").as_str()
+            );
+            assert_eq!(response.text().await.unwrap(), &"hello world"[start..]);
+        }
+        let response = get(format!("bytes={}-", u64::MAX)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[tokio::test]
+    async fn serves_content_and_metadata() {
+        let memory = MemoryStorageContainer::new();
+        let client = start(memory.clone()).await;
+
+        client
+            .set_content("level0/a.ts", body("hello world"))
+            .await
+            .unwrap();
+        client
+            .set_metadata("level0/a.ts", "{}".to_owned())
+            .await
+            .unwrap();
+        assert_eq!(
+            read_all(memory.get_content("level0/a.ts").await.unwrap()).await,
+            b"hello world"
+        );
+        assert_eq!(
+            read_all(client.get_content("level0/a.ts").await.unwrap()).await,
+            b"hello world"
+        );
+        assert_eq!(
+            read_all(
+                client
+                    .get_content_range("level0/a.ts", 6, Some(5))
+                    .await
+                    .unwrap()
+            )
+            .await,
+            b"world"
+        );
+        assert_eq!(
+            read_all(
+                client
+                    .get_content_range("level0/a.ts", 20, None)
+                    .await
+                    .unwrap()
+            )
+            .await,
+            b""
+        );
+        assert_eq!(client.get_metadata("level0/a.ts").await.unwrap(), "{}");
+
+        let properties = client.stat("level0/a.ts").await.unwrap();
+        assert_eq!(properties.content_length, 11);
+        assert_eq!(properties.metadata.as_deref(), Some("{}"));
+        assert_eq!(properties.checksums, Checksums::of(b"hello world"));
+        assert_eq!(
+            properties.etag,
+            memory.stat("level0/a.ts").await.unwrap().etag
+        );
+        assert!(matches!(
+            client.stat("missing.ts").await,
+            Err(StorageError::NotFound)
+        ));
+
+        let stale = WriteCondition::IfMatch("stale".to_owned());
+        assert!(matches!(
+            client.set_content_if("level0/a.ts", body("x"), stale).await,
+            Err(StorageError::PreconditionFailed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn conforms() {
+        let client = start(MemoryStorageContainer::new()).await;
+        storage::conformance::run(&client, "").await;
+    }
+
+    #[tokio::test]
+    async fn lists_copies_and_deletes() {
+        let memory = MemoryStorageContainer::new();
+        let client = start(memory.clone()).await;
+        memory.set_content("level0/a.ts", body("a")).await.unwrap();
+        memory.set_content("level1/b.ts", body("b")).await.unwrap();
+
+        client.copy("level0/a.ts", "level0/c.ts").await.unwrap();
+        client.rename("level1/b.ts", "level0/b.ts").await.unwrap();
+        let names: Vec<_> = client
+            .list_all("level0/")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| o.name)
+            .collect();
+        assert_eq!(names, ["level0/a.ts", "level0/b.ts", "level0/c.ts"]);
+
+        client.delete("level0/a.ts").await.unwrap();
+        assert!(matches!(
+            client.delete("level0/a.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert_eq!(client.delete_prefix("level0/").await.unwrap(), 2);
+        assert!(memory.list_all("").await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn streams_changes() {
+        let memory = MemoryStorageContainer::new();
+        let client = start(memory.clone()).await;
+        let mut changes = client.watch("level0/").await.unwrap();
+        memory.set_content("level1/a.ts", body("a")).await.unwrap();
+        memory.set_content("level0/a.ts", body("a")).await.unwrap();
+        let change = changes.next().await.unwrap().unwrap();
+        assert_eq!(change.name, "level0/a.ts");
+        assert_eq!(change.kind, ChangeKind::Created);
+    }
+
+    #[tokio::test]
+    async fn checks_signatures() {
+        let memory = MemoryStorageContainer::new();
+        memory
+            .set_content("secret.ts", body("secret"))
+            .await
+            .unwrap();
+        let key = UrlSigner::new("key");
+        let port = serve(serving(memory.clone()).with_signer(key.clone())).await;
+
+        let signed = client(
+            port,
+            StorageConfig {
+                signer: Some(key.clone()),
+                ..local()
+            },
+        );
+        signed.set_content("level0/a.ts", body("a")).await.unwrap();
+        signed
+            .set_metadata("level0/a.ts", "meta".into())
+            .await
+            .unwrap();
+        signed.copy("level0/a.ts", "level1/a.ts").await.unwrap();
+        signed.rename("level1/a.ts", "level1/b.ts").await.unwrap();
+        assert_eq!(
+            read_all(signed.get_content("level1/b.ts").await.unwrap()).await,
+            b"a"
+        );
+        assert!(signed.exists("level0/a.ts").await.unwrap());
+        assert_eq!(signed.list_all("").await.unwrap().len(), 3);
+        assert_eq!(signed.delete_prefix("level1/").await.unwrap(), 1);
+
+        let other = StorageConfig {
+            signer: Some(UrlSigner::new("other")),
+            ..local()
+        };
+        for config in [local(), other] {
+            assert!(matches!(
+                client(port, config).get_content("level0/a.ts").await,
+                Err(StorageError::AuthenticationError)
+            ));
+        }
+
+        let url = |path: &str| {
+            Url::parse(&format!("http://127.0.0.1:{port}/account/video/{path}")).unwrap()
+        };
+        let expiry = Duration::from_secs(60);
+        let http = reqwest::Client::new();
+        let presigned = key.sign(url("level0/a.ts"), Permissions::READ, expiry);
+        let response = http.get(presigned.clone()).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.text().await.unwrap(), "a");
+        let response = http.post(presigned).body("b").send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // the signature for the target does not cover reading the source.
+        let mut copy = url("level0/c.ts");
+        copy.query_pairs_mut()
+            .append_pair(COPY_FROM_QUERY, "secret.ts");
+        let copy = key.sign_operation(copy, Permissions::READ_WRITE, COPY_FROM_QUERY, expiry);
+        let response = http.post(copy).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(!memory.exists("level0/c.ts").await.unwrap());
+
+        // signatures name the operation they allow.
+        let delete = Permissions {
+            delete: true,
+            ..Permissions::default()
+        };
+        let mut recursive = key.sign(url("level0/"), delete, expiry);
+        recursive.query_pairs_mut().append_key_only(RECURSIVE_QUERY);
+        let response = http.delete(recursive).send().await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert!(memory.exists("level0/a.ts").await.unwrap());
+
+        let read = key.sign(url(""), Permissions::READ, expiry);
+        let mut list = read.clone();
+        list.query_pairs_mut().append_key_only(LIST_QUERY);
+        let mut metadata = read.clone();
+        metadata.set_path("/account/video/level0/a.ts/metadata");
+        for url in [list, metadata] {
+            let response = http.get(url).send().await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+        let response = http.get(read).send().await.unwrap();
+        assert_ne!(response.status(), StatusCode::FORBIDDEN);
+
+        // signatures for a body digest can't be replayed with another body.
+        let md5 = Checksums::of(b"meta").md5_base64().unwrap();
+        let metadata = key.sign_digest(
+            url("level0/a.ts/metadata"),
+            Permissions::WRITE,
+            Some(METADATA_OPERATION),
+            &md5,
+            expiry,
+        );
+        for (content_md5, body) in [(md5.as_str(), "other"), ("", "other")] {
+            let response = http
+                .post(metadata.clone())
+                .header(CONTENT_MD5_HEADER, content_md5)
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+            assert!(response.status().is_client_error());
+        }
+        assert_eq!(memory.get_metadata("level0/a.ts").await.unwrap(), "meta");
+    }
+
+    #[tokio::test]
+    async fn checks_bodies_against_their_digest() {
+        let memory = MemoryStorageContainer::new();
+        let port = serve(serving(memory.clone())).await;
+        let http = reqwest::Client::new();
+        let url = format!("http://127.0.0.1:{port}/account/video/a.ts");
+        let md5 = Checksums::of(b"abc").md5_base64().unwrap();
+        for body in ["abd", "abc"] {
+            let response = http
+                .post(&url)
+                .header(CONTENT_MD5_HEADER, &md5)
+                .body(body)
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status().is_success(), body == "abc");
+        }
+        assert_eq!(
+            read_all(memory.get_content("a.ts").await.unwrap()).await,
+            b"abc"
+        );
+    }
+
+    #[tokio::test]
+    async fn serves_https() {
+        let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_owned()]).unwrap();
+        let pem = certificate.serialize_pem().unwrap();
+        let key = certificate.serialize_private_key_pem();
+        let server = serving(MemoryStorageContainer::new())
+            .with_tls(pem.as_bytes(), key.as_bytes())
+            .unwrap();
+        let port = serve(server).await;
+
+        let config = StorageConfig {
+            node_address: "localhost".to_owned(),
+            https: true,
+            ca_certificate: Some(pem.into_bytes()),
+            ..Default::default()
+        };
+        let secure = client(port, config.clone());
+        secure.set_content("a.ts", body("a")).await.unwrap();
+        assert_eq!(
+            read_all(secure.get_content("a.ts").await.unwrap()).await,
+            b"a"
+        );
+
+        let untrusted = StorageConfig {
+            ca_certificate: None,
+            ..config.clone()
+        };
+        let plain = StorageConfig {
+            https: false,
+            ..config
+        };
+        for config in [untrusted, plain] {
+            assert!(client(port, config).get_content("a.ts").await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn speaks_http2() {
+        let memory = MemoryStorageContainer::new();
+        let port = serve(serving(memory.clone())).await;
+        let config = StorageConfig {
+            http2_prior_knowledge: true,
+            ..local()
+        };
+        let client = client(port, config);
+        client.set_content("a.ts", body("a")).await.unwrap();
+        assert_eq!(
+            read_all(client.get_content("a.ts").await.unwrap()).await,
+            b"a"
+        );
+        assert_eq!(
+            read_all(memory.get_content("a.ts").await.unwrap()).await,
+            b"a"
+        );
+    }
+}
@@ -1,11 +1,89 @@
+use std::time::Duration;
+
 use async_trait::async_trait;
 use futures::StreamExt;
-use reqwest::{Body, Client};
-use storage::{StorageContainer, StorageError, StreamType};
+use reqwest::{
+    header::{
+        HeaderMap, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, ETAG, IF_MATCH, IF_NONE_MATCH,
+        LAST_MODIFIED, RANGE,
+    },
+    Body, Certificate, Client, RequestBuilder, Response, StatusCode,
+};
+use storage::{
+    enforce_throughput, invalid_url, slice_stream, Checksums, ListStream, MinThroughput,
+    ObjectProperties, Permissions, StorageContainer, StorageError, StreamType, Url, UrlSigner,
+    WatchStream, WriteCondition, SIGNATURE_QUERY,
+};
+
+use crate::protocol::{
+    self, CONTENT_CRC64_HEADER, CONTENT_MD5_HEADER, COPY_FROM_QUERY, LIST_QUERY, METADATA_SUFFIX,
+    MOVE_FROM_QUERY, RECURSIVE_QUERY, WATCH_QUERY,
+};
 
+#[derive(Clone)]
 pub struct StorageConfig {
     pub storage_port: u32,
     pub node_address: String,
+    /// Talks HTTPS to the proxy rather than HTTP.
+    pub https: bool,
+    /// A PEM certificate to trust on top of the system ones, for proxies
+    /// with certificates of a private CA.
+    pub ca_certificate: Option<Vec<u8>>,
+    /// Signs every request, for proxies that check signatures, see
+    /// [`crate::ProxyServer::with_signer`].
+    pub signer: Option<UrlSigner>,
+    /// How long request signatures stay valid, 30 seconds by default. It has
+    /// to cover how far the clocks of the client and the proxy disagree, and
+    /// bounds how long a captured signature can be replayed. Bodies that are
+    /// not streamed are signed with their digest, which can't be replayed
+    /// with another body at all.
+    pub signature_expiry: Duration,
+    /// The time to open a connection to the proxy.
+    pub connect_timeout: Option<Duration>,
+    /// The time to wait for the proxy to answer a request, not counting
+    /// reading the body of the answer. Uploads of content are not bounded,
+    /// as their body may be produced while it is sent.
+    pub request_timeout: Option<Duration>,
+    /// How long unused connections stay open for later requests.
+    pub idle_timeout: Option<Duration>,
+    /// The most unused connections kept open to the proxy, 16 by default.
+    /// Connections beyond it are closed once their request completes, so a
+    /// burst of requests does not leave as many sockets open behind it.
+    pub pool_size: usize,
+    /// How often open connections are probed, with TCP keep-alive and, over
+    /// HTTP/2, pings.
+    pub keep_alive: Option<Duration>,
+    /// Talks HTTP/2 from the first request instead of HTTP/1.1, which
+    /// [`crate::ProxyServer`] understands as well.
+    pub http2_prior_knowledge: bool,
+    pub user_agent: String,
+    /// The slowest content may stream before reading it fails with
+    /// [`StorageError::Timeout`], which catches stuck `get_content` streams.
+    pub min_throughput: Option<MinThroughput>,
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        Self {
+            storage_port: 80,
+            node_address: String::new(),
+            https: false,
+            ca_certificate: None,
+            signer: None,
+            signature_expiry: Duration::from_secs(30),
+            connect_timeout: Some(Duration::from_secs(10)),
+            request_timeout: Some(Duration::from_secs(60)),
+            idle_timeout: Some(Duration::from_secs(90)),
+            pool_size: 16,
+            keep_alive: Some(Duration::from_secs(60)),
+            http2_prior_knowledge: false,
+            user_agent: concat!(env!("CARGO_PKG_NAME"), "/", env!("CARGO_PKG_VERSION")).to_owned(),
+            min_throughput: Some(MinThroughput {
+                bytes: 1,
+                window: Duration::from_secs(30),
+            }),
+        }
+    }
 }
 
 pub struct StorageClient {
@@ -15,24 +93,252 @@ pub struct StorageClient {
     video: String,
 }
 
+fn invalid_config(error: reqwest::Error) -> StorageError {
+    StorageError::Other(std::io::Error::new(std::io::ErrorKind::InvalidInput, error))
+}
+
+/// The longest directory, ending in `/`, that holds both `a` and `b`.
+fn common_directory<'a>(a: &'a str, b: &str) -> &'a str {
+    let common = a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count();
+    a.as_bytes()[..common]
+        .iter()
+        .rposition(|&c| c == b'/')
+        .map_or("", |end| &a[..=end])
+}
+
 impl StorageClient {
-    pub fn new(config: StorageConfig, account: &str, video: &str) -> Self {
-        StorageClient {
+    pub fn new(config: StorageConfig, account: &str, video: &str) -> Result<Self, StorageError> {
+        let mut builder = Client::builder()
+            .pool_idle_timeout(config.idle_timeout)
+            .pool_max_idle_per_host(config.pool_size)
+            .tcp_keepalive(config.keep_alive)
+            .http2_keep_alive_interval(config.keep_alive)
+            .user_agent(&config.user_agent);
+        if let Some(timeout) = config.connect_timeout {
+            builder = builder.connect_timeout(timeout);
+        }
+        if config.http2_prior_knowledge {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(pem) = &config.ca_certificate {
+            let certificate = Certificate::from_pem(pem).map_err(invalid_config)?;
+            builder = builder.add_root_certificate(certificate);
+        }
+        Ok(StorageClient {
+            client: builder.build().map_err(invalid_config)?,
             config,
-            client: Client::new(),
             account: account.to_owned(),
             video: video.to_owned(),
-        }
+        })
+    }
+
+    /// Opens the video a `http://host:port/account/video` URL points to.
+    pub fn from_url(url: &Url) -> Result<Self, StorageError> {
+        Self::from_url_with(url, StorageConfig::default())
+    }
+
+    /// Opens the video a `http://host:port/account/video` or
+    /// `https://host:port/account/video` URL points to, with the rest of the
+    /// settings from `config`.
+    pub fn from_url_with(url: &Url, config: StorageConfig) -> Result<Self, StorageError> {
+        let node_address = url
+            .host_str()
+            .ok_or_else(|| invalid_url(url, "missing host"))?;
+        let segments: Vec<_> = url
+            .path_segments()
+            .map(|s| s.filter(|s| !s.is_empty()).collect())
+            .unwrap_or_default();
+        let [account, video] = segments[..] else {
+            return Err(invalid_url(url, "expected /account/video"));
+        };
+        let config = StorageConfig {
+            storage_port: url.port_or_known_default().unwrap_or(80).into(),
+            node_address: node_address.to_owned(),
+            https: url.scheme() == "https",
+            ..config
+        };
+        Self::new(config, account, video)
     }
 
     pub fn from_reqwest_error(error: reqwest::Error) -> StorageError {
-        StorageError::HttpError(error.to_string())
+        if error.is_timeout() {
+            return StorageError::Timeout(error.into());
+        }
+        if error.is_connect() {
+            return StorageError::ServiceUnavailable(error.into());
+        }
+        if error.is_body() {
+            // the connection broke while streaming the body.
+            return StorageError::Other(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                error,
+            ));
+        }
+        match error.status() {
+            Some(StatusCode::BAD_REQUEST) => StorageError::Other(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                error,
+            )),
+            Some(StatusCode::NOT_FOUND) => StorageError::NotFound,
+            Some(StatusCode::UNAUTHORIZED) | Some(StatusCode::FORBIDDEN) => {
+                StorageError::AuthenticationError
+            }
+            Some(StatusCode::CONFLICT) => StorageError::Conflict,
+            Some(StatusCode::PRECONDITION_FAILED) => StorageError::PreconditionFailed,
+            Some(StatusCode::REQUEST_TIMEOUT) | Some(StatusCode::GATEWAY_TIMEOUT) => {
+                StorageError::Timeout(error.into())
+            }
+            Some(StatusCode::TOO_MANY_REQUESTS) => StorageError::Throttled(error.into()),
+            Some(StatusCode::NOT_IMPLEMENTED) => StorageError::Unsupported,
+            Some(StatusCode::INTERNAL_SERVER_ERROR)
+            | Some(StatusCode::BAD_GATEWAY)
+            | Some(StatusCode::SERVICE_UNAVAILABLE) => {
+                StorageError::ServiceUnavailable(error.into())
+            }
+            _ => StorageError::HttpError(error.into()),
+        }
+    }
+
+    /// Sends `request`, signed for its path, the permissions its method
+    /// needs and the digest of a body that is not streamed when the config
+    /// has a signer. Requests signed already are sent as they are.
+    async fn execute(&self, request: RequestBuilder) -> Result<Response, StorageError> {
+        let mut request = request.build().map_err(Self::from_reqwest_error)?;
+        if let Some(signer) = &self.config.signer {
+            let signed = request
+                .url()
+                .query_pairs()
+                .any(|(key, _)| key == SIGNATURE_QUERY);
+            if !signed {
+                let permissions = protocol::permissions_for(request.method());
+                let url = request.url().clone();
+                let operation = protocol::operation_of(request.method(), &url);
+                let expiry = self.config.signature_expiry;
+                let digest = request
+                    .body()
+                    .and_then(|body| body.as_bytes())
+                    .and_then(|body| Checksums::of(body).md5_base64());
+                let url = match (digest, operation) {
+                    (Some(md5), operation) => {
+                        let value = HeaderValue::from_str(&md5).expect("base64 is a valid header");
+                        request.headers_mut().insert(CONTENT_MD5_HEADER, value);
+                        signer.sign_digest(url, permissions, operation, &md5, expiry)
+                    }
+                    (None, Some(operation)) => {
+                        signer.sign_operation(url, permissions, operation, expiry)
+                    }
+                    (None, None) => signer.sign(url, permissions, expiry),
+                };
+                *request.url_mut() = url;
+            }
+        }
+        // streamed bodies may take any time to produce.
+        let streamed = request.body().is_some_and(|body| body.as_bytes().is_none());
+        let response = self.client.execute(request);
+        match self.config.request_timeout {
+            Some(timeout) if !streamed => tokio::time::timeout(timeout, response)
+                .await
+                .map_err(|_| {
+                    StorageError::Timeout(format!("no answer within {timeout:?}").into())
+                })?,
+            _ => response.await,
+        }
+        .map_err(Self::from_reqwest_error)
+    }
+
+    /// The content of `response`, failing when it streams too slowly.
+    fn content(&self, response: Response) -> StreamType {
+        let stream: StreamType = Box::pin(
+            response
+                .bytes_stream()
+                .map(|f| f.map_err(Self::from_reqwest_error)),
+        );
+        match self.config.min_throughput {
+            Some(minimum) => enforce_throughput(stream, minimum),
+            None => stream,
+        }
+    }
+
+    /// Sends `request` and turns error statuses into the matching
+    /// [`StorageError`].
+    async fn send(&self, request: RequestBuilder) -> Result<Response, StorageError> {
+        self.execute(request)
+            .await?
+            .error_for_status()
+            .map_err(Self::from_reqwest_error)
+    }
+
+    fn get_header(headers: &HeaderMap, name: impl reqwest::header::AsHeaderName) -> Option<&str> {
+        headers.get(name).and_then(|v| v.to_str().ok())
+    }
+
+    async fn get_optional_metadata(&self, path: &str) -> Result<Option<String>, StorageError> {
+        let uri = self.get_url(path, true);
+        let response = self.execute(self.client.get(uri)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let metadata = response
+            .error_for_status()
+            .map_err(Self::from_reqwest_error)?
+            .text()
+            .await
+            .map_err(Self::from_reqwest_error)?;
+        Ok(Some(metadata))
+    }
+
+    async fn post(
+        &self,
+        uri: String,
+        body: Body,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        let request = match condition {
+            Some(WriteCondition::IfMatch(etag)) => {
+                self.client.post(uri).header(IF_MATCH, format!("\"{etag}\""))
+            }
+            Some(WriteCondition::IfNoneMatch) => self.client.post(uri).header(IF_NONE_MATCH, "*"),
+            None => self.client.post(uri),
+        };
+        self.send(request.body(body)).await?;
+        Ok(())
+    }
+
+    async fn delete_url(&self, uri: String) -> Result<Response, StorageError> {
+        self.send(self.client.delete(uri)).await
+    }
+
+    async fn post_from(&self, query: &str, from: &str, to: &str) -> Result<(), StorageError> {
+        let uri = self.get_url(to, false);
+        let mut request = self.client.post(uri).query(&[(query, from)]);
+        if let Some(signer) = &self.config.signer {
+            // a single signature covers the source and the target.
+            let scope = self.get_url(common_directory(from, to), false);
+            let scope = Url::parse(&scope).map_err(|e| {
+                StorageError::Other(std::io::Error::new(std::io::ErrorKind::InvalidInput, e))
+            })?;
+            let permissions = Permissions {
+                write: true,
+                ..protocol::source_permissions(query).unwrap_or_default()
+            };
+            let expiry = self.config.signature_expiry;
+            let signed = signer.sign_operation(scope, permissions, query, expiry);
+            request = request.query(&signed.query_pairs().collect::<Vec<_>>());
+        }
+        self.send(request).await?;
+        Ok(())
     }
 
     fn get_url(&self, path: &str, metadata: bool) -> String {
         format!(
-            "http://{}:{}/{}/{}/{}{}",
-            self.config.node_address, self.config.storage_port, self.account, self.video, path, if metadata { "/metadata" } else { ""}
+            "{}://{}:{}/{}/{}/{}{}",
+            if self.config.https { "https" } else { "http" },
+            self.config.node_address,
+            self.config.storage_port,
+            self.account,
+            self.video,
+            path,
+            if metadata { METADATA_SUFFIX } else { "" }
         )
     }
 }
@@ -41,64 +347,319 @@ impl StorageClient {
 impl StorageContainer for StorageClient {
     async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
         let uri = self.get_url(path, true);
-        let response = self
-            .client
-            .get(uri)
-            .send()
-            .await
-            .map_err(Self::from_reqwest_error)?;
+        let response = self.send(self.client.get(uri)).await?;
         let metadata = response.text().await.map_err(Self::from_reqwest_error)?;
         Ok(metadata)
     }
 
     async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
         let uri = self.get_url(path, true);
-        self
-            .client
-            .post(uri)
-            .body(metadata)
-            .send()
-            .await
-            .map_err(Self::from_reqwest_error)?;
-        Ok(())
+        self.post(uri, metadata.into(), None).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        let uri = self.get_url(path, true);
+        self.post(uri, metadata.into(), Some(condition)).await
     }
 
     async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
         let uri = self.get_url(path, false);
 
-        let res = self
-            .client
-            .get(uri)
-            .send()
-            .await
-            .map_err(Self::from_reqwest_error)?;
-        let stream = res
-            .bytes_stream()
-            .map(|f| f.map_err(Self::from_reqwest_error));
-        Ok(Box::pin(stream))
+        let res = self.send(self.client.get(uri)).await?;
+        Ok(self.content(res))
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        if length == Some(0) {
+            return Ok(Box::pin(futures::stream::empty()));
+        }
+        let uri = self.get_url(path, false);
+        // a range reaching past the largest offset is open-ended.
+        let range = match length.and_then(|length| offset.checked_add(length)) {
+            Some(end) => format!("bytes={}-{}", offset, end - 1),
+            None => format!("bytes={}-", offset),
+        };
+
+        let res = self.execute(self.client.get(uri).header(RANGE, range)).await?;
+        if res.status() == StatusCode::RANGE_NOT_SATISFIABLE {
+            return Ok(Box::pin(futures::stream::empty()));
+        }
+        let res = res.error_for_status().map_err(Self::from_reqwest_error)?;
+        let partial = res.status() == StatusCode::PARTIAL_CONTENT;
+        let stream = self.content(res);
+        // the server ignored the range and returned the whole object.
+        if !partial {
+            return Ok(slice_stream(stream, offset, length));
+        }
+        Ok(stream)
     }
 
     async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
         let uri = self.get_url(path, false);
         let pinned_content = Box::pin(content);
         let body = Body::wrap_stream(pinned_content);
-        self
-            .client
-            .post(uri)
-            .body(body)
-            .send()
-            .await
+        self.post(uri, body, None).await
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        let uri = self.get_url(path, false);
+        self.post(uri, Body::wrap_stream(content), Some(condition)).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        let uri = self.get_url(path, false);
+        let head = self.execute(self.client.head(uri));
+        let (response, metadata) = futures::join!(head, self.get_optional_metadata(path));
+        let response = response?
+            .error_for_status()
             .map_err(Self::from_reqwest_error)?;
+
+        let headers = response.headers();
+        Ok(ObjectProperties {
+            content_length: Self::get_header(headers, CONTENT_LENGTH)
+                .and_then(|v| v.parse().ok())
+                .unwrap_or_default(),
+            etag: Self::get_header(headers, ETAG).map(|v| v.trim_matches('"').to_owned()),
+            content_type: Self::get_header(headers, CONTENT_TYPE).map(str::to_owned),
+            last_modified: Self::get_header(headers, LAST_MODIFIED)
+                .and_then(|v| httpdate::parse_http_date(v).ok()),
+            metadata: metadata?,
+            checksums: Checksums::from_base64(
+                Self::get_header(headers, CONTENT_MD5_HEADER),
+                Self::get_header(headers, CONTENT_CRC64_HEADER),
+            ),
+        })
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        let uri = self.get_url(path, false);
+        self.delete_url(uri).await?;
         Ok(())
     }
 
-    async fn exists(&self, path:&str) -> bool {
+    async fn delete_prefix(&self, prefix: &str) -> Result<usize, StorageError> {
+        let uri = format!("{}?{}", self.get_url(prefix, false), RECURSIVE_QUERY);
+        let response = self.delete_url(uri).await?;
+        let count = response.text().await.map_err(Self::from_reqwest_error)?;
+        count.trim().parse().map_err(|_| {
+            StorageError::HttpError(format!("invalid delete count {count}").into())
+        })
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.post_from(COPY_FROM_QUERY, from, to).await
+    }
+
+    async fn rename(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.post_from(MOVE_FROM_QUERY, from, to).await
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        let uri = format!("{}?{}", self.get_url(prefix, false), LIST_QUERY);
+        let res = self.send(self.client.get(uri)).await?;
+        let body = res
+            .bytes_stream()
+            .map(|f| f.map_err(Self::from_reqwest_error));
+        Ok(protocol::decode_pages(body))
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        let uri = format!("{}?{}", self.get_url(prefix, false), WATCH_QUERY);
+        let body = self
+            .send(self.client.get(uri))
+            .await?
+            .bytes_stream()
+            .map(|f| f.map_err(Self::from_reqwest_error));
+        Ok(protocol::decode_events(body))
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
         let uri = self.get_url(path, false);
-        self
-            .client
-            .head(uri)
-            .send()
-            .await
-            .is_ok()
+        let response = self.execute(self.client.head(uri)).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(false);
+        }
+        response.error_for_status().map_err(Self::from_reqwest_error)?;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use hyper::{
+        server::conn::AddrIncoming,
+        service::{make_service_fn, service_fn},
+        Server,
+    };
+    use std::convert::Infallible;
+
+    /// Serves every request with `respond()` on a local port and returns the
+    /// port.
+    async fn serve<F>(respond: F) -> u16
+    where
+        F: Fn() -> hyper::Response<hyper::Body> + Clone + Send + Sync + 'static,
+    {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let make_service = make_service_fn(move |_| {
+            let respond = respond.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |_| {
+                    let response = respond();
+                    async move { Ok::<_, Infallible>(response) }
+                }))
+            }
+        });
+        let incoming = AddrIncoming::from_listener(listener).unwrap();
+        tokio::spawn(Server::builder(incoming).serve(make_service));
+        port
+    }
+
+    /// A client of a server that answers every request with `status` and an
+    /// error page.
+    async fn answering(status: u16) -> StorageClient {
+        let port = serve(move || {
+            let mut response = hyper::Response::new(hyper::Body::from("error page"));
+            *response.status_mut() = StatusCode::from_u16(status).unwrap();
+            response
+        })
+        .await;
+        client(port)
+    }
+
+    fn local(port: u16) -> StorageConfig {
+        StorageConfig {
+            storage_port: port.into(),
+            node_address: "127.0.0.1".to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn client(port: u16) -> StorageClient {
+        StorageClient::new(local(port), "account", "video").unwrap()
+    }
+
+    fn body() -> StreamType {
+        Box::pin(futures::stream::iter([Ok(Bytes::from("content"))]))
+    }
+
+    #[tokio::test]
+    async fn maps_error_statuses() {
+        let missing = answering(404).await;
+        assert!(matches!(
+            missing.get_content("a.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(matches!(
+            missing.get_metadata("a.ts").await,
+            Err(StorageError::NotFound)
+        ));
+        assert!(!missing.exists("a.ts").await.unwrap());
+
+        let forbidden = answering(403).await;
+        assert!(matches!(
+            forbidden.get_content_range("a.ts", 0, Some(10)).await,
+            Err(StorageError::AuthenticationError)
+        ));
+        assert!(matches!(
+            forbidden.set_content("a.ts", body()).await,
+            Err(StorageError::AuthenticationError)
+        ));
+
+        let conflict = answering(409).await;
+        assert!(matches!(
+            conflict.set_metadata("a.ts", "{}".to_owned()).await,
+            Err(StorageError::Conflict)
+        ));
+
+        let failing = answering(500).await;
+        assert!(matches!(
+            failing.set_content("a.ts", body()).await,
+            Err(StorageError::ServiceUnavailable(_))
+        ));
+        assert!(matches!(
+            failing.exists("a.ts").await,
+            Err(StorageError::ServiceUnavailable(_))
+        ));
+        assert!(failing.list_all("").await.is_err());
+        assert!(matches!(
+            answering(501).await.watch("").await,
+            Err(StorageError::Unsupported)
+        ));
+    }
+
+    #[tokio::test]
+    async fn unreachable_servers_are_not_absent() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        assert!(client(port).exists("a.ts").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn times_out_hung_proxies() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            // keeps the connections open without ever answering.
+            let mut connections = Vec::new();
+            while let Ok((connection, _)) = listener.accept().await {
+                connections.push(connection);
+            }
+        });
+        let config = StorageConfig {
+            request_timeout: Some(Duration::from_millis(100)),
+            ..local(port)
+        };
+        let client = StorageClient::new(config, "account", "video").unwrap();
+        assert!(matches!(
+            client.get_content("a.ts").await,
+            Err(StorageError::Timeout(_))
+        ));
+        assert!(matches!(
+            client.exists("a.ts").await,
+            Err(StorageError::Timeout(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn fails_stalled_content() {
+        let port = serve(|| {
+            let stalling = futures::stream::iter([Ok::<_, Infallible>(Bytes::from("a"))])
+                .chain(futures::stream::pending());
+            hyper::Response::new(hyper::Body::wrap_stream(stalling))
+        })
+        .await;
+        let config = StorageConfig {
+            min_throughput: Some(MinThroughput {
+                bytes: 1,
+                window: Duration::from_millis(100),
+            }),
+            ..local(port)
+        };
+        let client = StorageClient::new(config, "account", "video").unwrap();
+        let mut content = client.get_content("a.ts").await.unwrap();
+        assert_eq!(content.next().await.unwrap().unwrap(), "a");
+        assert!(matches!(
+            content.next().await,
+            Some(Err(StorageError::Timeout(_)))
+        ));
     }
 }
@@ -0,0 +1,1003 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::ErrorKind,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::{Duration, SystemTime},
+};
+
+use async_trait::async_trait;
+use aws_sdk_s3::{
+    config::{http::HttpResponse, BehaviorVersion, Builder, Credentials, Region},
+    error::{ProvideErrorMetadata, SdkError},
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    types::{
+        ChecksumAlgorithm, ChecksumMode, ChecksumType, CompletedMultipartUpload, CompletedPart,
+        MetadataDirective,
+    },
+    Client,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::{Bytes, BytesMut};
+use futures::{Stream, StreamExt};
+use storage::{
+    guess_content_type, invalid_url, poll_changes, BlockUpload, ChecksumHasher, Checksums,
+    ListStream, ObjectInfo, ObjectProperties, Permissions, StorageContainer, StorageError,
+    StorageRegistry, StreamType, Url, WatchStream, WriteCondition,
+};
+
+pub use aws_sdk_s3;
+
+/// Registers `s3://bucket/prefix` URLs, connecting with the settings of
+/// [`S3Config::from_env`].
+pub fn register(registry: &mut StorageRegistry) {
+    let client = S3Config::from_env().client();
+    registry.register("s3", move |url| {
+        Ok(Box::new(S3StorageContainer::from_url(client.clone(), url)?))
+    });
+}
+
+/// The user-metadata key holding the (base64 encoded) container metadata.
+const METADATA_KEY: &str = "metadata";
+/// S3 rejects parts smaller than 5 MiB, except for the last one.
+const MIN_PART_SIZE: usize = 5 * 1024 * 1024;
+const DEFAULT_PART_SIZE: usize = 8 * 1024 * 1024;
+const DEFAULT_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Connection settings for an S3-compatible service.
+#[derive(Debug, Clone, Default)]
+pub struct S3Config {
+    /// A custom endpoint, e.g. `http://localhost:9000` for MinIO.
+    pub endpoint: Option<String>,
+    pub region: String,
+    pub access_key_id: Option<String>,
+    pub secret_access_key: Option<String>,
+    /// Addresses buckets as `endpoint/bucket` rather than `bucket.endpoint`,
+    /// which most S3-compatible services need.
+    pub force_path_style: bool,
+}
+
+impl S3Config {
+    /// Reads the settings from the standard `AWS_ENDPOINT_URL`, `AWS_REGION`,
+    /// `AWS_ACCESS_KEY_ID` and `AWS_SECRET_ACCESS_KEY` variables.
+    pub fn from_env() -> Self {
+        let endpoint = std::env::var("AWS_ENDPOINT_URL").ok();
+        Self {
+            force_path_style: endpoint.is_some(),
+            endpoint,
+            region: std::env::var("AWS_REGION").unwrap_or_else(|_| "us-east-1".to_owned()),
+            access_key_id: std::env::var("AWS_ACCESS_KEY_ID").ok(),
+            secret_access_key: std::env::var("AWS_SECRET_ACCESS_KEY").ok(),
+        }
+    }
+
+    pub fn client(&self) -> Client {
+        let mut builder = Builder::new()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(self.region.clone()))
+            .force_path_style(self.force_path_style);
+        if let Some(endpoint) = &self.endpoint {
+            builder = builder.endpoint_url(endpoint);
+        }
+        if let (Some(id), Some(secret)) = (&self.access_key_id, &self.secret_access_key) {
+            builder = builder.credentials_provider(Credentials::new(
+                id,
+                secret,
+                None,
+                None,
+                "storage_s3",
+            ));
+        }
+        Client::from_conf(builder.build())
+    }
+}
+
+/// A container backed by an S3 bucket, optionally below a key prefix.
+///
+/// The metadata is kept as user-metadata of the object, so S3 ETags, which
+/// only depend on the content, don't change when only the metadata does.
+#[derive(Clone)]
+pub struct S3StorageContainer {
+    client: Client,
+    bucket: String,
+    prefix: String,
+    part_size: usize,
+    watch_interval: Duration,
+}
+
+impl S3StorageContainer {
+    pub fn new(client: Client, bucket: &str, prefix: &str) -> Self {
+        let prefix = prefix.trim_matches('/');
+        Self {
+            client,
+            bucket: bucket.to_owned(),
+            prefix: if prefix.is_empty() {
+                String::new()
+            } else {
+                format!("{prefix}/")
+            },
+            part_size: DEFAULT_PART_SIZE,
+            watch_interval: DEFAULT_WATCH_INTERVAL,
+        }
+    }
+
+    /// Opens the bucket and prefix of a `s3://bucket/prefix` URL.
+    pub fn from_url(client: Client, url: &Url) -> Result<Self, StorageError> {
+        match url.host_str() {
+            Some(bucket) if url.scheme() == "s3" => Ok(Self::new(client, bucket, url.path())),
+            _ => Err(invalid_url(url, "expected s3://bucket/prefix")),
+        }
+    }
+
+    /// Sets the size of the parts of a multipart upload. Content smaller than
+    /// one part is uploaded with a single request.
+    pub fn with_part_size(mut self, part_size: usize) -> Self {
+        self.part_size = part_size.max(MIN_PART_SIZE);
+        self
+    }
+
+    /// Sets how often [`StorageContainer::watch`] lists the bucket, plain S3
+    /// has no change notifications that reach the client.
+    pub fn with_watch_interval(mut self, interval: Duration) -> Self {
+        self.watch_interval = interval;
+        self
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn bucket(&self) -> &str {
+        &self.bucket
+    }
+
+    fn key(&self, path: &str) -> String {
+        format!("{}{}", self.prefix, path.trim_start_matches('/'))
+    }
+
+    fn copy_source(&self, path: &str) -> String {
+        encode_copy_source(&format!("{}/{}", self.bucket, self.key(path)))
+    }
+
+    pub fn from_sdk_error<E>(error: SdkError<E, HttpResponse>) -> StorageError
+    where
+        E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    {
+        match &error {
+            SdkError::TimeoutError(_) => return StorageError::Timeout(error.into()),
+            SdkError::DispatchFailure(failure) if failure.is_timeout() => {
+                return StorageError::Timeout(error.into())
+            }
+            SdkError::DispatchFailure(failure) if failure.is_io() => {
+                return StorageError::ServiceUnavailable(error.into())
+            }
+            _ => {}
+        }
+        let status = error.raw_response().map(|r| r.status().as_u16());
+        match status {
+            Some(404) => StorageError::NotFound,
+            Some(401) | Some(403) => StorageError::AuthenticationError,
+            Some(409) => StorageError::Conflict,
+            Some(412) => StorageError::PreconditionFailed,
+            Some(408) | Some(504) => StorageError::Timeout(error.into()),
+            Some(503) if error.code() == Some("SlowDown") => StorageError::Throttled(error.into()),
+            Some(429) => StorageError::Throttled(error.into()),
+            Some(500) | Some(502) | Some(503) => StorageError::ServiceUnavailable(error.into()),
+            _ => StorageError::HttpError(error.into()),
+        }
+    }
+
+    /// A conditional request racing with another one fails with a conflict.
+    fn from_conditional_error<E>(error: SdkError<E, HttpResponse>) -> StorageError
+    where
+        E: ProvideErrorMetadata + std::error::Error + Send + Sync + 'static,
+    {
+        match Self::from_sdk_error(error) {
+            StorageError::Conflict => StorageError::PreconditionFailed,
+            error => error,
+        }
+    }
+
+    fn decode_metadata(
+        metadata: Option<&HashMap<String, String>>,
+    ) -> Result<Option<String>, StorageError> {
+        let Some(value) = metadata.and_then(|m| m.get(METADATA_KEY)) else {
+            return Ok(None);
+        };
+        let bytes = STANDARD
+            .decode(value)
+            .map_err(|e| StorageError::HttpError(e.into()))?;
+        String::from_utf8(bytes)
+            .map(Some)
+            .map_err(|e| StorageError::HttpError(e.into()))
+    }
+
+    fn encode_metadata(metadata: &str) -> HashMap<String, String> {
+        HashMap::from([(METADATA_KEY.to_owned(), STANDARD.encode(metadata))])
+    }
+
+    /// The ETag and user-metadata of an existing object, if any.
+    async fn head(
+        &self,
+        path: &str,
+    ) -> Result<Option<(Option<String>, HashMap<String, String>)>, StorageError> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .send()
+            .await
+        {
+            Ok(output) => Ok(Some((
+                output.e_tag().map(str::to_owned),
+                output.metadata().cloned().unwrap_or_default(),
+            ))),
+            Err(e) => match Self::from_sdk_error(e) {
+                StorageError::NotFound => Ok(None),
+                e => Err(e),
+            },
+        }
+    }
+
+    async fn write_content(
+        &self,
+        path: &str,
+        mut content: StreamType,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        // keep the metadata like the other containers do when the content is replaced.
+        let existing = self.head(path).await?;
+        match (&condition, &existing) {
+            (Some(WriteCondition::IfNoneMatch), Some(_)) => {
+                return Err(StorageError::PreconditionFailed)
+            }
+            (Some(WriteCondition::IfMatch(_)), None) => {
+                return Err(StorageError::PreconditionFailed)
+            }
+            _ => {}
+        }
+        let metadata = existing.and_then(|(_, metadata)| {
+            metadata
+                .get(METADATA_KEY)
+                .map(|value| HashMap::from([(METADATA_KEY.to_owned(), value.clone())]))
+        });
+        let (if_match, if_none_match) = match condition {
+            Some(WriteCondition::IfMatch(etag)) => (Some(etag), None),
+            Some(WriteCondition::IfNoneMatch) => (None, Some("*".to_owned())),
+            None => (None, None),
+        };
+        let key = self.key(path);
+        let content_type = guess_content_type(path);
+
+        let (first, done) = read_part(&mut content, self.part_size).await?;
+        if done {
+            // S3 checks both on receipt and keeps the CRC64 with the object.
+            let checksums = Checksums::of(&first);
+            self.client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(&key)
+                .set_content_md5(checksums.md5_base64())
+                .set_checksum_crc64_nvme(checksums.crc64_base64())
+                .set_content_type(content_type.map(str::to_owned))
+                .set_metadata(metadata)
+                .set_if_match(if_match)
+                .set_if_none_match(if_none_match)
+                .body(ByteStream::from(first))
+                .send()
+                .await
+                .map_err(Self::from_conditional_error)?;
+            return Ok(());
+        }
+
+        let upload_id = self.create_upload(&key, content_type, metadata).await?;
+        let result = self
+            .upload_parts(&key, &upload_id, first, content, if_match, if_none_match)
+            .await;
+        if result.is_err() {
+            self.abort_upload(&key, &upload_id).await;
+        }
+        result
+    }
+
+    async fn upload_parts(
+        &self,
+        key: &str,
+        upload_id: &str,
+        first: Bytes,
+        mut content: StreamType,
+        if_match: Option<String>,
+        if_none_match: Option<String>,
+    ) -> Result<(), StorageError> {
+        let mut hasher = ChecksumHasher::new();
+        hasher.update(&first);
+        let mut parts = Vec::new();
+        let mut part = first;
+        let mut done = false;
+        while !part.is_empty() || parts.is_empty() {
+            let part_number = parts.len() as i32 + 1;
+            parts.push(self.upload_part(key, upload_id, part_number, part).await?);
+            if done {
+                break;
+            }
+            (part, done) = read_part(&mut content, self.part_size).await?;
+            hasher.update(&part);
+        }
+        let checksums = hasher.finish();
+        self.complete_upload(key, upload_id, parts, &checksums, if_match, if_none_match)
+            .await
+    }
+
+    /// Starts a multipart upload with a full-object CRC64 and returns its id.
+    async fn create_upload(
+        &self,
+        key: &str,
+        content_type: Option<&str>,
+        metadata: Option<HashMap<String, String>>,
+    ) -> Result<String, StorageError> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .checksum_algorithm(ChecksumAlgorithm::Crc64Nvme)
+            .checksum_type(ChecksumType::FullObject)
+            .set_content_type(content_type.map(str::to_owned))
+            .set_metadata(metadata)
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        Ok(upload
+            .upload_id()
+            .ok_or_else(|| StorageError::HttpError("missing multipart upload id".into()))?
+            .to_owned())
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        part_number: i32,
+        part: Bytes,
+    ) -> Result<CompletedPart, StorageError> {
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .checksum_algorithm(ChecksumAlgorithm::Crc64Nvme)
+            .body(ByteStream::from(part))
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        Ok(CompletedPart::builder()
+            .set_e_tag(output.e_tag().map(str::to_owned))
+            .set_checksum_crc64_nvme(output.checksum_crc64_nvme().map(str::to_owned))
+            .part_number(part_number)
+            .build())
+    }
+
+    /// Completes the upload with `parts`, which must be in ascending order.
+    async fn complete_upload(
+        &self,
+        key: &str,
+        upload_id: &str,
+        parts: Vec<CompletedPart>,
+        checksums: &Checksums,
+        if_match: Option<String>,
+        if_none_match: Option<String>,
+    ) -> Result<(), StorageError> {
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .checksum_type(ChecksumType::FullObject)
+            .set_checksum_crc64_nvme(checksums.crc64_base64())
+            .set_if_match(if_match)
+            .set_if_none_match(if_none_match)
+            .send()
+            .await
+            .map_err(Self::from_conditional_error)?;
+        Ok(())
+    }
+
+    async fn abort_upload(&self, key: &str, upload_id: &str) {
+        // best effort, a lifecycle rule should clean up the rest.
+        let _ = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await;
+    }
+
+    async fn write_metadata(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: Option<WriteCondition>,
+    ) -> Result<(), StorageError> {
+        let head = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        let etag = head.e_tag().map(str::to_owned);
+        let holds = match &condition {
+            None => true,
+            Some(WriteCondition::IfMatch(expected)) => etag.as_ref() == Some(expected),
+            Some(WriteCondition::IfNoneMatch) => head
+                .metadata()
+                .is_none_or(|m| !m.contains_key(METADATA_KEY)),
+        };
+        if !holds {
+            return Err(StorageError::PreconditionFailed);
+        }
+        // S3 can only replace metadata by copying the object onto itself.
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .copy_source(self.copy_source(path))
+            .set_copy_source_if_match(condition.and(etag))
+            .metadata_directive(MetadataDirective::Replace)
+            .checksum_algorithm(ChecksumAlgorithm::Crc64Nvme)
+            .set_content_type(head.content_type().map(str::to_owned))
+            .set_metadata(Some(Self::encode_metadata(&metadata)))
+            .send()
+            .await
+            .map_err(Self::from_conditional_error)?;
+        Ok(())
+    }
+}
+
+/// Uploads the parts of a multipart upload, which S3 assembles in the order
+/// of their part numbers on completion.
+struct S3BlockUpload {
+    storage: S3StorageContainer,
+    key: String,
+    upload_id: String,
+    parts: Mutex<BTreeMap<usize, CompletedPart>>,
+}
+
+#[async_trait]
+impl BlockUpload for S3BlockUpload {
+    fn min_block_size(&self) -> usize {
+        MIN_PART_SIZE
+    }
+
+    async fn stage_block(&self, index: usize, block: Bytes) -> Result<(), StorageError> {
+        let part_number = i32::try_from(index + 1)
+            .map_err(|_| StorageError::Other(ErrorKind::InvalidInput.into()))?;
+        let part = self
+            .storage
+            .upload_part(&self.key, &self.upload_id, part_number, block)
+            .await?;
+        self.parts.lock().unwrap().insert(index, part);
+        Ok(())
+    }
+
+    async fn commit(
+        self: Box<Self>,
+        blocks: usize,
+        checksums: Checksums,
+    ) -> Result<(), StorageError> {
+        let parts = std::mem::take(&mut *self.parts.lock().unwrap());
+        if parts.len() != blocks || parts.keys().any(|&index| index >= blocks) {
+            self.storage.abort_upload(&self.key, &self.upload_id).await;
+            return Err(StorageError::Other(std::io::Error::new(
+                ErrorKind::InvalidInput,
+                "not every block was staged",
+            )));
+        }
+        let parts = parts.into_values().collect();
+        self.storage
+            .complete_upload(&self.key, &self.upload_id, parts, &checksums, None, None)
+            .await
+    }
+
+    async fn abort(self: Box<Self>) -> Result<(), StorageError> {
+        self.storage.abort_upload(&self.key, &self.upload_id).await;
+        Ok(())
+    }
+}
+
+/// Reads up to `size` bytes and reports whether the stream is exhausted.
+async fn read_part(content: &mut StreamType, size: usize) -> Result<(Bytes, bool), StorageError> {
+    let mut part = BytesMut::new();
+    while part.len() < size {
+        match content.next().await {
+            Some(chunk) => part.extend_from_slice(&chunk?),
+            None => return Ok((part.freeze(), true)),
+        }
+    }
+    Ok((part.freeze(), false))
+}
+
+/// Percent-encodes everything but unreserved characters and `/`.
+fn encode_copy_source(source: &str) -> String {
+    let mut encoded = String::with_capacity(source.len());
+    for byte in source.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    encoded
+}
+
+fn range_header(offset: u64, length: Option<u64>) -> String {
+    // a range reaching past the largest offset is open-ended.
+    match length.and_then(|length| offset.checked_add(length)) {
+        Some(end) => format!("bytes={}-{}", offset, end - 1),
+        None => format!("bytes={offset}-"),
+    }
+}
+
+/// Adapts the body of a response to a [`StreamType`].
+struct BodyStream(ByteStream);
+
+impl Stream for BodyStream {
+    type Item = Result<Bytes, StorageError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx).map(|item| {
+            item.map(|r| {
+                r.map_err(|e| {
+                    StorageError::Other(std::io::Error::new(ErrorKind::ConnectionAborted, e))
+                })
+            })
+        })
+    }
+}
+
+#[async_trait]
+impl StorageContainer for S3StorageContainer {
+    async fn get_content(&self, path: &str) -> Result<StreamType, StorageError> {
+        let output = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        Ok(Box::pin(BodyStream(output.body)))
+    }
+
+    async fn get_content_range(
+        &self,
+        path: &str,
+        offset: u64,
+        length: Option<u64>,
+    ) -> Result<StreamType, StorageError> {
+        if length == Some(0) {
+            return Ok(Box::pin(futures::stream::empty()));
+        }
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .range(range_header(offset, length))
+            .send()
+            .await;
+        match result {
+            Ok(output) => Ok(Box::pin(BodyStream(output.body))),
+            // the range starts at or beyond the end of the object.
+            Err(e) if e.raw_response().is_some_and(|r| r.status().as_u16() == 416) => {
+                Ok(Box::pin(futures::stream::empty()))
+            }
+            Err(e) => Err(Self::from_sdk_error(e)),
+        }
+    }
+
+    async fn get_metadata(&self, path: &str) -> Result<String, StorageError> {
+        let output = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        Self::decode_metadata(output.metadata())?.ok_or(StorageError::NotFound)
+    }
+
+    async fn set_content(&self, path: &str, content: StreamType) -> Result<(), StorageError> {
+        self.write_content(path, content, None).await
+    }
+
+    async fn set_metadata(&self, path: &str, metadata: String) -> Result<(), StorageError> {
+        self.write_metadata(path, metadata, None).await
+    }
+
+    async fn exists(&self, path: &str) -> Result<bool, StorageError> {
+        Ok(self.head(path).await?.is_some())
+    }
+
+    async fn set_content_if(
+        &self,
+        path: &str,
+        content: StreamType,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.write_content(path, content, Some(condition)).await
+    }
+
+    async fn set_metadata_if(
+        &self,
+        path: &str,
+        metadata: String,
+        condition: WriteCondition,
+    ) -> Result<(), StorageError> {
+        self.write_metadata(path, metadata, Some(condition)).await
+    }
+
+    async fn stat(&self, path: &str) -> Result<ObjectProperties, StorageError> {
+        let output = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .checksum_mode(ChecksumMode::Enabled)
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        Ok(ObjectProperties {
+            content_length: output.content_length().unwrap_or_default() as u64,
+            etag: output.e_tag().map(str::to_owned),
+            content_type: output.content_type().map(str::to_owned),
+            last_modified: output
+                .last_modified()
+                .and_then(|t| SystemTime::try_from(*t).ok()),
+            metadata: Self::decode_metadata(output.metadata())?,
+            // S3 only keeps the MD5 as ETag of objects uploaded in one part
+            // and not encrypted with KMS, so the CRC64 is what is reliable.
+            checksums: Checksums::from_base64(None, output.checksum_crc64_nvme()),
+        })
+    }
+
+    async fn list(&self, prefix: &str) -> Result<ListStream, StorageError> {
+        let client = self.client.clone();
+        let bucket = self.bucket.clone();
+        let root = self.prefix.clone();
+        let prefix = self.key(prefix);
+        // `None` once the last page was returned.
+        let pages = futures::stream::unfold(Some(None), move |token: Option<Option<String>>| {
+            let client = client.clone();
+            let bucket = bucket.clone();
+            let root = root.clone();
+            let prefix = prefix.clone();
+            async move {
+                let token = token?;
+                let result = client
+                    .list_objects_v2()
+                    .bucket(bucket)
+                    .prefix(prefix)
+                    .set_continuation_token(token)
+                    .send()
+                    .await;
+                let output = match result {
+                    Ok(output) => output,
+                    Err(e) => return Some((Err(Self::from_sdk_error(e)), None)),
+                };
+                let page = output
+                    .contents()
+                    .iter()
+                    .filter_map(|object| {
+                        Some(ObjectInfo {
+                            name: object.key()?.strip_prefix(root.as_str())?.to_owned(),
+                            size: object.size().unwrap_or_default() as u64,
+                            last_modified: object
+                                .last_modified()
+                                .and_then(|t| SystemTime::try_from(*t).ok()),
+                        })
+                    })
+                    .collect();
+                let next = match output.next_continuation_token() {
+                    Some(token) if output.is_truncated() == Some(true) => {
+                        Some(Some(token.to_owned()))
+                    }
+                    _ => None,
+                };
+                Some((Ok(page), next))
+            }
+        });
+        Ok(Box::pin(pages))
+    }
+
+    /// S3 binds a presigned URL to a single method, so only one of reading,
+    /// writing or deleting can be granted, and only for a single object.
+    async fn presign(
+        &self,
+        path: &str,
+        permissions: Permissions,
+        expiry: Duration,
+    ) -> Result<Url, StorageError> {
+        let config = PresigningConfig::expires_in(expiry)
+            .map_err(|e| StorageError::Other(std::io::Error::new(ErrorKind::InvalidInput, e)))?;
+        let key = self.key(path);
+        let request = match permissions {
+            _ if path.ends_with('/') => return Err(StorageError::Unsupported),
+            Permissions::READ => self
+                .client
+                .get_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .presigned(config)
+                .await
+                .map_err(Self::from_sdk_error)?,
+            Permissions::WRITE => self
+                .client
+                .put_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .presigned(config)
+                .await
+                .map_err(Self::from_sdk_error)?,
+            Permissions {
+                read: false,
+                write: false,
+                delete: true,
+            } => self
+                .client
+                .delete_object()
+                .bucket(&self.bucket)
+                .key(key)
+                .presigned(config)
+                .await
+                .map_err(Self::from_sdk_error)?,
+            _ => return Err(StorageError::Unsupported),
+        };
+        Url::parse(request.uri()).map_err(|e| StorageError::HttpError(e.into()))
+    }
+
+    /// The metadata is carried over when the upload starts, so metadata set
+    /// while it runs is lost.
+    async fn start_block_upload(&self, path: &str) -> Result<Box<dyn BlockUpload>, StorageError> {
+        let metadata = self.head(path).await?.and_then(|(_, metadata)| {
+            metadata
+                .get(METADATA_KEY)
+                .map(|value| HashMap::from([(METADATA_KEY.to_owned(), value.clone())]))
+        });
+        let key = self.key(path);
+        let upload_id = self
+            .create_upload(&key, guess_content_type(path), metadata)
+            .await?;
+        Ok(Box::new(S3BlockUpload {
+            storage: self.clone(),
+            key,
+            upload_id,
+            parts: Mutex::default(),
+        }))
+    }
+
+    async fn watch(&self, prefix: &str) -> Result<WatchStream, StorageError> {
+        let storage = self.clone();
+        let prefix = prefix.to_owned();
+        poll_changes(self.watch_interval, move || {
+            let storage = storage.clone();
+            let prefix = prefix.clone();
+            async move { storage.list_all(&prefix).await }
+        })
+        .await
+    }
+
+    async fn delete(&self, path: &str) -> Result<(), StorageError> {
+        // deleting a missing key succeeds on S3.
+        if self.head(path).await?.is_none() {
+            return Err(StorageError::NotFound);
+        }
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(self.key(path))
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        Ok(())
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .key(self.key(to))
+            .copy_source(self.copy_source(from))
+            .metadata_directive(MetadataDirective::Copy)
+            .checksum_algorithm(ChecksumAlgorithm::Crc64Nvme)
+            .send()
+            .await
+            .map_err(Self::from_sdk_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::TryStreamExt;
+
+    #[test]
+    fn builds_keys_and_ranges() {
+        let storage = S3StorageContainer::new(S3Config::default().client(), "bucket", "/a/b/");
+        assert_eq!(storage.key("/video.mp4"), "a/b/video.mp4");
+        assert_eq!(storage.copy_source("x y+z"), "bucket/a/b/x%20y%2Bz");
+        assert_eq!(range_header(10, Some(5)), "bytes=10-14");
+        assert_eq!(range_header(10, None), "bytes=10-");
+        assert_eq!(range_header(10, Some(u64::MAX)), "bytes=10-");
+    }
+
+    #[test]
+    fn opens_s3_urls() {
+        let client = S3Config::default().client();
+        let url = Url::parse("s3://videos/encoded/rust").unwrap();
+        let storage = S3StorageContainer::from_url(client.clone(), &url).unwrap();
+        assert_eq!(storage.bucket(), "videos");
+        assert_eq!(storage.key("video.mp4"), "encoded/rust/video.mp4");
+        let url = Url::parse("file:///videos").unwrap();
+        assert!(S3StorageContainer::from_url(client, &url).is_err());
+    }
+
+    #[tokio::test]
+    async fn presigns_single_method_urls() {
+        let client = S3Config {
+            endpoint: Some("http://localhost:9000".to_owned()),
+            region: "us-east-1".to_owned(),
+            access_key_id: Some("key".to_owned()),
+            secret_access_key: Some("secret".to_owned()),
+            force_path_style: true,
+        }
+        .client();
+        let storage = S3StorageContainer::new(client, "videos", "encoded");
+        let expiry = Duration::from_secs(600);
+        let url = storage
+            .presign("video.mp4", Permissions::READ, expiry)
+            .await
+            .unwrap();
+        assert_eq!(url.path(), "/videos/encoded/video.mp4");
+        assert!(url.query_pairs().any(|(k, v)| k == "X-Amz-Expires" && v == "600"));
+        for (path, permissions) in [
+            ("video.mp4", Permissions::READ_WRITE),
+            ("level0/", Permissions::WRITE),
+        ] {
+            assert!(matches!(
+                storage.presign(path, permissions, expiry).await,
+                Err(StorageError::Unsupported)
+            ));
+        }
+    }
+
+    #[test]
+    fn round_trips_metadata() {
+        let encoded = S3StorageContainer::encode_metadata("{\"duration\": 1.5}\n");
+        let decoded = S3StorageContainer::decode_metadata(Some(&encoded)).unwrap();
+        assert_eq!(decoded.as_deref(), Some("{\"duration\": 1.5}\n"));
+        assert_eq!(S3StorageContainer::decode_metadata(None).unwrap(), None);
+    }
+
+    /// Runs the conformance suite against the bucket in `S3_TEST_BUCKET`.
+    #[tokio::test]
+    async fn conforms_on_a_live_bucket() {
+        let Ok(bucket) = std::env::var("S3_TEST_BUCKET") else {
+            return;
+        };
+        let client = S3Config::from_env().client();
+        let _ = client.create_bucket().bucket(&bucket).send().await;
+        let storage = S3StorageContainer::new(client, &bucket, "storage-s3-conformance");
+        storage.delete_prefix("").await.unwrap();
+        storage::conformance::run(&storage, "").await;
+    }
+
+    /// Runs against the bucket in `S3_TEST_BUCKET`, e.g. on a local MinIO with
+    /// `AWS_ENDPOINT_URL=http://localhost:9000`. Skipped when it is not set.
+    #[tokio::test]
+    async fn round_trip_against_a_live_bucket() {
+        let Ok(bucket) = std::env::var("S3_TEST_BUCKET") else {
+            return;
+        };
+        let client = S3Config::from_env().client();
+        let _ = client.create_bucket().bucket(&bucket).send().await;
+        let storage = S3StorageContainer::new(client, &bucket, "storage-s3-test")
+            .with_part_size(MIN_PART_SIZE);
+        storage.delete_prefix("").await.unwrap();
+
+        // two and a half parts, sent in odd sized chunks.
+        let content: Vec<u8> = (0..MIN_PART_SIZE * 5 / 2).map(|i| i as u8).collect();
+        let chunks: Vec<_> = content
+            .chunks(100_000)
+            .map(|c| Ok(Bytes::copy_from_slice(c)))
+            .collect();
+        storage
+            .set_content("video.mp4", Box::pin(futures::stream::iter(chunks)))
+            .await
+            .unwrap();
+        storage
+            .set_metadata("video.mp4", "{}".to_owned())
+            .await
+            .unwrap();
+        let read: Vec<Bytes> = storage
+            .get_content("video.mp4")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(read.concat(), content);
+        let range: Vec<Bytes> = storage
+            .get_content_range("video.mp4", 10, Some(5))
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(range.concat(), &content[10..15]);
+        assert_eq!(storage.get_metadata("video.mp4").await.unwrap(), "{}");
+        let properties = storage.stat("video.mp4").await.unwrap();
+        assert_eq!(properties.checksums.crc64, Checksums::of(&content).crc64);
+
+        // the same parts, staged concurrently.
+        let policy = storage::UploadPolicy {
+            block_size: MIN_PART_SIZE,
+            concurrency: 3,
+        };
+        let body = futures::stream::iter([Ok(Bytes::from(content.clone()))]);
+        storage
+            .set_content_parallel("parallel.mp4", Box::pin(body), &policy)
+            .await
+            .unwrap();
+        let read: Vec<Bytes> = storage
+            .get_content("parallel.mp4")
+            .await
+            .unwrap()
+            .try_collect()
+            .await
+            .unwrap();
+        assert_eq!(read.concat(), content);
+        let properties = storage.stat("parallel.mp4").await.unwrap();
+        assert_eq!(properties.checksums.crc64, Checksums::of(&content).crc64);
+        storage.delete("parallel.mp4").await.unwrap();
+
+        let small = Box::pin(futures::stream::iter([Ok(Bytes::from("abc"))]));
+        assert!(matches!(
+            storage
+                .set_content_if("video.mp4", small, WriteCondition::IfNoneMatch)
+                .await,
+            Err(StorageError::PreconditionFailed)
+        ));
+        storage.copy("video.mp4", "copy.mp4").await.unwrap();
+        let names: Vec<_> = storage
+            .list_all("")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|o| o.name)
+            .collect();
+        assert_eq!(names, ["copy.mp4", "video.mp4"]);
+        assert_eq!(storage.get_metadata("copy.mp4").await.unwrap(), "{}");
+        assert_eq!(storage.delete_prefix("").await.unwrap(), 2);
+        assert!(matches!(
+            storage.get_content("video.mp4").await,
+            Err(StorageError::NotFound)
+        ));
+    }
+}